@@ -2,7 +2,10 @@ use blueprint_sdk::build;
 use blueprint_sdk::tangle::blueprint;
 use std::path::Path;
 use std::process;
-use tangle_mcp_blueprint::{create_workspace, destroy_workspace};
+use tangle_mcp_blueprint::{
+    clone_workspace, create_workspace, destroy_workspace, list_snapshots, restore_snapshot,
+    restore_workspace, snapshot_workspace, undelete_workspace, workspace_status,
+};
 // use tangle_mcp_blueprint::say_hello;
 
 fn main() {
@@ -28,7 +31,17 @@ fn main() {
         name: "tangle-mcp-blueprint",
         master_manager_revision: "Latest",
         manager: { Evm = "HelloBlueprint" },
-        jobs: [create_workspace, destroy_workspace],
+        jobs: [
+            create_workspace,
+            destroy_workspace,
+            workspace_status,
+            restore_workspace,
+            undelete_workspace,
+            snapshot_workspace,
+            list_snapshots,
+            restore_snapshot,
+            clone_workspace
+        ],
     };
 
     match blueprint {
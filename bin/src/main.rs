@@ -13,7 +13,11 @@ use blueprint_sdk::tangle::layers::TangleLayer;
 use blueprint_sdk::tangle::producer::TangleProducer;
 use tangle_mcp_blueprint::MyContext;
 use tangle_mcp_blueprint::{
-    CREATE_WORKSPACE_JOB_ID, DESTROY_WORKSPACE_JOB_ID, create_workspace, destroy_workspace,
+    CLONE_WORKSPACE_JOB_ID, CREATE_WORKSPACE_JOB_ID, DESTROY_WORKSPACE_JOB_ID,
+    LIST_SNAPSHOTS_JOB_ID, RESTORE_SNAPSHOT_JOB_ID, RESTORE_WORKSPACE_JOB_ID,
+    SNAPSHOT_WORKSPACE_JOB_ID, UNDELETE_WORKSPACE_JOB_ID, WORKSPACE_STATUS_JOB_ID, clone_workspace,
+    create_workspace, destroy_workspace, list_snapshots, restore_snapshot, restore_workspace,
+    snapshot_workspace, undelete_workspace, workspace_status,
 };
 use tower::filter::FilterLayer;
 use tracing::error;
@@ -34,7 +38,7 @@ async fn main() -> Result<(), blueprint_sdk::Error> {
     let tangle_consumer =
         TangleConsumer::new(tangle_client.rpc_client.clone(), sr25519_tangle_signer);
 
-    let context = MyContext::new(env.clone()).unwrap();
+    let context = MyContext::new(env.clone()).await.unwrap();
     let tangle_config = TangleConfig::default();
 
     let service_id = env.protocol_settings.tangle()?.service_id.unwrap();
@@ -52,6 +56,22 @@ async fn main() -> Result<(), blueprint_sdk::Error> {
                     DESTROY_WORKSPACE_JOB_ID,
                     destroy_workspace.layer(TangleLayer),
                 )
+                .route(WORKSPACE_STATUS_JOB_ID, workspace_status.layer(TangleLayer))
+                .route(
+                    RESTORE_WORKSPACE_JOB_ID,
+                    restore_workspace.layer(TangleLayer),
+                )
+                .route(
+                    UNDELETE_WORKSPACE_JOB_ID,
+                    undelete_workspace.layer(TangleLayer),
+                )
+                .route(
+                    SNAPSHOT_WORKSPACE_JOB_ID,
+                    snapshot_workspace.layer(TangleLayer),
+                )
+                .route(LIST_SNAPSHOTS_JOB_ID, list_snapshots.layer(TangleLayer))
+                .route(RESTORE_SNAPSHOT_JOB_ID, restore_snapshot.layer(TangleLayer))
+                .route(CLONE_WORKSPACE_JOB_ID, clone_workspace.layer(TangleLayer))
                 // Add the `FilterLayer` to filter out job calls that don't match the service ID
                 //
                 // This layer is global to the router, and is applied to every job call.
@@ -0,0 +1,231 @@
+//! Archives of workspace data kept after a destroy.
+//!
+//! When the operator sets `MCP_ARCHIVE_DIR`, `destroy_workspace` packs the workspace
+//! directory into `{archive_dir}/{service_id}-{timestamp}.tar.gz` before deleting it.
+//! Archives are kept for `MCP_ARCHIVE_RETENTION` seconds (a week by default), during which
+//! `restore_workspace` unpacks the latest one back into place for the next
+//! `create_workspace` to pick up.
+
+use crate::storage::move_into_place;
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+pub const DEFAULT_RETENTION: u64 = 7 * 24 * 60 * 60;
+
+// How often expired archives are looked for
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveConfig {
+    /// Directory archives are kept in (`MCP_ARCHIVE_DIR`).
+    pub dir: PathBuf,
+    /// Seconds an archive is kept after the destroy (`MCP_ARCHIVE_RETENTION`).
+    pub retention: u64,
+}
+
+// An archive of a destroyed workspace
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Archive {
+    pub path: PathBuf,
+    pub service_id: u64,
+    /// Seconds since the Unix epoch.
+    pub archived_at: u64,
+}
+
+impl Archive {
+    // `{service_id}-{archived_at}.tar.gz`
+    fn from_path(path: PathBuf) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.strip_suffix(".tar.gz")?;
+        let (service_id, archived_at) = name.split_once('-')?;
+        Some(Self {
+            service_id: service_id.parse().ok()?,
+            archived_at: archived_at.parse().ok()?,
+            path,
+        })
+    }
+}
+
+fn now() -> Result<u64, BoxError> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())
+}
+
+impl ArchiveConfig {
+    /// Pack `workspace_dir` into a new archive of `service_id`.
+    pub async fn archive(
+        &self,
+        service_id: u64,
+        workspace_dir: &Path,
+    ) -> Result<Archive, BoxError> {
+        let archived_at = now()?;
+        let path = self
+            .dir
+            .join(format!("{}-{}.tar.gz", service_id, archived_at));
+        let workspace_dir = workspace_dir.to_path_buf();
+        let archive = Archive {
+            path: path.clone(),
+            service_id,
+            archived_at,
+        };
+
+        pack(&workspace_dir, &path).await?;
+
+        tracing::info!(
+            "Archived workspace {} to {}",
+            service_id,
+            archive.path.display()
+        );
+        Ok(archive)
+    }
+
+    /// The most recent archive of `service_id` that is still retained.
+    pub fn latest(&self, service_id: u64) -> Result<Option<Archive>, BoxError> {
+        let now = now()?;
+        Ok(self
+            .archives()?
+            .into_iter()
+            .filter(|archive| {
+                archive.service_id == service_id && archive.archived_at + self.retention > now
+            })
+            .max_by_key(|archive| archive.archived_at))
+    }
+
+    /// Delete the archives past their retention, returning how many were deleted.
+    pub fn purge_expired(&self) -> Result<usize, BoxError> {
+        let now = now()?;
+        let mut purged = 0;
+        for archive in self.archives()? {
+            if archive.archived_at + self.retention <= now {
+                std::fs::remove_file(&archive.path)?;
+                tracing::info!("Purged expired archive {}", archive.path.display());
+                purged += 1;
+            }
+        }
+        Ok(purged)
+    }
+
+    /// Purge expired archives in the background until the runtime shuts down.
+    pub fn spawn_purger(&self) {
+        let config = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(PURGE_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = config.purge_expired() {
+                    tracing::warn!("Failed to purge expired archives: {}", e);
+                }
+            }
+        });
+    }
+
+    fn archives(&self) -> Result<Vec<Archive>, BoxError> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut archives = Vec::new();
+        for entry in entries {
+            if let Some(archive) = Archive::from_path(entry?.path()) {
+                archives.push(archive);
+            }
+        }
+        Ok(archives)
+    }
+}
+
+/// Unpack `archive` into `workspace_dir`, which must not exist yet or be empty.
+pub async fn restore(archive: &Archive, workspace_dir: &Path) -> Result<(), BoxError> {
+    unpack(&archive.path, workspace_dir).await
+}
+
+/// Pack `dir` into a gzipped tarball at `path`, returning its size in bytes.
+pub async fn pack(dir: &Path, path: &Path) -> Result<u64, BoxError> {
+    let dir = dir.to_path_buf();
+    let path = path.to_path_buf();
+
+    tokio::task::spawn_blocking(move || -> Result<u64, BoxError> {
+        std::fs::create_dir_all(path.parent().ok_or("Invalid archive path")?)?;
+        // Write then rename, so a crash never leaves a truncated archive behind
+        let tmp = path.with_extension("tmp");
+        let mut builder =
+            tar::Builder::new(GzEncoder::new(File::create(&tmp)?, Compression::default()));
+        // Keep links as they are, following them could pack files outside the workspace
+        builder.follow_symlinks(false);
+        builder.append_dir_all(".", &dir)?;
+        let file = builder.into_inner()?.finish()?;
+        file.sync_all()?;
+        let size = file.metadata()?.len();
+        std::fs::rename(&tmp, &path)?;
+        Ok(size)
+    })
+    .await?
+}
+
+/// Unpack the gzipped tarball at `path` into `workspace_dir`, which must not exist yet or be
+/// empty.
+pub async fn unpack(path: &Path, workspace_dir: &Path) -> Result<(), BoxError> {
+    let archive = path.to_path_buf();
+    let workspace_dir = workspace_dir.to_path_buf();
+
+    tokio::task::spawn_blocking(move || -> Result<(), BoxError> {
+        // Unpack next to the destination, so a failed restore leaves nothing half-written
+        let tmp = workspace_dir.with_extension("restore");
+        if tmp.exists() {
+            std::fs::remove_dir_all(&tmp)?;
+        }
+        std::fs::create_dir_all(&tmp)?;
+
+        let mut unpacker = tar::Archive::new(GzDecoder::new(File::open(&archive)?));
+        unpacker.set_preserve_ownerships(true);
+        // Rejects entries escaping the destination through `..` or links
+        if let Err(e) = unpacker.unpack(&tmp) {
+            let _ = std::fs::remove_dir_all(&tmp);
+            return Err(e.into());
+        }
+        move_into_place(&tmp, &workspace_dir)?;
+        Ok(())
+    })
+    .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueprint_sdk::testing::tempfile::TempDir;
+
+    #[tokio::test]
+    async fn it_restores_the_latest_retained_archive() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path();
+        let workspace = root.join("workspaces/5");
+        std::fs::create_dir_all(workspace.join("notes")).unwrap();
+        std::fs::write(workspace.join("notes/todo.md"), "keep me").unwrap();
+
+        let config = ArchiveConfig {
+            dir: root.join("archives"),
+            retention: DEFAULT_RETENTION,
+        };
+        // An expired archive of the same workspace and one of another workspace
+        std::fs::create_dir_all(&config.dir).unwrap();
+        std::fs::write(config.dir.join("5-1.tar.gz"), "").unwrap();
+        std::fs::write(config.dir.join("6-1.tar.gz"), "").unwrap();
+
+        let archive = config.archive(5, &workspace).await.unwrap();
+        assert_eq!(config.latest(5).unwrap(), Some(archive.clone()));
+        assert_eq!(config.latest(6).unwrap(), None);
+        assert_eq!(config.purge_expired().unwrap(), 2);
+
+        std::fs::remove_dir_all(&workspace).unwrap();
+        restore(&archive, &workspace).await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(workspace.join("notes/todo.md")).unwrap(),
+            "keep me"
+        );
+    }
+}
@@ -0,0 +1,60 @@
+//! Requests signed by the owner of a workspace.
+//!
+//! The owner signs a message naming the request with their Sr25519 key. `x-mcp-timestamp`
+//! holds the Unix time in seconds the message includes and `authorization` is
+//! `Sr25519 {signature}`, base64 encoded. Timestamps more than five minutes off are rejected.
+
+use crate::error::WorkspaceError;
+use axum::http::HeaderMap;
+use axum::http::header::AUTHORIZATION;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use blueprint_sdk::crypto::sp_core::{SpSr25519, SpSr25519Public, SpSr25519Signature};
+use blueprint_sdk::crypto::{BytesEncoding, KeyType};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const TIMESTAMP_HEADER: &str = "x-mcp-timestamp";
+
+// How far the timestamp of a request may be from the operator's clock, in seconds
+const MAX_CLOCK_SKEW: u64 = 5 * 60;
+
+/// Message opening an MCP session on a workspace the operator serves, e.g. a stdio one.
+pub fn session_message(service_id: u64, timestamp: u64) -> Vec<u8> {
+    format!("mcp-session\n{}\n{}", service_id, timestamp).into_bytes()
+}
+
+/// Check `headers` carry a recent signature by `owner` of `message`, built from the
+/// request's timestamp.
+pub fn verify_owner(
+    owner: &SpSr25519Public,
+    headers: &HeaderMap,
+    message: impl FnOnce(u64) -> Vec<u8>,
+) -> Result<(), WorkspaceError> {
+    let unauthorized = |message: &str| WorkspaceError::Unauthorized(message.to_string());
+    let timestamp: u64 = headers
+        .get(TIMESTAMP_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| unauthorized("Missing or invalid timestamp"))?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| WorkspaceError::Internal(e.to_string()))?
+        .as_secs();
+    if now.abs_diff(timestamp) > MAX_CLOCK_SKEW {
+        return Err(unauthorized(
+            "Request timestamp is too far from the operator's clock",
+        ));
+    }
+
+    let signature = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Sr25519 "))
+        .and_then(|value| BASE64.decode(value.trim()).ok())
+        .and_then(|bytes| SpSr25519Signature::from_bytes(&bytes).ok())
+        .ok_or_else(|| unauthorized("Missing or invalid signature"))?;
+    if !SpSr25519::verify(owner, &message(timestamp), &signature) {
+        return Err(unauthorized("Request isn't signed by the workspace owner"));
+    }
+    Ok(())
+}
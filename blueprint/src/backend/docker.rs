@@ -0,0 +1,478 @@
+use super::{
+    AttachedStdio, BackendError, ContainerInfo, ContainerSpec, ContainerState, ExecOutput,
+    HealthState, Runtimes, StdioOutput, WorkspaceBackend, WorkspaceNetwork,
+};
+use crate::egress::DOCKER_USER_CHAIN;
+use crate::labels::{self, Labels};
+use crate::network::{
+    BridgeNaming, create_workspace_network, remove_workspace_network, workspace_bridge_interface,
+};
+use docktopus::bollard::Docker;
+use docktopus::bollard::container::{
+    AttachContainerOptions, AttachContainerResults, Config, CreateContainerOptions,
+    InspectContainerOptions, ListContainersOptions, LogOutput, LogsOptions, RemoveContainerOptions,
+};
+use docktopus::bollard::errors::Error as DockerError;
+use docktopus::bollard::exec::{CreateExecOptions, StartExecResults};
+use docktopus::bollard::image::{CommitContainerOptions, RemoveImageOptions};
+use docktopus::bollard::models::{EndpointSettings, HealthConfig, HostConfig, PortBinding};
+use docktopus::bollard::secret::HealthStatusEnum;
+use docktopus::bollard::volume::{CreateVolumeOptions, RemoveVolumeOptions};
+use futures_util::StreamExt;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+pub struct DockerBackend {
+    pub(super) docker: Arc<Docker>,
+    /// Name or ID of the operator's own container, attached to every workspace network.
+    pub(super) operator_container: Option<String>,
+}
+
+impl DockerBackend {
+    pub fn new(docker: Docker, operator_container: Option<String>) -> Self {
+        Self {
+            docker: Arc::new(docker),
+            operator_container,
+        }
+    }
+}
+
+fn is_not_found(e: &DockerError) -> bool {
+    matches!(
+        e,
+        DockerError::DockerResponseServerError {
+            status_code: 404,
+            ..
+        }
+    )
+}
+
+fn container_state(status: Option<&str>) -> ContainerState {
+    match status {
+        Some("created") => ContainerState::Created,
+        Some("running") => ContainerState::Running,
+        Some("paused") => ContainerState::Paused,
+        Some("restarting") => ContainerState::Restarting,
+        Some("removing") => ContainerState::Removing,
+        Some("exited") => ContainerState::Exited,
+        Some("dead") => ContainerState::Dead,
+        _ => ContainerState::Unknown,
+    }
+}
+
+// Addresses of a container by network, skipping networks it has none on
+fn network_addresses(networks: HashMap<String, EndpointSettings>) -> HashMap<String, IpAddr> {
+    networks
+        .into_iter()
+        .filter_map(|(name, endpoint)| Some((name, endpoint.ip_address?.parse().ok()?)))
+        .collect()
+}
+
+#[async_trait::async_trait]
+impl WorkspaceBackend for DockerBackend {
+    async fn create_network(
+        &self,
+        service_id: u64,
+        labels: &Labels,
+    ) -> Result<WorkspaceNetwork, BackendError> {
+        let mut options = HashMap::new();
+        // Only one workspace container lives on the network, there is nothing to talk to
+        options.insert(
+            "com.docker.network.bridge.enable_icc".to_string(),
+            "false".to_string(),
+        );
+
+        let name = create_workspace_network(
+            &self.docker,
+            service_id,
+            self.operator_container.as_deref(),
+            options,
+            labels,
+        )
+        .await?;
+        let interface =
+            workspace_bridge_interface(&self.docker, service_id, BridgeNaming::NetworkId).await?;
+        Ok(WorkspaceNetwork {
+            name,
+            interface: Some(interface),
+            forward_chain: DOCKER_USER_CHAIN,
+        })
+    }
+
+    async fn remove_network(&self, service_id: u64) -> Result<(), BackendError> {
+        remove_workspace_network(&self.docker, service_id, self.operator_container.as_deref()).await
+    }
+
+    async fn create(&self, spec: ContainerSpec) -> Result<String, BackendError> {
+        let mut host_config = HostConfig {
+            network_mode: spec.network,
+            runtime: spec.runtime,
+            cap_drop: Some(spec.cap_drop),
+            readonly_rootfs: Some(spec.readonly_rootfs),
+            tmpfs: Some(spec.tmpfs),
+            pids_limit: spec.pids_limit,
+            security_opt: Some(spec.security_opt),
+            ..Default::default()
+        };
+        if !spec.cap_add.is_empty() {
+            host_config.cap_add = Some(spec.cap_add);
+        }
+        if !spec.binds.is_empty() {
+            host_config.binds = Some(spec.binds);
+        }
+        if !spec.ports.is_empty() {
+            let mut port_bindings = HashMap::new();
+            for port in spec.ports {
+                port_bindings.insert(
+                    format!("{}/tcp", port.container_port),
+                    Some(vec![PortBinding {
+                        host_ip: Some(port.host_ip.to_string()),
+                        host_port: Some(port.host_port.to_string()),
+                    }]),
+                );
+            }
+            host_config.port_bindings = Some(port_bindings);
+        }
+
+        let mut config = Config {
+            image: Some(spec.image),
+            user: spec.user,
+            env: Some(spec.env),
+            labels: Some(spec.labels),
+            host_config: Some(host_config),
+            ..Default::default()
+        };
+        if !spec.cmd.is_empty() {
+            config.cmd = Some(spec.cmd);
+        }
+        if spec.interactive {
+            // Keep stdin open so the operator can attach to it
+            config.open_stdin = Some(true);
+            config.stdin_once = Some(false);
+            config.attach_stdin = Some(true);
+            config.attach_stdout = Some(true);
+            config.attach_stderr = Some(true);
+            // The image's health check probes the HTTP server, which isn't running
+            config.healthcheck = Some(HealthConfig {
+                test: Some(vec!["NONE".to_string()]),
+                ..Default::default()
+            });
+        }
+
+        let response = self
+            .docker
+            .create_container(
+                Some(CreateContainerOptions {
+                    name: spec.name,
+                    platform: None,
+                }),
+                config,
+            )
+            .await?;
+        for warning in response.warnings {
+            tracing::warn!("{}", warning);
+        }
+        Ok(response.id)
+    }
+
+    async fn attach(&self, id: &str) -> Result<AttachedStdio, BackendError> {
+        let AttachContainerResults { output, input } = self
+            .docker
+            .attach_container(
+                id,
+                Some(AttachContainerOptions::<String> {
+                    stdin: Some(true),
+                    stdout: Some(true),
+                    stderr: Some(true),
+                    stream: Some(true),
+                    ..Default::default()
+                }),
+            )
+            .await?;
+
+        let output = output.filter_map(|chunk| async move {
+            match chunk {
+                Ok(LogOutput::StdOut { message }) | Ok(LogOutput::Console { message }) => {
+                    Some(Ok(StdioOutput::Stdout(message)))
+                }
+                Ok(LogOutput::StdErr { message }) => Some(Ok(StdioOutput::Stderr(message))),
+                Ok(LogOutput::StdIn { .. }) => None,
+                Err(e) => Some(Err(e.into())),
+            }
+        });
+
+        Ok(AttachedStdio {
+            output: Box::pin(output),
+            input,
+        })
+    }
+
+    async fn start(&self, id: &str) -> Result<(), BackendError> {
+        self.docker.start_container::<String>(id, None).await?;
+        Ok(())
+    }
+
+    async fn stop(&self, id: &str) -> Result<(), BackendError> {
+        match self.docker.stop_container(id, None).await {
+            Ok(())
+            // Already stopped
+            | Err(DockerError::DockerResponseServerError {
+                status_code: 304, ..
+            }) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn pause(&self, id: &str) -> Result<(), BackendError> {
+        self.docker.pause_container(id).await?;
+        Ok(())
+    }
+
+    async fn unpause(&self, id: &str) -> Result<(), BackendError> {
+        self.docker.unpause_container(id).await?;
+        Ok(())
+    }
+
+    async fn inspect(&self, id_or_name: &str) -> Result<Option<ContainerInfo>, BackendError> {
+        let info = match self
+            .docker
+            .inspect_container(id_or_name, Some(InspectContainerOptions { size: false }))
+            .await
+        {
+            Ok(info) => info,
+            Err(e) if is_not_found(&e) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let state = info.state.unwrap_or_default();
+        let status = state.status.map(|status| status.to_string());
+        let health =
+            state
+                .health
+                .and_then(|health| health.status)
+                .and_then(|status| match status {
+                    HealthStatusEnum::STARTING => Some(HealthState::Starting),
+                    HealthStatusEnum::HEALTHY => Some(HealthState::Healthy),
+                    HealthStatusEnum::UNHEALTHY => Some(HealthState::Unhealthy),
+                    _ => None,
+                });
+
+        Ok(Some(ContainerInfo {
+            labels: info
+                .config
+                .and_then(|config| config.labels)
+                .unwrap_or_default(),
+            addresses: network_addresses(
+                info.network_settings
+                    .and_then(|settings| settings.networks)
+                    .unwrap_or_default(),
+            ),
+            id: info.id.unwrap_or_default(),
+            name: info
+                .name
+                .unwrap_or_default()
+                .trim_start_matches('/')
+                .to_string(),
+            state: container_state(status.as_deref()),
+            health,
+        }))
+    }
+
+    async fn list(&self, labels: &Labels) -> Result<Vec<ContainerInfo>, BackendError> {
+        let containers = self
+            .docker
+            .list_containers(Some(ListContainersOptions::<String> {
+                all: true,
+                filters: HashMap::from([("label".to_string(), labels::filters(labels))]),
+                ..Default::default()
+            }))
+            .await?;
+
+        Ok(containers
+            .into_iter()
+            .map(|container| ContainerInfo {
+                id: container.id.unwrap_or_default(),
+                // Container names from Docker API include a leading slash
+                name: container
+                    .names
+                    .and_then(|names| names.into_iter().next())
+                    .unwrap_or_default()
+                    .trim_start_matches('/')
+                    .to_string(),
+                state: container_state(container.state.as_deref()),
+                // Not part of the summary, `inspect` reports it
+                health: None,
+                labels: container.labels.unwrap_or_default(),
+                addresses: network_addresses(
+                    container
+                        .network_settings
+                        .and_then(|settings| settings.networks)
+                        .unwrap_or_default(),
+                ),
+            })
+            .collect())
+    }
+
+    async fn remove(&self, id: &str) -> Result<(), BackendError> {
+        match self
+            .docker
+            .remove_container(
+                id,
+                Some(RemoveContainerOptions {
+                    force: true,
+                    // Anonymous volumes declared by the image would outlive the container
+                    v: true,
+                    ..Default::default()
+                }),
+            )
+            .await
+        {
+            Ok(()) => Ok(()),
+            Err(e) if is_not_found(&e) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn logs(&self, id: &str, tail: usize) -> Result<String, BackendError> {
+        let mut logs = self.docker.logs(
+            id,
+            Some(LogsOptions::<String> {
+                stdout: true,
+                stderr: true,
+                tail: tail.to_string(),
+                ..Default::default()
+            }),
+        );
+
+        let mut output = String::new();
+        while let Some(chunk) = logs.next().await {
+            output.push_str(&chunk?.to_string());
+        }
+        Ok(output)
+    }
+
+    async fn exec(&self, id: &str, cmd: Vec<String>) -> Result<ExecOutput, BackendError> {
+        let exec = self
+            .docker
+            .create_exec(
+                id,
+                CreateExecOptions {
+                    cmd: Some(cmd),
+                    attach_stdout: Some(true),
+                    attach_stderr: Some(true),
+                    ..Default::default()
+                },
+            )
+            .await?;
+
+        let mut output = String::new();
+        if let StartExecResults::Attached {
+            output: mut stream, ..
+        } = self.docker.start_exec(&exec.id, None).await?
+        {
+            while let Some(chunk) = stream.next().await {
+                output.push_str(&chunk?.to_string());
+            }
+        }
+
+        let exit_code = self
+            .docker
+            .inspect_exec(&exec.id)
+            .await?
+            .exit_code
+            .unwrap_or_default();
+        Ok(ExecOutput { exit_code, output })
+    }
+
+    async fn commit(&self, id: &str, repository: &str, tag: &str) -> Result<String, BackendError> {
+        self.docker
+            .commit_container(
+                CommitContainerOptions {
+                    container: id,
+                    repo: repository,
+                    tag,
+                    // Don't capture files mid-write
+                    pause: true,
+                    ..Default::default()
+                },
+                Config::<String>::default(),
+            )
+            .await?;
+        Ok(format!("{}:{}", repository, tag))
+    }
+
+    async fn remove_image(&self, reference: &str) -> Result<(), BackendError> {
+        match self
+            .docker
+            .remove_image(
+                reference,
+                Some(RemoveImageOptions {
+                    force: true,
+                    ..Default::default()
+                }),
+                None,
+            )
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) if is_not_found(&e) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn create_volume(
+        &self,
+        name: &str,
+        driver: Option<&str>,
+        options: &HashMap<String, String>,
+        labels: &Labels,
+    ) -> Result<PathBuf, BackendError> {
+        let volume = self
+            .docker
+            .create_volume(CreateVolumeOptions {
+                name: name.to_string(),
+                // Empty for the engine's default driver
+                driver: driver.unwrap_or_default().to_string(),
+                driver_opts: options.clone(),
+                labels: labels.clone(),
+            })
+            .await?;
+        Ok(PathBuf::from(volume.mountpoint))
+    }
+
+    async fn volume_path(&self, name: &str) -> Result<Option<PathBuf>, BackendError> {
+        match self.docker.inspect_volume(name).await {
+            Ok(volume) => Ok(Some(PathBuf::from(volume.mountpoint))),
+            Err(e) if is_not_found(&e) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn remove_volume(&self, name: &str) -> Result<(), BackendError> {
+        match self
+            .docker
+            .remove_volume(name, Some(RemoveVolumeOptions { force: true }))
+            .await
+        {
+            Ok(()) => Ok(()),
+            Err(e) if is_not_found(&e) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn runtimes(&self) -> Result<Runtimes, BackendError> {
+        let info = self.docker.info().await?;
+        let mut available = info
+            .runtimes
+            .unwrap_or_default()
+            .into_keys()
+            .collect::<Vec<_>>();
+        available.sort();
+        Ok(Runtimes {
+            available,
+            default: info.default_runtime.unwrap_or_else(|| "runc".to_string()),
+        })
+    }
+}
@@ -0,0 +1,347 @@
+use super::{
+    AttachedStdio, BackendError, ContainerInfo, ContainerSpec, ContainerState, ExecOutput,
+    HealthState, Runtimes, WorkspaceBackend, WorkspaceNetwork,
+};
+use crate::MyContext;
+use crate::config::OperatorConfig;
+use crate::egress::{EgressMode, EgressPolicy, FORWARD_CHAIN};
+use crate::labels::Labels;
+use crate::network::workspace_network_name;
+use crate::state::WorkspaceStore;
+use blueprint_sdk::testing::tempfile::TempDir;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// In-memory backend for tests, recording the container lifecycle calls it gets
+#[derive(Default)]
+pub struct FakeBackend {
+    state: Mutex<FakeState>,
+    /// Where volume data is kept, volumes aren't supported when unset.
+    volume_root: Option<PathBuf>,
+}
+
+// A volume created on the fake backend
+#[derive(Debug, Clone)]
+pub struct FakeVolume {
+    pub path: PathBuf,
+    pub options: HashMap<String, String>,
+    pub labels: Labels,
+}
+
+#[derive(Default)]
+struct FakeState {
+    /// Containers by ID, IDs are the container names.
+    containers: BTreeMap<String, ContainerInfo>,
+    events: Vec<String>,
+    /// Committed images.
+    images: Vec<String>,
+    volumes: BTreeMap<String, FakeVolume>,
+    /// Labels of the networks by name.
+    networks: BTreeMap<String, Labels>,
+    creating: usize,
+    max_creating: usize,
+}
+
+impl FakeBackend {
+    /// Backend keeping the data of its volumes under `root`.
+    pub fn with_volumes(root: PathBuf) -> Self {
+        Self {
+            volume_root: Some(root),
+            ..Default::default()
+        }
+    }
+
+    pub fn volume(&self, name: &str) -> Option<FakeVolume> {
+        self.state.lock().unwrap().volumes.get(name).cloned()
+    }
+
+    /// Labels of a network, `None` unless it exists.
+    pub fn network(&self, name: &str) -> Option<Labels> {
+        self.state.lock().unwrap().networks.get(name).cloned()
+    }
+
+    pub fn containers(&self) -> Vec<String> {
+        self.state
+            .lock()
+            .unwrap()
+            .containers
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    pub fn images(&self) -> Vec<String> {
+        self.state.lock().unwrap().images.clone()
+    }
+
+    pub fn events(&self) -> Vec<String> {
+        self.state.lock().unwrap().events.clone()
+    }
+
+    /// Most containers ever being created at the same time.
+    pub fn max_concurrent_creates(&self) -> usize {
+        self.state.lock().unwrap().max_creating
+    }
+
+    /// Add a running container the blueprint didn't create, or created before labeling its
+    /// containers when `labels` is empty.
+    pub fn add_foreign(&self, name: &str, labels: Labels) {
+        self.state.lock().unwrap().containers.insert(
+            name.to_string(),
+            ContainerInfo {
+                id: name.to_string(),
+                name: name.to_string(),
+                state: ContainerState::Running,
+                health: None,
+                labels,
+                addresses: HashMap::new(),
+            },
+        );
+    }
+
+    fn set_state(&self, id: &str, state: ContainerState, event: &str) {
+        let mut fake = self.state.lock().unwrap();
+        if let Some(container) = fake.containers.get_mut(id) {
+            container.state = state;
+        }
+        fake.events.push(format!("{} {}", event, id));
+    }
+}
+
+/// Operator running workspaces on a fake backend, keeping its data in a temporary
+/// directory removed when the environment is dropped.
+pub struct TestEnv {
+    pub backend: Arc<FakeBackend>,
+    pub ctx: MyContext,
+    dir: TempDir,
+}
+
+impl TestEnv {
+    /// Operator keeping workspace data in its data directory.
+    pub fn new() -> Self {
+        let dir = TempDir::new().unwrap();
+        let backend = Arc::new(FakeBackend::default());
+        let mut ctx = context(backend.clone());
+        ctx.env.data_dir = Some(dir.path().to_path_buf());
+        Self { backend, ctx, dir }
+    }
+
+    /// The operator's data directory.
+    pub fn root(&self) -> &Path {
+        self.dir.path()
+    }
+}
+
+/// Context running workspaces on `backend`, without TLS or a data directory.
+pub fn context(backend: Arc<FakeBackend>) -> MyContext {
+    let config = OperatorConfig {
+        // The fake backend's networks aren't visible to the host firewall
+        egress: EgressPolicy {
+            mode: EgressMode::Open,
+            allow: Vec::new(),
+        },
+        ..Default::default()
+    };
+    MyContext {
+        env: Default::default(),
+        backend,
+        config: Arc::new(config),
+        tls: None,
+        bridges: Default::default(),
+        workspaces: Arc::new(WorkspaceStore::open(None).unwrap()),
+        locks: Default::default(),
+        backups: None,
+    }
+}
+
+#[async_trait::async_trait]
+impl WorkspaceBackend for FakeBackend {
+    async fn create_network(
+        &self,
+        service_id: u64,
+        labels: &Labels,
+    ) -> Result<WorkspaceNetwork, BackendError> {
+        let name = workspace_network_name(service_id);
+        let mut fake = self.state.lock().unwrap();
+        fake.networks
+            .entry(name.clone())
+            .or_insert_with(|| labels.clone());
+        Ok(WorkspaceNetwork {
+            name,
+            interface: None,
+            forward_chain: FORWARD_CHAIN,
+        })
+    }
+
+    async fn remove_network(&self, service_id: u64) -> Result<(), BackendError> {
+        let name = workspace_network_name(service_id);
+        self.state.lock().unwrap().networks.remove(&name);
+        Ok(())
+    }
+
+    async fn create(&self, spec: ContainerSpec) -> Result<String, BackendError> {
+        {
+            let mut fake = self.state.lock().unwrap();
+            fake.creating += 1;
+            fake.max_creating = fake.max_creating.max(fake.creating);
+        }
+        // Give other jobs a chance to run in between, like a real engine would
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let mut fake = self.state.lock().unwrap();
+        fake.creating -= 1;
+        if fake.containers.contains_key(&spec.name) {
+            return Err(format!("Container name {} is already in use", spec.name).into());
+        }
+        fake.containers.insert(
+            spec.name.clone(),
+            ContainerInfo {
+                id: spec.name.clone(),
+                name: spec.name.clone(),
+                state: ContainerState::Created,
+                health: Some(HealthState::Healthy),
+                labels: spec.labels,
+                addresses: HashMap::new(),
+            },
+        );
+        fake.events.push(format!("create {}", spec.name));
+        Ok(spec.name)
+    }
+
+    async fn attach(&self, _id: &str) -> Result<AttachedStdio, BackendError> {
+        Err("Attaching isn't supported by the fake backend".into())
+    }
+
+    async fn start(&self, id: &str) -> Result<(), BackendError> {
+        self.set_state(id, ContainerState::Running, "start");
+        Ok(())
+    }
+
+    async fn stop(&self, id: &str) -> Result<(), BackendError> {
+        self.set_state(id, ContainerState::Exited, "stop");
+        Ok(())
+    }
+
+    async fn pause(&self, id: &str) -> Result<(), BackendError> {
+        self.set_state(id, ContainerState::Paused, "pause");
+        Ok(())
+    }
+
+    async fn unpause(&self, id: &str) -> Result<(), BackendError> {
+        self.set_state(id, ContainerState::Running, "unpause");
+        Ok(())
+    }
+
+    async fn inspect(&self, id_or_name: &str) -> Result<Option<ContainerInfo>, BackendError> {
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .containers
+            .get(id_or_name)
+            .cloned())
+    }
+
+    async fn list(&self, labels: &Labels) -> Result<Vec<ContainerInfo>, BackendError> {
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .containers
+            .values()
+            .filter(|container| {
+                labels
+                    .iter()
+                    .all(|(key, value)| container.labels.get(key) == Some(value))
+            })
+            .cloned()
+            .collect())
+    }
+
+    async fn remove(&self, id: &str) -> Result<(), BackendError> {
+        let mut fake = self.state.lock().unwrap();
+        if fake.containers.remove(id).is_some() {
+            fake.events.push(format!("remove {}", id));
+        }
+        Ok(())
+    }
+
+    async fn logs(&self, _id: &str, _tail: usize) -> Result<String, BackendError> {
+        Ok(String::new())
+    }
+
+    async fn exec(&self, _id: &str, _cmd: Vec<String>) -> Result<ExecOutput, BackendError> {
+        Ok(ExecOutput {
+            exit_code: 0,
+            output: String::new(),
+        })
+    }
+
+    async fn commit(&self, id: &str, repository: &str, tag: &str) -> Result<String, BackendError> {
+        let mut fake = self.state.lock().unwrap();
+        let reference = format!("{}:{}", repository, tag);
+        fake.events.push(format!("commit {} {}", id, reference));
+        fake.images.push(reference.clone());
+        Ok(reference)
+    }
+
+    async fn remove_image(&self, reference: &str) -> Result<(), BackendError> {
+        let mut fake = self.state.lock().unwrap();
+        fake.images.retain(|image| image != reference);
+        Ok(())
+    }
+
+    async fn create_volume(
+        &self,
+        name: &str,
+        _driver: Option<&str>,
+        options: &HashMap<String, String>,
+        labels: &Labels,
+    ) -> Result<PathBuf, BackendError> {
+        let root = self
+            .volume_root
+            .as_ref()
+            .ok_or("Volumes aren't supported by this fake backend")?;
+        let mut fake = self.state.lock().unwrap();
+        if let Some(volume) = fake.volumes.get(name) {
+            return Ok(volume.path.clone());
+        }
+        // Laid out like Docker's local driver
+        let path = root.join(name).join("_data");
+        std::fs::create_dir_all(&path)?;
+        fake.volumes.insert(
+            name.to_string(),
+            FakeVolume {
+                path: path.clone(),
+                options: options.clone(),
+                labels: labels.clone(),
+            },
+        );
+        fake.events.push(format!("create volume {}", name));
+        Ok(path)
+    }
+
+    async fn volume_path(&self, name: &str) -> Result<Option<PathBuf>, BackendError> {
+        Ok(self.volume(name).map(|volume| volume.path))
+    }
+
+    async fn remove_volume(&self, name: &str) -> Result<(), BackendError> {
+        let mut fake = self.state.lock().unwrap();
+        if let Some(volume) = fake.volumes.remove(name) {
+            if let Some(dir) = volume.path.parent() {
+                std::fs::remove_dir_all(dir)?;
+            }
+            fake.events.push(format!("remove volume {}", name));
+        }
+        Ok(())
+    }
+
+    async fn runtimes(&self) -> Result<Runtimes, BackendError> {
+        Ok(Runtimes {
+            available: vec!["runc".to_string()],
+            default: "runc".to_string(),
+        })
+    }
+}
@@ -0,0 +1,485 @@
+//! Container backends workspaces run on.
+//!
+//! Jobs only talk to a [`WorkspaceBackend`] (through `MyContext::backend`), never to a
+//! container engine directly. [`DockerBackend`] and [`PodmanBackend`] are the
+//! implementations used in production, picked by [`connect`].
+
+mod docker;
+#[cfg(test)]
+pub(crate) mod fake;
+mod podman;
+
+pub use docker::DockerBackend;
+pub use podman::PodmanBackend;
+
+use crate::config::{BackendKind, DockerConnection, OperatorConfig};
+use crate::labels::{self, Labels};
+use bytes::Bytes;
+use docktopus::bollard::{API_DEFAULT_VERSION, Docker};
+use futures_util::Stream;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWrite;
+
+pub type BackendError = Box<dyn std::error::Error + Send + Sync>;
+
+// Everything needed to create a workspace container
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct ContainerSpec {
+    pub name: String,
+    pub image: String,
+    /// Command overriding the image's, empty to keep it.
+    pub cmd: Vec<String>,
+    pub env: Vec<String>,
+    pub user: Option<String>,
+    /// Network the container is attached to instead of the default one.
+    pub network: Option<String>,
+    pub ports: Vec<PortMapping>,
+    /// Bind mounts and named volumes in `source:container[:options]` form.
+    pub binds: Vec<String>,
+    /// Keep stdin open for [`WorkspaceBackend::attach`] and skip the image's health check.
+    pub interactive: bool,
+    /// OCI runtime, the engine's default when unset.
+    pub runtime: Option<String>,
+    pub cap_add: Vec<String>,
+    pub cap_drop: Vec<String>,
+    pub readonly_rootfs: bool,
+    /// tmpfs mounts, path to mount options.
+    pub tmpfs: HashMap<String, String>,
+    pub pids_limit: Option<i64>,
+    /// Engine security options (`no-new-privileges:true`, `seccomp=...`, `apparmor=...`).
+    pub security_opt: Vec<String>,
+    pub labels: Labels,
+}
+
+// A TCP port of the container published on the host
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortMapping {
+    pub container_port: u16,
+    pub host_ip: IpAddr,
+    pub host_port: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContainerState {
+    Created,
+    Running,
+    Paused,
+    Restarting,
+    Removing,
+    Exited,
+    Dead,
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HealthState {
+    Starting,
+    Healthy,
+    Unhealthy,
+}
+
+impl fmt::Display for ContainerState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ContainerState::Created => "created",
+            ContainerState::Running => "running",
+            ContainerState::Paused => "paused",
+            ContainerState::Restarting => "restarting",
+            ContainerState::Removing => "removing",
+            ContainerState::Exited => "exited",
+            ContainerState::Dead => "dead",
+            ContainerState::Unknown => "unknown",
+        })
+    }
+}
+
+impl fmt::Display for HealthState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            HealthState::Starting => "starting",
+            HealthState::Healthy => "healthy",
+            HealthState::Unhealthy => "unhealthy",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContainerInfo {
+    pub id: String,
+    /// Name without the leading slash Docker reports.
+    pub name: String,
+    pub state: ContainerState,
+    /// `None` when the container has no health check.
+    pub health: Option<HealthState>,
+    pub labels: Labels,
+    /// Address of the container on each network it is attached to, by network name.
+    pub addresses: HashMap<String, IpAddr>,
+}
+
+// Network a workspace container is attached to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkspaceNetwork {
+    pub name: String,
+    /// Host interface backing the network, which egress rules apply to. `None` when the
+    /// network isn't visible to the host firewall.
+    pub interface: Option<String>,
+    /// Chain of the host firewall the network's forwarded traffic can be filtered in.
+    pub forward_chain: &'static str,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecOutput {
+    pub exit_code: i64,
+    /// Combined stdout and stderr.
+    pub output: String,
+}
+
+pub enum StdioOutput {
+    Stdout(Bytes),
+    Stderr(Bytes),
+}
+
+pub type StdioStream = Pin<Box<dyn Stream<Item = Result<StdioOutput, BackendError>> + Send>>;
+
+// Streams of an attached interactive container
+pub struct AttachedStdio {
+    pub output: StdioStream,
+    pub input: Pin<Box<dyn AsyncWrite + Send>>,
+}
+
+// OCI runtimes known to the engine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Runtimes {
+    pub available: Vec<String>,
+    pub default: String,
+}
+
+#[async_trait::async_trait]
+pub trait WorkspaceBackend: Send + Sync {
+    /// Create the dedicated network of a workspace, reusing a leftover one.
+    async fn create_network(
+        &self,
+        service_id: u64,
+        labels: &Labels,
+    ) -> Result<WorkspaceNetwork, BackendError>;
+
+    /// Remove the network of a workspace, succeeding if it is already gone.
+    async fn remove_network(&self, service_id: u64) -> Result<(), BackendError>;
+
+    /// Create a container, returning its ID.
+    async fn create(&self, spec: ContainerSpec) -> Result<String, BackendError>;
+
+    /// Attach to the stdin and stdout of an interactive container, before starting it so no
+    /// output is lost.
+    async fn attach(&self, id: &str) -> Result<AttachedStdio, BackendError>;
+
+    async fn start(&self, id: &str) -> Result<(), BackendError>;
+
+    async fn stop(&self, id: &str) -> Result<(), BackendError>;
+
+    /// Freeze the processes of a running container, keeping its stdio and network attached.
+    async fn pause(&self, id: &str) -> Result<(), BackendError>;
+
+    async fn unpause(&self, id: &str) -> Result<(), BackendError>;
+
+    /// Look up a container by ID or name, `None` when it doesn't exist.
+    async fn inspect(&self, id_or_name: &str) -> Result<Option<ContainerInfo>, BackendError>;
+
+    /// Containers in any state carrying all of `labels`.
+    async fn list(&self, labels: &Labels) -> Result<Vec<ContainerInfo>, BackendError>;
+
+    /// Force-remove a container and its anonymous volumes, succeeding if it is already gone.
+    async fn remove(&self, id: &str) -> Result<(), BackendError>;
+
+    /// The last `tail` lines of the container's output.
+    async fn logs(&self, id: &str, tail: usize) -> Result<String, BackendError>;
+
+    /// Run a command inside a running container and wait for it to finish.
+    async fn exec(&self, id: &str, cmd: Vec<String>) -> Result<ExecOutput, BackendError>;
+
+    async fn runtimes(&self) -> Result<Runtimes, BackendError>;
+
+    /// Save the container's filesystem as the image `repository:tag`, returning the reference
+    /// to run it by.
+    async fn commit(&self, id: &str, repository: &str, tag: &str) -> Result<String, BackendError>;
+
+    /// Remove an image, succeeding if it is already gone.
+    async fn remove_image(&self, reference: &str) -> Result<(), BackendError>;
+
+    /// Create a named volume, reusing an existing one, returning the host path of its data.
+    async fn create_volume(
+        &self,
+        name: &str,
+        driver: Option<&str>,
+        options: &HashMap<String, String>,
+        labels: &Labels,
+    ) -> Result<PathBuf, BackendError>;
+
+    /// Host path of the data of a volume, `None` when it doesn't exist.
+    async fn volume_path(&self, name: &str) -> Result<Option<PathBuf>, BackendError>;
+
+    /// Remove a volume, succeeding if it is already gone.
+    async fn remove_volume(&self, name: &str) -> Result<(), BackendError>;
+
+    /// The container of a workspace, found by its ownership labels, or by its name when it was
+    /// created before workspaces were labeled.
+    async fn find_workspace(
+        &self,
+        blueprint_id: u64,
+        service_id: u64,
+    ) -> Result<Option<ContainerInfo>, BackendError> {
+        let owned = self
+            .list(&labels::ownership(blueprint_id, service_id))
+            .await?;
+        if let Some(container) = owned.first() {
+            // Listing doesn't report health
+            return self.inspect(&container.id).await;
+        }
+
+        // Adopt an unlabeled container going by the workspace's name, leaving those of other
+        // blueprints alone
+        match self.inspect(&workspace_container_name(service_id)).await? {
+            Some(container) if !container.labels.contains_key(labels::BLUEPRINT_ID) => {
+                Ok(Some(container))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+pub fn workspace_container_name(service_id: u64) -> String {
+    format!("mcp-svc-{}", service_id)
+}
+
+// Oldest engine API the backends are written against (Docker 20.10, Podman 3)
+const MIN_API_VERSION: (u32, u32) = (1, 40);
+
+/// Connect to the configured container engine, detecting Podman when set to `auto`.
+///
+/// Fails when the engine can't be reached or is too old.
+pub async fn connect(config: &OperatorConfig) -> Result<Arc<dyn WorkspaceBackend>, BackendError> {
+    let connection = &config.docker;
+    let endpoint = endpoint(connection, config.backend, docker_configured, podman_socket)?;
+    let docker = match endpoint {
+        Endpoint::Configured => connect_configured(connection)?,
+        Endpoint::Podman(socket) => {
+            Docker::connect_with_socket(&socket, connection.timeout, API_DEFAULT_VERSION)?
+        }
+        Endpoint::LocalDefaults => local_defaults(connection)?,
+    };
+
+    let version = docker.version().await.map_err(|e| {
+        format!(
+            "Can't reach the container engine at {}: {}",
+            connection.host.as_deref().unwrap_or("its default socket"),
+            e
+        )
+    })?;
+    let api_version = version.api_version.clone().unwrap_or_default();
+    check_api_version(&api_version)?;
+    // Talk to older engines in their own API version
+    let docker = docker.negotiate_version().await?;
+
+    let components = version.components.unwrap_or_default();
+    let is_podman = is_podman(
+        config.backend,
+        components.iter().map(|component| component.name.as_str()),
+    );
+    tracing::info!(
+        "Connected to {} {} (API {})",
+        if is_podman { "Podman" } else { "Docker" },
+        version.version.unwrap_or_default(),
+        api_version
+    );
+
+    let operator_container = config.operator_container.clone();
+    if is_podman {
+        Ok(Arc::new(
+            PodmanBackend::new(docker, operator_container).await?,
+        ))
+    } else {
+        Ok(Arc::new(DockerBackend::new(docker, operator_container)))
+    }
+}
+
+// Where to reach the container engine
+#[derive(Debug, PartialEq, Eq)]
+enum Endpoint {
+    /// The operator's `MCP_DOCKER_HOST`.
+    Configured,
+    /// A Podman API socket.
+    Podman(String),
+    /// Docker's defaults (`DOCKER_HOST` or its default socket).
+    LocalDefaults,
+}
+
+// Pick the engine endpoint, an explicit host always wins. Probing the host is left to
+// `docker_configured` and `podman_socket` so it stays out of the choice itself.
+fn endpoint(
+    connection: &DockerConnection,
+    backend: BackendKind,
+    docker_configured: impl FnOnce() -> bool,
+    podman_socket: impl FnOnce() -> Option<String>,
+) -> Result<Endpoint, BackendError> {
+    Ok(match (&connection.host, backend) {
+        (Some(_), _) => Endpoint::Configured,
+        (None, BackendKind::Podman) => {
+            Endpoint::Podman(podman_socket().ok_or("No Podman socket found, set MCP_DOCKER_HOST")?)
+        }
+        (None, BackendKind::Auto) if !docker_configured() => match podman_socket() {
+            Some(socket) => Endpoint::Podman(socket),
+            None => Endpoint::LocalDefaults,
+        },
+        (None, BackendKind::Auto | BackendKind::Docker) => Endpoint::LocalDefaults,
+    })
+}
+
+fn check_api_version(api_version: &str) -> Result<(), BackendError> {
+    match parse_api_version(api_version) {
+        Some(api) if api >= MIN_API_VERSION => Ok(()),
+        _ => Err(format!(
+            "Container engine API version {} is too old, at least {}.{} is required",
+            api_version, MIN_API_VERSION.0, MIN_API_VERSION.1
+        )
+        .into()),
+    }
+}
+
+// `podman-docker` serves Podman on the Docker socket, so `auto` goes by the components the
+// engine reports
+fn is_podman<'a>(backend: BackendKind, mut components: impl Iterator<Item = &'a str>) -> bool {
+    match backend {
+        BackendKind::Docker => false,
+        BackendKind::Podman => true,
+        BackendKind::Auto => components.any(|name| name.contains("Podman")),
+    }
+}
+
+// Connect to an explicitly configured socket or TCP endpoint
+fn connect_configured(connection: &DockerConnection) -> Result<Docker, BackendError> {
+    let host = connection.host.as_deref().unwrap_or_default();
+    let timeout = connection.timeout;
+
+    if let Some(tls) = &connection.tls {
+        // The client certificate resolver needs a process-wide crypto provider
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        return Ok(Docker::connect_with_ssl(
+            host,
+            &tls.key_path,
+            &tls.cert_path,
+            &tls.ca_path,
+            timeout,
+            API_DEFAULT_VERSION,
+        )?);
+    }
+
+    if host.starts_with("tcp://") || host.starts_with("http://") {
+        Ok(Docker::connect_with_http(
+            host,
+            timeout,
+            API_DEFAULT_VERSION,
+        )?)
+    } else {
+        Ok(Docker::connect_with_socket(
+            host,
+            timeout,
+            API_DEFAULT_VERSION,
+        )?)
+    }
+}
+
+fn local_defaults(connection: &DockerConnection) -> Result<Docker, BackendError> {
+    Ok(
+        Docker::connect_with_local_defaults()?
+            .with_timeout(Duration::from_secs(connection.timeout)),
+    )
+}
+
+// `1.43` -> `(1, 43)`
+fn parse_api_version(version: &str) -> Option<(u32, u32)> {
+    let (major, minor) = version.split_once('.')?;
+    Some((major.parse().ok()?, minor.parse().ok()?))
+}
+
+// Whether a Docker daemon is explicitly configured or listening on its default socket
+fn docker_configured() -> bool {
+    std::env::var_os("DOCKER_HOST").is_some() || PathBuf::from("/var/run/docker.sock").exists()
+}
+
+// Podman API socket, `CONTAINER_HOST` first, then the rootless and rootful defaults
+fn podman_socket() -> Option<String> {
+    if let Ok(host) = std::env::var("CONTAINER_HOST")
+        && host.starts_with("unix://")
+    {
+        return Some(host);
+    }
+
+    let mut candidates = Vec::new();
+    if let Some(runtime_dir) = std::env::var_os("XDG_RUNTIME_DIR") {
+        candidates.push(PathBuf::from(runtime_dir).join("podman/podman.sock"));
+    }
+    candidates.push(PathBuf::from("/run/podman/podman.sock"));
+
+    candidates
+        .into_iter()
+        .find(|path| path.exists())
+        .map(|path| format!("unix://{}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_prefers_an_explicit_host_over_detection() {
+        let connection = DockerConnection {
+            host: Some("tcp://10.0.0.2:2376".to_string()),
+            ..Default::default()
+        };
+        for backend in [BackendKind::Auto, BackendKind::Docker, BackendKind::Podman] {
+            let endpoint = endpoint(&connection, backend, || false, || None).unwrap();
+            assert_eq!(endpoint, Endpoint::Configured);
+        }
+    }
+
+    #[test]
+    fn it_detects_the_engine_without_a_host() {
+        let connection = DockerConnection::default();
+        let socket = || Some("unix:///run/podman/podman.sock".to_string());
+        let podman = Endpoint::Podman(socket().unwrap());
+
+        let auto = |docker: bool, socket: Option<String>| {
+            endpoint(&connection, BackendKind::Auto, || docker, || socket).unwrap()
+        };
+        assert_eq!(auto(false, socket()), podman);
+        assert_eq!(auto(true, socket()), Endpoint::LocalDefaults);
+        assert_eq!(auto(false, None), Endpoint::LocalDefaults);
+
+        let docker = endpoint(&connection, BackendKind::Docker, || false, socket).unwrap();
+        assert_eq!(docker, Endpoint::LocalDefaults);
+        let explicit = endpoint(&connection, BackendKind::Podman, || true, socket).unwrap();
+        assert_eq!(explicit, podman);
+        assert!(endpoint(&connection, BackendKind::Podman, || true, || None).is_err());
+
+        let components = ["Engine", "Podman Engine"];
+        assert!(is_podman(BackendKind::Auto, components.into_iter()));
+        assert!(!is_podman(BackendKind::Auto, ["Engine"].into_iter()));
+        assert!(!is_podman(BackendKind::Docker, components.into_iter()));
+    }
+
+    #[test]
+    fn it_requires_the_minimum_api_version() {
+        assert!(check_api_version("1.40").is_ok());
+        assert!(check_api_version("1.47").is_ok());
+        assert!(check_api_version("2.0").is_ok());
+        assert!(check_api_version("1.39").is_err());
+        assert!(check_api_version("").is_err());
+        assert!(check_api_version("latest").is_err());
+    }
+}
@@ -0,0 +1,230 @@
+use super::{
+    AttachedStdio, BackendError, ContainerInfo, ContainerSpec, ContainerState, DockerBackend,
+    ExecOutput, HealthState, Runtimes, WorkspaceBackend, WorkspaceNetwork,
+};
+use crate::egress::FORWARD_CHAIN;
+use crate::labels::Labels;
+use crate::network::{BridgeNaming, create_workspace_network, workspace_bridge_interface};
+use docktopus::bollard::Docker;
+use docktopus::bollard::container::InspectContainerOptions;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+// Podman through its Docker-compatible API, papering over where it behaves differently
+pub struct PodmanBackend {
+    inner: DockerBackend,
+    /// Rootless Podman runs its networks in a user namespace the host firewall can't see.
+    rootless: bool,
+}
+
+impl PodmanBackend {
+    pub async fn new(
+        docker: Docker,
+        operator_container: Option<String>,
+    ) -> Result<Self, BackendError> {
+        let info = docker.info().await?;
+        let rootless = info
+            .security_options
+            .unwrap_or_default()
+            .iter()
+            .any(|option| option.contains("rootless"));
+        if rootless {
+            tracing::info!("Using rootless Podman");
+        }
+
+        Ok(Self {
+            inner: DockerBackend::new(docker, operator_container),
+            rootless,
+        })
+    }
+
+    // Command of the image's health check, `None` when there is none
+    async fn health_test(&self, id: &str) -> Result<Option<Vec<String>>, BackendError> {
+        let info = self
+            .inner
+            .docker
+            .inspect_container(id, Some(InspectContainerOptions { size: false }))
+            .await?;
+        let test = info
+            .config
+            .and_then(|config| config.healthcheck)
+            .and_then(|healthcheck| healthcheck.test)
+            .unwrap_or_default();
+
+        Ok(match test.split_first() {
+            Some((kind, command)) if kind == "CMD" && !command.is_empty() => Some(command.to_vec()),
+            Some((kind, command)) if kind == "CMD-SHELL" && !command.is_empty() => Some(vec![
+                "/bin/sh".to_string(),
+                "-c".to_string(),
+                command.join(" "),
+            ]),
+            _ => None,
+        })
+    }
+}
+
+/// Podman resolves short image names against registries, local builds live under
+/// `localhost/`.
+fn qualify_image(image: &str) -> String {
+    if image.contains('/') {
+        image.to_string()
+    } else {
+        format!("localhost/{}", image)
+    }
+}
+
+#[async_trait::async_trait]
+impl WorkspaceBackend for PodmanBackend {
+    async fn create_network(
+        &self,
+        service_id: u64,
+        labels: &Labels,
+    ) -> Result<WorkspaceNetwork, BackendError> {
+        // Netavark rejects Docker's bridge driver options
+        let name = create_workspace_network(
+            &self.inner.docker,
+            service_id,
+            self.inner.operator_container.as_deref(),
+            HashMap::new(),
+            labels,
+        )
+        .await?;
+        // Netavark names bridges `podman1`, `podman2`... and reports them on inspect
+        let interface = if self.rootless {
+            None
+        } else {
+            let naming = BridgeNaming::Reported;
+            Some(workspace_bridge_interface(&self.inner.docker, service_id, naming).await?)
+        };
+        // Podman has no `DOCKER-USER` chain, its own rules jump from `FORWARD`
+        Ok(WorkspaceNetwork {
+            name,
+            interface,
+            forward_chain: FORWARD_CHAIN,
+        })
+    }
+
+    async fn remove_network(&self, service_id: u64) -> Result<(), BackendError> {
+        self.inner.remove_network(service_id).await
+    }
+
+    async fn create(&self, mut spec: ContainerSpec) -> Result<String, BackendError> {
+        spec.image = qualify_image(&spec.image);
+        self.inner.create(spec).await
+    }
+
+    async fn attach(&self, id: &str) -> Result<AttachedStdio, BackendError> {
+        self.inner.attach(id).await
+    }
+
+    async fn start(&self, id: &str) -> Result<(), BackendError> {
+        self.inner.start(id).await
+    }
+
+    async fn stop(&self, id: &str) -> Result<(), BackendError> {
+        self.inner.stop(id).await
+    }
+
+    async fn pause(&self, id: &str) -> Result<(), BackendError> {
+        self.inner.pause(id).await
+    }
+
+    async fn unpause(&self, id: &str) -> Result<(), BackendError> {
+        self.inner.unpause(id).await
+    }
+
+    async fn inspect(&self, id_or_name: &str) -> Result<Option<ContainerInfo>, BackendError> {
+        let Some(mut info) = self.inner.inspect(id_or_name).await? else {
+            return Ok(None);
+        };
+
+        // Podman only runs health checks from systemd timers, which rootless and containerized
+        // setups often lack, leaving the status stuck at `starting`. Probe it directly instead.
+        if info.state == ContainerState::Running
+            && info.health != Some(HealthState::Healthy)
+            && let Some(test) = self.health_test(&info.id).await?
+        {
+            let healthy = matches!(
+                self.inner.exec(&info.id, test).await,
+                Ok(ExecOutput { exit_code: 0, .. })
+            );
+            info.health = Some(if healthy {
+                HealthState::Healthy
+            } else {
+                info.health.unwrap_or(HealthState::Starting)
+            });
+        }
+
+        Ok(Some(info))
+    }
+
+    async fn list(&self, labels: &Labels) -> Result<Vec<ContainerInfo>, BackendError> {
+        self.inner.list(labels).await
+    }
+
+    async fn remove(&self, id: &str) -> Result<(), BackendError> {
+        self.inner.remove(id).await
+    }
+
+    async fn logs(&self, id: &str, tail: usize) -> Result<String, BackendError> {
+        self.inner.logs(id, tail).await
+    }
+
+    async fn exec(&self, id: &str, cmd: Vec<String>) -> Result<ExecOutput, BackendError> {
+        self.inner.exec(id, cmd).await
+    }
+
+    async fn commit(&self, id: &str, repository: &str, tag: &str) -> Result<String, BackendError> {
+        self.inner.commit(id, &qualify_image(repository), tag).await
+    }
+
+    async fn remove_image(&self, reference: &str) -> Result<(), BackendError> {
+        self.inner.remove_image(&qualify_image(reference)).await
+    }
+
+    async fn create_volume(
+        &self,
+        name: &str,
+        driver: Option<&str>,
+        options: &HashMap<String, String>,
+        labels: &Labels,
+    ) -> Result<PathBuf, BackendError> {
+        self.inner
+            .create_volume(name, driver, options, labels)
+            .await
+    }
+
+    async fn volume_path(&self, name: &str) -> Result<Option<PathBuf>, BackendError> {
+        self.inner.volume_path(name).await
+    }
+
+    async fn remove_volume(&self, name: &str) -> Result<(), BackendError> {
+        self.inner.remove_volume(name).await
+    }
+
+    async fn runtimes(&self) -> Result<Runtimes, BackendError> {
+        self.inner.runtimes().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_qualifies_local_image_names() {
+        assert_eq!(
+            qualify_image("tangle-mcp:0.1.0"),
+            "localhost/tangle-mcp:0.1.0"
+        );
+        assert_eq!(
+            qualify_image("localhost/tangle-mcp:0.1.0"),
+            "localhost/tangle-mcp:0.1.0"
+        );
+        assert_eq!(
+            qualify_image("ghcr.io/tangle-network/mcp:latest"),
+            "ghcr.io/tangle-network/mcp:latest"
+        );
+        assert_eq!(qualify_image("registry:5000/mcp"), "registry:5000/mcp");
+    }
+}
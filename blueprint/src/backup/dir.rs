@@ -0,0 +1,72 @@
+use super::{BackupTarget, BoxError};
+use std::path::{Path, PathBuf};
+
+// Backups kept as files under a directory, e.g. a mounted network share
+pub struct DirTarget {
+    root: PathBuf,
+}
+
+impl DirTarget {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+}
+
+#[async_trait::async_trait]
+impl BackupTarget for DirTarget {
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<(), BoxError> {
+        let path = self.root.join(key);
+        tokio::fs::create_dir_all(path.parent().ok_or("Invalid backup key")?).await?;
+        // Write then rename, so a crash never leaves a truncated object behind
+        let tmp = path.with_extension("tmp");
+        tokio::fs::write(&tmp, data).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, BoxError> {
+        match tokio::fs::read(self.root.join(key)).await {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, BoxError> {
+        let dir = self.root.join(prefix);
+        let prefix = prefix.to_string();
+        tokio::task::spawn_blocking(move || {
+            let mut keys = Vec::new();
+            list_files(&dir, &prefix, &mut keys)?;
+            Ok(keys)
+        })
+        .await?
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), BoxError> {
+        match tokio::fs::remove_file(self.root.join(key)).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+// Keys of the files under `dir`, which holds the objects under `prefix`
+fn list_files(dir: &Path, prefix: &str, keys: &mut Vec<String>) -> Result<(), BoxError> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if entry.file_type()?.is_dir() {
+            list_files(&entry.path(), &format!("{}{}/", prefix, name), keys)?;
+        } else if !name.ends_with(".tmp") {
+            keys.push(format!("{}{}", prefix, name));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,898 @@
+//! Encrypted, incremental backups of workspace data to an object store.
+//!
+//! With `MCP_BACKUP_TARGET` set, every running workspace's directory is backed up each
+//! `MCP_BACKUP_INTERVAL` seconds. Files are split into chunks stored once under
+//! `chunks/{service_id}/{id}`, so a backup only uploads the chunks that changed since the
+//! previous ones. Each backup is a manifest under `manifests/{service_id}/{name}` listing
+//! the files and their chunks, named after when it was taken plus a random suffix so
+//! backups taken within the same second don't overwrite each other.
+//!
+//! Chunks and manifests are encrypted with AES-256-GCM under a key derived from the
+//! operator's `MCP_BACKUP_SECRET` and the owner's public key, so the store never sees
+//! plaintext and a workspace recreated for another owner can't read them. Chunk IDs are
+//! keyed hashes for the same reason.
+//!
+//! Backups older than `MCP_BACKUP_RETENTION` seconds, or beyond the `MCP_BACKUP_KEEP`
+//! newest ones, are pruned, except for the latest backup of a running workspace. When
+//! `create_workspace` finds no local data, it restores the latest backup first. Destroying a
+//! workspace deletes all of its backups, so they never bring back data deliberately
+//! destroyed.
+
+mod dir;
+mod s3;
+
+pub use dir::DirTarget;
+pub use s3::{S3Config, S3Target};
+
+use crate::MyContext;
+use crate::config::required_var;
+use crate::storage;
+use aes_gcm::aead::Aead;
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use blueprint_sdk::std::rand::{self, RngCore};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::HashSet;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+pub const DEFAULT_INTERVAL: u64 = 60 * 60;
+pub const DEFAULT_RETENTION: u64 = 7 * 24 * 60 * 60;
+pub const DEFAULT_KEEP: usize = 24;
+
+// Files are stored in chunks of this many bytes, the unit of deduplication
+const CHUNK_SIZE: usize = 4 * 1024 * 1024;
+const NONCE_LEN: usize = 12;
+
+/// Object store backups are kept in.
+#[async_trait::async_trait]
+pub trait BackupTarget: Send + Sync {
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<(), BoxError>;
+
+    /// The object at `key`, `None` if there is none.
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, BoxError>;
+
+    /// Keys of the objects under `prefix`, which ends with a `/`.
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, BoxError>;
+
+    /// Delete the object at `key`, succeeding if it is already gone.
+    async fn delete(&self, key: &str) -> Result<(), BoxError>;
+}
+
+// A secret kept out of debug output
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret(pub String);
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret(..)")
+    }
+}
+
+// Where backups are kept
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TargetConfig {
+    /// A local or mounted directory.
+    Dir(PathBuf),
+    /// An S3-compatible bucket.
+    S3(S3Config),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupConfig {
+    /// `s3://bucket/prefix` or a directory (`MCP_BACKUP_TARGET`).
+    pub target: TargetConfig,
+    /// Operator secret the encryption keys are derived from (`MCP_BACKUP_SECRET`).
+    pub secret: Secret,
+    /// Seconds between backups (`MCP_BACKUP_INTERVAL`).
+    pub interval: u64,
+    /// Seconds a backup is kept (`MCP_BACKUP_RETENTION`).
+    pub retention: u64,
+    /// Most backups kept per workspace (`MCP_BACKUP_KEEP`).
+    pub keep: usize,
+}
+
+impl BackupConfig {
+    /// Backup settings, `None` when `MCP_BACKUP_TARGET` isn't set.
+    pub fn from_env() -> Result<Option<Self>, BoxError> {
+        let Ok(target) = std::env::var("MCP_BACKUP_TARGET") else {
+            return Ok(None);
+        };
+        let target = match target.strip_prefix("s3://") {
+            Some(location) => {
+                let (bucket, prefix) = location.split_once('/').unwrap_or((location, ""));
+                let region = std::env::var("MCP_BACKUP_S3_REGION")
+                    .unwrap_or_else(|_| "us-east-1".to_string());
+                // MinIO and other S3-compatible stores need their own endpoint
+                let endpoint = std::env::var("MCP_BACKUP_S3_ENDPOINT")
+                    .unwrap_or_else(|_| format!("https://s3.{}.amazonaws.com", region));
+                TargetConfig::S3(S3Config {
+                    endpoint,
+                    region,
+                    bucket: bucket.to_string(),
+                    prefix: prefix.trim_matches('/').to_string(),
+                    access_key: required_var("AWS_ACCESS_KEY_ID")?,
+                    secret_key: Secret(required_var("AWS_SECRET_ACCESS_KEY")?),
+                })
+            }
+            None => TargetConfig::Dir(target.into()),
+        };
+
+        Ok(Some(Self {
+            target,
+            secret: Secret(required_var("MCP_BACKUP_SECRET")?),
+            interval: number_var("MCP_BACKUP_INTERVAL", DEFAULT_INTERVAL)?,
+            retention: number_var("MCP_BACKUP_RETENTION", DEFAULT_RETENTION)?,
+            keep: number_var("MCP_BACKUP_KEEP", DEFAULT_KEEP)?,
+        }))
+    }
+}
+
+fn number_var<T: std::str::FromStr>(name: &str, default: T) -> Result<T, BoxError> {
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map_err(|_| format!("Invalid {}: {}", name, value).into()),
+        Err(_) => Ok(default),
+    }
+}
+
+// What a backup uploaded
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BackupStats {
+    /// Seconds since the Unix epoch.
+    pub created_at: u64,
+    /// Chunks that weren't stored yet.
+    pub uploaded: usize,
+    /// Bytes of those chunks before encryption.
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+enum EntryKind {
+    Dir,
+    File,
+    Symlink,
+}
+
+// A file of the workspace directory, as recorded in a manifest
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+struct Entry {
+    /// Relative to the workspace directory, `/` separated.
+    path: String,
+    kind: EntryKind,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    /// Where a symlink points to.
+    #[serde(default)]
+    target: String,
+    /// IDs of the chunks of a file, in order.
+    #[serde(default)]
+    chunks: Vec<String>,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+struct Manifest {
+    created_at: u64,
+    entries: Vec<Entry>,
+}
+
+// Keys of one workspace's backups
+struct Keys {
+    cipher: Aes256Gcm,
+    ids: Hmac<Sha256>,
+}
+
+impl Keys {
+    fn new(secret: &Secret, service_id: u64, owner: &str) -> Result<Self, BoxError> {
+        let hkdf = Hkdf::<Sha256>::new(Some(b"tangle-mcp-backup"), secret.0.as_bytes());
+        let mut okm = [0u8; 64];
+        hkdf.expand(format!("{}:{}", owner, service_id).as_bytes(), &mut okm)
+            .map_err(|_| "Failed to derive the backup keys")?;
+        Ok(Self {
+            cipher: Aes256Gcm::new_from_slice(&okm[..32])?,
+            ids: <Hmac<Sha256> as Mac>::new_from_slice(&okm[32..])?,
+        })
+    }
+
+    fn chunk_id(&self, chunk: &[u8]) -> String {
+        let mut mac = self.ids.clone();
+        mac.update(chunk);
+        hex(&mac.finalize().into_bytes())
+    }
+
+    // The nonce followed by the ciphertext
+    fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>, BoxError> {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::rngs::OsRng.fill_bytes(&mut nonce);
+        let ciphertext = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), data)
+            .map_err(|_| "Failed to encrypt backup data")?;
+        Ok([nonce.as_slice(), &ciphertext].concat())
+    }
+
+    fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, BoxError> {
+        if data.len() < NONCE_LEN {
+            return Err("Truncated backup data".into());
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        Ok(self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| "Backup data can't be decrypted with this owner's key")?)
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn now() -> Result<u64, BoxError> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())
+}
+
+fn chunk_key(service_id: u64, id: &str) -> String {
+    format!("chunks/{}/{}", service_id, id)
+}
+
+fn manifest_key(service_id: u64, name: &str) -> String {
+    format!("manifests/{}/{}", service_id, name)
+}
+
+// `{seconds}.{nanoseconds}-{random}`, sorting by when the backup was taken within a second
+fn manifest_name(taken: Duration) -> String {
+    let mut suffix = [0u8; 4];
+    rand::rngs::OsRng.fill_bytes(&mut suffix);
+    format!(
+        "{}.{:09}-{}",
+        taken.as_secs(),
+        taken.subsec_nanos(),
+        hex(&suffix)
+    )
+}
+
+/// A workspace directory as captured for a backup. Its files are copied into a staging
+/// directory next to it (reflinked where the filesystem supports it), so the backup can be
+/// uploaded without holding the workspace's lock while the workspace keeps writing and jobs
+/// replace or delete the data. The workspace keeps running during the capture, a file it
+/// writes meanwhile may be copied halfway through the write.
+pub struct Capture {
+    dir: PathBuf,
+    /// Entries of the workspace directory, with where regular files were staged.
+    entries: Vec<(Option<PathBuf>, Entry)>,
+}
+
+impl Capture {
+    pub async fn take(workspace_dir: &Path) -> Result<Self, BoxError> {
+        let root = workspace_dir.to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            let dir = root.with_extension("backup");
+            // Left by a backup the operator didn't get to finish
+            if dir.exists() {
+                std::fs::remove_dir_all(&dir)?;
+            }
+            std::fs::create_dir(&dir)?;
+            match stage(&root, &dir) {
+                Ok(entries) => Ok(Self { dir, entries }),
+                Err(e) => {
+                    let _ = std::fs::remove_dir_all(&dir);
+                    Err(e)
+                }
+            }
+        })
+        .await?
+    }
+
+    async fn remove(&self) {
+        if let Err(e) = tokio::fs::remove_dir_all(&self.dir).await
+            && e.kind() != std::io::ErrorKind::NotFound
+        {
+            tracing::warn!("Failed to remove {}: {}", self.dir.display(), e);
+        }
+    }
+}
+
+// Copy the regular files under `root` into `dir`, numbered in walk order
+fn stage(root: &Path, dir: &Path) -> Result<Vec<(Option<PathBuf>, Entry)>, BoxError> {
+    let mut entries = Vec::new();
+    for (i, (path, entry)) in walk(root)?.into_iter().enumerate() {
+        if entry.kind != EntryKind::File {
+            entries.push((None, entry));
+            continue;
+        }
+        let staged = dir.join(i.to_string());
+        match copy_file(&path, &staged) {
+            Ok(true) => entries.push((Some(staged), entry)),
+            // Swapped for something else since the walk
+            Ok(false) => {}
+            // Deleted since the walk
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(entries)
+}
+
+// Copy the file at `path` to `staged` unless it isn't a regular file anymore, without
+// following links or blocking on pipes. The kernel reflinks the copy where it can.
+fn copy_file(path: &Path, staged: &Path) -> std::io::Result<bool> {
+    let mut options = std::fs::OpenOptions::new();
+    options.read(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::custom_flags(
+        &mut options,
+        libc::O_NOFOLLOW | libc::O_NONBLOCK,
+    );
+    let mut file = match options.open(path) {
+        Ok(file) => file,
+        #[cfg(unix)]
+        Err(e) if e.raw_os_error() == Some(libc::ELOOP) => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    if !file.metadata()?.is_file() {
+        return Ok(false);
+    }
+    let mut copy = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(staged)?;
+    std::io::copy(&mut file, &mut copy)?;
+    Ok(true)
+}
+
+pub struct Backups {
+    target: Box<dyn BackupTarget>,
+    secret: Secret,
+    interval: u64,
+    retention: u64,
+    keep: usize,
+}
+
+impl Backups {
+    pub fn new(config: &BackupConfig) -> Result<Self, BoxError> {
+        let target: Box<dyn BackupTarget> = match &config.target {
+            TargetConfig::Dir(dir) => Box::new(DirTarget::new(dir.clone())),
+            TargetConfig::S3(s3) => Box::new(S3Target::new(s3.clone())?),
+        };
+        Ok(Self::with_target(target, config))
+    }
+
+    pub fn with_target(target: Box<dyn BackupTarget>, config: &BackupConfig) -> Self {
+        Self {
+            target,
+            secret: config.secret.clone(),
+            interval: config.interval,
+            retention: config.retention,
+            keep: config.keep,
+        }
+    }
+
+    /// Back up `workspace_dir`, uploading the chunks no earlier backup stored.
+    pub async fn backup(
+        &self,
+        service_id: u64,
+        owner: &str,
+        workspace_dir: &Path,
+    ) -> Result<BackupStats, BoxError> {
+        let capture = Capture::take(workspace_dir).await?;
+        self.upload(service_id, owner, capture).await
+    }
+
+    /// Back up a captured workspace directory, uploading the chunks no earlier backup stored.
+    pub async fn upload(
+        &self,
+        service_id: u64,
+        owner: &str,
+        capture: Capture,
+    ) -> Result<BackupStats, BoxError> {
+        let result = self.upload_entries(service_id, owner, &capture).await;
+        capture.remove().await;
+        result
+    }
+
+    async fn upload_entries(
+        &self,
+        service_id: u64,
+        owner: &str,
+        capture: &Capture,
+    ) -> Result<BackupStats, BoxError> {
+        let keys = Keys::new(&self.secret, service_id, owner)?;
+        let prefix = format!("chunks/{}/", service_id);
+        let mut stored: HashSet<String> = self
+            .target
+            .list(&prefix)
+            .await?
+            .into_iter()
+            .filter_map(|key| key.strip_prefix(&prefix).map(str::to_string))
+            .collect();
+
+        let mut stats = BackupStats::default();
+        let mut entries = Vec::with_capacity(capture.entries.len());
+        for (staged, entry) in &capture.entries {
+            let mut entry = entry.clone();
+            if let Some(staged) = staged {
+                let mut file = tokio::fs::File::open(staged).await?;
+                loop {
+                    let mut chunk = Vec::new();
+                    (&mut file)
+                        .take(CHUNK_SIZE as u64)
+                        .read_to_end(&mut chunk)
+                        .await?;
+                    if chunk.is_empty() {
+                        break;
+                    }
+                    let id = keys.chunk_id(&chunk);
+                    if stored.insert(id.clone()) {
+                        let data = keys.encrypt(&chunk)?;
+                        self.target.put(&chunk_key(service_id, &id), data).await?;
+                        stats.uploaded += 1;
+                        stats.bytes += chunk.len() as u64;
+                    }
+                    entry.chunks.push(id);
+                    if chunk.len() < CHUNK_SIZE {
+                        break;
+                    }
+                }
+            }
+            entries.push(entry);
+        }
+
+        let taken = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        stats.created_at = taken.as_secs();
+        let manifest = Manifest {
+            created_at: stats.created_at,
+            entries,
+        };
+        let data = keys.encrypt(&serde_json::to_vec(&manifest)?)?;
+        self.target
+            .put(&manifest_key(service_id, &manifest_name(taken)), data)
+            .await?;
+        Ok(stats)
+    }
+
+    /// Restore the latest backup into `workspace_dir`, which must not exist yet or be empty.
+    /// Returns when the backup was taken, `None` when there is no backup this owner can read.
+    pub async fn restore(
+        &self,
+        service_id: u64,
+        owner: &str,
+        workspace_dir: &Path,
+    ) -> Result<Option<u64>, BoxError> {
+        let Some((_, latest)) = self.manifests(service_id).await?.into_iter().max() else {
+            return Ok(None);
+        };
+        let keys = Keys::new(&self.secret, service_id, owner)?;
+        let Some(data) = self.target.get(&manifest_key(service_id, &latest)).await? else {
+            return Ok(None);
+        };
+        let manifest: Manifest = match keys.decrypt(&data) {
+            Ok(manifest) => serde_json::from_slice(&manifest)?,
+            Err(e) => {
+                tracing::warn!(
+                    "Not restoring the backup of workspace {}: {}",
+                    service_id,
+                    e
+                );
+                return Ok(None);
+            }
+        };
+
+        // Restore next to the destination, so a failed restore leaves nothing half-written
+        let tmp = workspace_dir.with_extension("restore");
+        if tmp.exists() {
+            tokio::fs::remove_dir_all(&tmp).await?;
+        }
+        tokio::fs::create_dir_all(&tmp).await?;
+        if let Err(e) = self
+            .restore_entries(&keys, service_id, &manifest, &tmp)
+            .await
+        {
+            let _ = tokio::fs::remove_dir_all(&tmp).await;
+            return Err(e);
+        }
+        let (from, to) = (tmp.clone(), workspace_dir.to_path_buf());
+        tokio::task::spawn_blocking(move || storage::move_into_place(&from, &to)).await??;
+        Ok(Some(manifest.created_at))
+    }
+
+    async fn restore_entries(
+        &self,
+        keys: &Keys,
+        service_id: u64,
+        manifest: &Manifest,
+        dir: &Path,
+    ) -> Result<(), BoxError> {
+        for entry in &manifest.entries {
+            let path = dir.join(relative_path(&entry.path)?);
+            match entry.kind {
+                EntryKind::Dir => tokio::fs::create_dir_all(&path).await?,
+                EntryKind::File => {
+                    let mut file = tokio::fs::File::create(&path).await?;
+                    for id in &entry.chunks {
+                        let data = self
+                            .target
+                            .get(&chunk_key(service_id, id))
+                            .await?
+                            .ok_or_else(|| format!("Backup chunk {} is missing", id))?;
+                        file.write_all(&keys.decrypt(&data)?).await?;
+                    }
+                    file.flush().await?;
+                }
+                EntryKind::Symlink => symlink(&entry.target, &path)?,
+            }
+            set_metadata(&path, entry)?;
+        }
+        Ok(())
+    }
+
+    /// Delete the backups past retention. `owner` is set for running workspaces, whose latest
+    /// backup is always kept and whose unreferenced chunks are deleted.
+    pub async fn prune(&self, service_id: u64, owner: Option<&str>) -> Result<usize, BoxError> {
+        let mut manifests = self.manifests(service_id).await?;
+        manifests.sort_unstable_by(|a, b| b.cmp(a));
+        let now = now()?;
+        let (kept, expired): (Vec<_>, Vec<_>) =
+            manifests
+                .into_iter()
+                .enumerate()
+                .partition(|(i, (created_at, _))| {
+                    (*i < self.keep && created_at + self.retention > now)
+                        || (*i == 0 && owner.is_some())
+                });
+        for (_, (_, name)) in &expired {
+            self.target.delete(&manifest_key(service_id, name)).await?;
+        }
+
+        let prefix = format!("chunks/{}/", service_id);
+        let referenced = match (kept.is_empty(), owner) {
+            (true, _) => HashSet::new(),
+            (false, Some(owner)) => {
+                let keys = Keys::new(&self.secret, service_id, owner)?;
+                let mut referenced = HashSet::new();
+                for (_, (_, name)) in &kept {
+                    let key = manifest_key(service_id, name);
+                    let Some(data) = self.target.get(&key).await? else {
+                        continue;
+                    };
+                    // Left by a previous owner, keep everything until it expires
+                    let Ok(manifest) = keys.decrypt(&data) else {
+                        return Ok(expired.len());
+                    };
+                    let manifest: Manifest = serde_json::from_slice(&manifest)?;
+                    for entry in manifest.entries {
+                        referenced.extend(
+                            entry
+                                .chunks
+                                .into_iter()
+                                .map(|id| chunk_key(service_id, &id)),
+                        );
+                    }
+                }
+                referenced
+            }
+            // Nothing to tell which chunks are still used without the owner
+            (false, None) => return Ok(expired.len()),
+        };
+        for key in self.target.list(&prefix).await? {
+            if !referenced.contains(&key) {
+                self.target.delete(&key).await?;
+            }
+        }
+        Ok(expired.len())
+    }
+
+    /// Delete every backup of a workspace, manifests first so none is left pointing at missing
+    /// chunks. Returns how many backups were deleted.
+    pub async fn remove(&self, service_id: u64) -> Result<usize, BoxError> {
+        let manifests = self.manifests(service_id).await?;
+        for (_, name) in &manifests {
+            self.target.delete(&manifest_key(service_id, name)).await?;
+        }
+        for key in self.target.list(&format!("chunks/{}/", service_id)).await? {
+            self.target.delete(&key).await?;
+        }
+        Ok(manifests.len())
+    }
+
+    /// Service IDs with backups in the store.
+    pub async fn service_ids(&self) -> Result<HashSet<u64>, BoxError> {
+        Ok(self
+            .target
+            .list("manifests/")
+            .await?
+            .iter()
+            .filter_map(|key| key.split('/').nth(1)?.parse().ok())
+            .collect())
+    }
+
+    // Creation times and names of the backups of `service_id`, sorting in the order taken
+    async fn manifests(&self, service_id: u64) -> Result<Vec<(u64, String)>, BoxError> {
+        let prefix = format!("manifests/{}/", service_id);
+        Ok(self
+            .target
+            .list(&prefix)
+            .await?
+            .iter()
+            .filter_map(|key| {
+                let name = key.strip_prefix(&prefix)?;
+                let created_at = name.split('.').next()?.parse().ok()?;
+                Some((created_at, name.to_string()))
+            })
+            .collect())
+    }
+}
+
+/// Back up the running workspaces and prune old backups in the background until the runtime
+/// shuts down.
+pub fn spawn_backups(ctx: MyContext) {
+    let Some(backups) = ctx.backups.clone() else {
+        return;
+    };
+    if !storage::is_persistent(&ctx) {
+        tracing::warn!("Workspaces keep no data on this operator, they won't be backed up");
+        return;
+    }
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(backups.interval));
+        loop {
+            interval.tick().await;
+            for record in ctx.workspaces.list() {
+                let service_id = record.service_id;
+                // Only hold the lock while capturing the data, not while uploading it
+                let capture = {
+                    let _guard = ctx.locks.lock(service_id).await;
+                    // Destroyed while waiting for the lock
+                    let live = ctx.workspaces.get(service_id);
+                    if live.is_none_or(|record| record.deleted_at.is_some()) {
+                        continue;
+                    }
+                    let workspace_dir = match storage::workspace_dir(&ctx, service_id).await {
+                        Ok(Some(workspace_dir)) => workspace_dir,
+                        Ok(None) => continue,
+                        Err(e) => {
+                            tracing::warn!(
+                                "Failed to find the data of workspace {}: {}",
+                                service_id,
+                                e
+                            );
+                            continue;
+                        }
+                    };
+                    match Capture::take(&workspace_dir).await {
+                        Ok(capture) => capture,
+                        Err(e) => {
+                            tracing::warn!(
+                                "Failed to capture the data of workspace {}: {}",
+                                service_id,
+                                e
+                            );
+                            continue;
+                        }
+                    }
+                };
+                back_up(
+                    &ctx,
+                    &backups,
+                    service_id,
+                    &record.owner_public_key,
+                    capture,
+                )
+                .await;
+            }
+
+            // Backups of workspaces that are gone expire too
+            let service_ids = match backups.service_ids().await {
+                Ok(service_ids) => service_ids,
+                Err(e) => {
+                    tracing::warn!("Failed to list workspace backups: {}", e);
+                    continue;
+                }
+            };
+            for service_id in service_ids {
+                let live = ctx.workspaces.get(service_id);
+                if live.is_some_and(|record| record.deleted_at.is_none()) {
+                    continue;
+                }
+                if let Err(e) = backups.prune(service_id, None).await {
+                    tracing::warn!("Failed to prune backups of workspace {}: {}", service_id, e);
+                }
+            }
+        }
+    });
+}
+
+async fn back_up(
+    ctx: &MyContext,
+    backups: &Arc<Backups>,
+    service_id: u64,
+    owner: &str,
+    capture: Capture,
+) {
+    match backups.upload(service_id, owner, capture).await {
+        Ok(stats) => tracing::info!(
+            "Backed up workspace {}, uploading {} chunks ({} bytes)",
+            service_id,
+            stats.uploaded,
+            stats.bytes
+        ),
+        Err(e) => tracing::warn!("Failed to back up workspace {}: {}", service_id, e),
+    }
+
+    // Destroyed while uploading, after it deleted its backups
+    {
+        let _guard = ctx.locks.lock(service_id).await;
+        if ctx.workspaces.get(service_id).is_none() {
+            if let Err(e) = backups.remove(service_id).await {
+                tracing::warn!(
+                    "Failed to delete backups of destroyed workspace {}: {}",
+                    service_id,
+                    e
+                );
+            }
+            return;
+        }
+    }
+    if let Err(e) = backups.prune(service_id, Some(owner)).await {
+        tracing::warn!("Failed to prune backups of workspace {}: {}", service_id, e);
+    }
+}
+
+// Everything under `root`, parents before their contents, without following links
+fn walk(root: &Path) -> Result<Vec<(PathBuf, Entry)>, BoxError> {
+    let mut entries = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let mut children: Vec<_> = std::fs::read_dir(&dir)?
+            .map(|child| child.map(|child| child.path()))
+            .collect::<Result<_, _>>()?;
+        children.sort();
+        for path in children {
+            let metadata = path.symlink_metadata()?;
+            let kind = if metadata.is_dir() {
+                pending.push(path.clone());
+                EntryKind::Dir
+            } else if metadata.is_file() {
+                EntryKind::File
+            } else if metadata.is_symlink() {
+                EntryKind::Symlink
+            } else {
+                // Sockets and pipes are meaningless outside the running workspace
+                continue;
+            };
+            let relative = path.strip_prefix(root)?;
+            let (mode, uid, gid) = ownership(&metadata);
+            let target = match kind {
+                EntryKind::Symlink => std::fs::read_link(&path)?.to_string_lossy().into_owned(),
+                _ => String::new(),
+            };
+            let entry = Entry {
+                path: relative.to_string_lossy().replace('\\', "/"),
+                kind,
+                mode,
+                uid,
+                gid,
+                target,
+                chunks: Vec::new(),
+            };
+            entries.push((path, entry));
+        }
+    }
+    Ok(entries)
+}
+
+// `path` of a manifest entry, refusing anything that would land outside the workspace
+fn relative_path(path: &str) -> Result<PathBuf, BoxError> {
+    let path = PathBuf::from(path);
+    if path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        Ok(path)
+    } else {
+        Err(format!("Invalid path in backup: {}", path.display()).into())
+    }
+}
+
+#[cfg(unix)]
+fn ownership(metadata: &std::fs::Metadata) -> (u32, u32, u32) {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.mode(), metadata.uid(), metadata.gid())
+}
+
+#[cfg(not(unix))]
+fn ownership(_metadata: &std::fs::Metadata) -> (u32, u32, u32) {
+    (0, 0, 0)
+}
+
+#[cfg(unix)]
+fn symlink(target: &str, path: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+#[cfg(not(unix))]
+fn symlink(_target: &str, _path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_metadata(path: &Path, entry: &Entry) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    // Operators not running as root can still restore the data, owned by themselves
+    if let Err(e) = std::os::unix::fs::lchown(path, Some(entry.uid), Some(entry.gid)) {
+        tracing::warn!(
+            "Failed to restore the ownership of {}: {}",
+            path.display(),
+            e
+        );
+    }
+    // Links have no permissions of their own
+    if entry.kind != EntryKind::Symlink {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(entry.mode & 0o7777))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_metadata(_path: &Path, _entry: &Entry) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueprint_sdk::testing::tempfile::TempDir;
+
+    #[tokio::test]
+    async fn it_backs_up_incrementally_and_restores_for_the_owner() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path();
+        let workspace = root.join("workspaces/8");
+        std::fs::create_dir_all(workspace.join("notes")).unwrap();
+        std::fs::write(workspace.join("notes/todo.md"), "first").unwrap();
+        std::fs::write(workspace.join("config.json"), "{}").unwrap();
+
+        let config = BackupConfig {
+            target: TargetConfig::Dir(root.join("store")),
+            secret: Secret("operator secret".to_string()),
+            interval: DEFAULT_INTERVAL,
+            retention: DEFAULT_RETENTION,
+            keep: 1,
+        };
+        let backups = Backups::new(&config).unwrap();
+        let first = backups.backup(8, "aa", &workspace).await.unwrap();
+        assert_eq!(first.uploaded, 2);
+
+        // Only the changed file is uploaded again
+        std::fs::write(workspace.join("notes/todo.md"), "second").unwrap();
+        let second = backups.backup(8, "aa", &workspace).await.unwrap();
+        assert_eq!(second.uploaded, 1);
+        assert_eq!(backups.prune(8, Some("aa")).await.unwrap(), 1);
+        assert_eq!(backups.target.list("chunks/8/").await.unwrap().len(), 2);
+
+        // A capture keeps the data it was taken from, even once the workspace rewrites its
+        // files in place or is gone
+        let capture = Capture::take(&workspace).await.unwrap();
+        std::fs::write(workspace.join("notes/todo.md"), "third").unwrap();
+        std::fs::remove_dir_all(&workspace).unwrap();
+        let third = backups.upload(8, "aa", capture).await.unwrap();
+        assert_eq!(third.uploaded, 0);
+        assert!(!root.join("workspaces/8.backup").exists());
+
+        assert_eq!(backups.restore(8, "bb", &workspace).await.unwrap(), None);
+        let restored = backups.restore(8, "aa", &workspace).await.unwrap();
+        assert_eq!(restored, Some(third.created_at));
+        assert_eq!(
+            std::fs::read_to_string(workspace.join("notes/todo.md")).unwrap(),
+            "second"
+        );
+    }
+}
@@ -0,0 +1,575 @@
+//! Operator-side bridges in front of workspace MCP servers.
+//!
+//! The WebSocket bridge forwards every text frame received from a client as a JSON-RPC
+//! message to the workspace over its MCP transport, and sends every message the server
+//! emits back to the client as a text frame. The stdio bridge (see [`StdioBridge`])
+//! serves stdio-only MCP servers over HTTP.
+
+mod sse;
+mod stdio;
+
+pub use stdio::StdioBridge;
+
+use crate::auth::TIMESTAMP_HEADER;
+use crate::error::WorkspaceError;
+use crate::{McpTransport, TlsProxy};
+use blueprint_sdk::std::{Rng, rand};
+use futures_util::{SinkExt, StreamExt};
+use reqwest::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, HeaderMap, HeaderName, HeaderValue};
+use sse::EventStream;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio::task::{JoinHandle, JoinSet};
+use tokio_rustls::TlsAcceptor;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::handshake::server::{
+    Callback, ErrorResponse, Request, Response,
+};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+const SESSION_HEADER: &str = "mcp-session-id";
+
+// Pause after a failed accept, which keeps failing while e.g. file descriptors run out
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+// Operator-side bridges of a single workspace, all stopped when dropped
+pub struct WorkspaceBridges {
+    pub websocket: WsBridge,
+    /// Present when the workspace runs a stdio-only MCP server.
+    pub stdio: Option<StdioBridge>,
+    /// Present when the workspace endpoint is served over TLS.
+    pub tls_proxy: Option<TlsProxy>,
+}
+
+// A running WebSocket listener for a single workspace, stopped when dropped
+pub struct WsBridge {
+    port: u16,
+    secure: bool,
+    task: JoinHandle<()>,
+}
+
+impl WsBridge {
+    /// Start a bridge in front of the MCP server reachable at `upstream` (e.g. `http://127.0.0.1:12345`).
+    ///
+    /// Connections are accepted over TLS (`wss://`) when `tls` is set.
+    pub async fn spawn(
+        upstream: String,
+        transport: McpTransport,
+        tls: Option<TlsAcceptor>,
+    ) -> Result<Self, BoxError> {
+        let listener = bind_random_port().await?;
+        let port = listener.local_addr()?.port();
+        let client = reqwest::Client::new();
+        let secure = tls.is_some();
+
+        let task = tokio::spawn(async move {
+            // Connections live in the set so they are torn down together with the listener
+            let mut connections = JoinSet::new();
+            loop {
+                let (stream, peer) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        tracing::warn!("WebSocket bridge failed to accept connection: {}", e);
+                        tokio::time::sleep(ACCEPT_BACKOFF).await;
+                        continue;
+                    }
+                };
+
+                let client = client.clone();
+                let upstream = upstream.clone();
+                let tls = tls.clone();
+                connections.spawn(async move {
+                    let result = match tls {
+                        Some(acceptor) => match acceptor.accept(stream).await {
+                            Ok(stream) => {
+                                handle_connection(stream, client, upstream, transport).await
+                            }
+                            Err(e) => Err(e.into()),
+                        },
+                        None => handle_connection(stream, client, upstream, transport).await,
+                    };
+                    if let Err(e) = result {
+                        tracing::warn!("WebSocket bridge connection from {} failed: {}", peer, e);
+                    }
+                });
+
+                // Reap finished connections
+                while connections.try_join_next().is_some() {}
+            }
+        });
+
+        Ok(Self { port, secure, task })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn ws_url(&self, domain: &str) -> String {
+        let scheme = if self.secure { "wss" } else { "ws" };
+        format!("{}://{}:{}", scheme, domain, self.port)
+    }
+}
+
+impl Drop for WsBridge {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn bind_random_port() -> Result<TcpListener, BoxError> {
+    let mut rng = rand::rngs::OsRng;
+    // Allocate a random port between 20000-30000, retrying on collisions
+    for _ in 0..10 {
+        let port: u16 = rng.gen_range(20000..30000);
+        match TcpListener::bind(("0.0.0.0", port)).await {
+            Ok(listener) => return Ok(listener),
+            Err(e) => tracing::debug!("Failed to bind WebSocket bridge port {}: {}", port, e),
+        }
+    }
+
+    Err(WorkspaceError::PortExhausted.into())
+}
+
+async fn handle_connection<S>(
+    stream: S,
+    client: reqwest::Client,
+    upstream: String,
+    transport: McpTransport,
+) -> Result<(), BoxError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // The workspace authenticates the client itself, so its credentials are passed along
+    let mut credentials = HeaderMap::new();
+    let ws =
+        tokio_tungstenite::accept_hdr_async(stream, CaptureCredentials(&mut credentials)).await?;
+    let (mut sink, mut incoming) = ws.split();
+
+    // Messages emitted by the MCP server, in the order they should reach the client
+    let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+    let mut session = match transport {
+        McpTransport::Sse => {
+            Upstream::Sse(SseUpstream::connect(&client, &upstream, credentials, tx).await?)
+        }
+        McpTransport::StreamableHttp => Upstream::StreamableHttp(StreamableHttpUpstream {
+            client,
+            endpoint: format!("{}/mcp", upstream),
+            credentials,
+            session_id: None,
+            established: false,
+            tx,
+            tasks: JoinSet::new(),
+        }),
+    };
+
+    let result = loop {
+        tokio::select! {
+            frame = incoming.next() => match frame {
+                Some(Ok(Message::Text(text))) => {
+                    if let Err(e) = session.send(text.to_string()).await {
+                        break Err(e);
+                    }
+                }
+                Some(Ok(Message::Binary(bytes))) => {
+                    let text = match String::from_utf8(bytes.to_vec()) {
+                        Ok(text) => text,
+                        Err(e) => break Err(e.into()),
+                    };
+                    if let Err(e) = session.send(text).await {
+                        break Err(e);
+                    }
+                }
+                Some(Ok(Message::Close(_))) | None => break Ok(()),
+                Some(Ok(_)) => {}
+                Some(Err(e)) => break Err(e.into()),
+            },
+            message = rx.recv() => match message {
+                Some(message) => {
+                    if let Err(e) = sink.send(Message::text(message)).await {
+                        break Err(e.into());
+                    }
+                }
+                // The upstream stream ended, so there is nothing left to bridge
+                None => break Ok(()),
+            },
+        }
+    };
+
+    session.close().await;
+    let _ = sink.close().await;
+    result
+}
+
+// Handshake callback keeping the client's credentials: its `Authorization` header and the
+// timestamp an owner signature is made at
+struct CaptureCredentials<'a>(&'a mut HeaderMap);
+
+impl Callback for CaptureCredentials<'_> {
+    fn on_request(self, request: &Request, response: Response) -> Result<Response, ErrorResponse> {
+        for name in [AUTHORIZATION, HeaderName::from_static(TIMESTAMP_HEADER)] {
+            if let Some(value) = request
+                .headers()
+                .get(name.as_str())
+                .and_then(|value| HeaderValue::from_bytes(value.as_bytes()).ok())
+            {
+                self.0.insert(name, value);
+            }
+        }
+        Ok(response)
+    }
+}
+
+enum Upstream {
+    Sse(SseUpstream),
+    StreamableHttp(StreamableHttpUpstream),
+}
+
+impl Upstream {
+    async fn send(&mut self, message: String) -> Result<(), BoxError> {
+        match self {
+            Upstream::Sse(upstream) => upstream.send(message).await,
+            Upstream::StreamableHttp(upstream) => upstream.send(message).await,
+        }
+    }
+
+    async fn close(self) {
+        match self {
+            Upstream::Sse(upstream) => upstream.reader.abort(),
+            Upstream::StreamableHttp(upstream) => upstream.close().await,
+        }
+    }
+}
+
+// Legacy HTTP+SSE transport: a long-lived `GET /sse` stream plus a POST endpoint
+struct SseUpstream {
+    client: reqwest::Client,
+    endpoint: String,
+    credentials: HeaderMap,
+    reader: JoinHandle<()>,
+}
+
+impl SseUpstream {
+    async fn connect(
+        client: &reqwest::Client,
+        upstream: &str,
+        credentials: HeaderMap,
+        tx: mpsc::UnboundedSender<String>,
+    ) -> Result<Self, BoxError> {
+        let request = client
+            .get(format!("{}/sse", upstream))
+            .header(ACCEPT, "text/event-stream");
+        let response = request
+            .headers(credentials.clone())
+            .send()
+            .await?
+            .error_for_status()?;
+        let mut events = EventStream::new(response);
+
+        // The server announces where messages should be posted before anything else
+        let endpoint = loop {
+            match events.next_event().await {
+                Some(Ok(event)) if event.event == "endpoint" => break event.data,
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => return Err(e.into()),
+                None => return Err("SSE stream closed before the endpoint event".into()),
+            }
+        };
+        let endpoint = if endpoint.starts_with("http://") || endpoint.starts_with("https://") {
+            endpoint
+        } else {
+            format!("{}{}", upstream, endpoint)
+        };
+
+        let reader = tokio::spawn(forward_events(events, tx));
+
+        Ok(Self {
+            client: client.clone(),
+            endpoint,
+            credentials,
+            reader,
+        })
+    }
+
+    async fn send(&mut self, message: String) -> Result<(), BoxError> {
+        // Responses arrive on the event stream, the POST itself only acknowledges
+        let request = self
+            .client
+            .post(&self.endpoint)
+            .header(CONTENT_TYPE, "application/json")
+            .body(message);
+        request
+            .headers(self.credentials.clone())
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+// Streamable HTTP transport: every message is a POST to `/mcp`, answered with either a
+// JSON body or an SSE stream, within an optional `Mcp-Session-Id` session
+struct StreamableHttpUpstream {
+    client: reqwest::Client,
+    endpoint: String,
+    credentials: HeaderMap,
+    session_id: Option<String>,
+    // Whether the server answered once, and so assigned the session if it uses any
+    established: bool,
+    tx: mpsc::UnboundedSender<String>,
+    tasks: JoinSet<()>,
+}
+
+impl StreamableHttpUpstream {
+    async fn send(&mut self, message: String) -> Result<(), BoxError> {
+        // The ID of a request, to answer it with an error when its POST fails
+        let request_id = serde_json::from_str::<serde_json::Value>(&message)
+            .ok()
+            .filter(|message| message.get("method").is_some())
+            .and_then(|message| message.get("id").cloned());
+        let request = self
+            .client
+            .post(&self.endpoint)
+            .header(CONTENT_TYPE, "application/json")
+            .header(ACCEPT, "application/json, text/event-stream")
+            .body(message);
+        let mut request = request.headers(self.credentials.clone());
+        if let Some(session_id) = &self.session_id {
+            request = request.header(SESSION_HEADER, session_id);
+        }
+
+        // Requests run on their own once the session is known: a slow tool call mustn't hold
+        // up the client's other messages, such as its answers to what the server asks it
+        // in the meantime. Notifications and responses are accepted right away and stay in
+        // order.
+        if let Some(id) = request_id.filter(|_| self.established) {
+            let tx = self.tx.clone();
+            self.tasks.spawn(async move {
+                match request.send().await.and_then(|r| r.error_for_status()) {
+                    Ok(response) => forward_response(response, tx).await,
+                    Err(e) => {
+                        tracing::warn!("Upstream request failed: {}", e);
+                        let error = serde_json::json!({
+                            "jsonrpc": "2.0",
+                            "id": id,
+                            "error": { "code": -32603, "message": e.to_string() },
+                        });
+                        let _ = tx.send(error.to_string());
+                    }
+                }
+            });
+            return Ok(());
+        }
+
+        let response = request.send().await?.error_for_status()?;
+        self.established = true;
+        if self.session_id.is_none()
+            && let Some(session_id) = response
+                .headers()
+                .get(SESSION_HEADER)
+                .and_then(|value| value.to_str().ok())
+        {
+            self.session_id = Some(session_id.to_string());
+            self.listen_for_server_messages();
+        }
+        self.tasks
+            .spawn(forward_response(response, self.tx.clone()));
+
+        Ok(())
+    }
+
+    // Open the optional `GET /mcp` stream for server-initiated requests and notifications
+    fn listen_for_server_messages(&mut self) {
+        let request = self
+            .client
+            .get(&self.endpoint)
+            .header(ACCEPT, "text/event-stream")
+            .header(SESSION_HEADER, self.session_id.clone().unwrap_or_default());
+        let request = request.headers(self.credentials.clone());
+        let tx = self.tx.clone();
+        self.tasks.spawn(async move {
+            // Servers that don't offer the stream answer with 405, which is fine
+            if let Ok(response) = request.send().await
+                && response.status().is_success()
+                && is_event_stream(&response)
+            {
+                forward_events(EventStream::new(response), tx).await;
+            }
+        });
+    }
+
+    async fn close(mut self) {
+        self.tasks.abort_all();
+        // Explicitly terminate the session so the server can release it
+        if let Some(session_id) = self.session_id.take() {
+            let request = self
+                .client
+                .delete(&self.endpoint)
+                .header(SESSION_HEADER, session_id);
+            let _ = request.headers(self.credentials.clone()).send().await;
+        }
+    }
+}
+
+fn is_event_stream(response: &reqwest::Response) -> bool {
+    response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/event-stream"))
+}
+
+// Forward the messages a POST is answered with, either a JSON body or an event stream
+async fn forward_response(response: reqwest::Response, tx: mpsc::UnboundedSender<String>) {
+    if is_event_stream(&response) {
+        return forward_events(EventStream::new(response), tx).await;
+    }
+    match response.text().await {
+        Ok(body) if !body.trim().is_empty() => {
+            let _ = tx.send(body);
+        }
+        Ok(_) => {}
+        Err(e) => tracing::warn!("Failed to read upstream response: {}", e),
+    }
+}
+
+async fn forward_events(mut events: EventStream, tx: mpsc::UnboundedSender<String>) {
+    while let Some(event) = events.next_event().await {
+        match event {
+            Ok(event) if event.event == "message" => {
+                if tx.send(event.data).is_err() {
+                    break;
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                tracing::warn!("Upstream event stream failed: {}", e);
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Json;
+    use axum::extract::State;
+    use axum::routing::post;
+    use std::sync::Arc;
+    use tokio::sync::Notify;
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+    // Streamable HTTP server answering every message with what it got
+    async fn echo(headers: axum::http::HeaderMap, body: String) -> Json<serde_json::Value> {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        Json(serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "result": {
+                "authorization": header("authorization"),
+                "timestamp": header(TIMESTAMP_HEADER),
+                "cookie": header("cookie"),
+                "received": serde_json::from_str::<serde_json::Value>(&body).unwrap(),
+            },
+        }))
+    }
+
+    #[tokio::test]
+    async fn it_bridges_frames_to_the_workspace_with_the_client_credentials() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let upstream = format!("http://{}", listener.local_addr().unwrap());
+        let app = axum::Router::new().route("/mcp", post(echo));
+        tokio::spawn(axum::serve(listener, app).into_future());
+
+        let bridge = WsBridge::spawn(upstream, McpTransport::StreamableHttp, None)
+            .await
+            .unwrap();
+        let mut request = bridge.ws_url("127.0.0.1").into_client_request().unwrap();
+        let headers = request.headers_mut();
+        headers.insert("authorization", "Bearer token".parse().unwrap());
+        headers.insert(TIMESTAMP_HEADER, "1700000000".parse().unwrap());
+        headers.insert("cookie", "unrelated=1".parse().unwrap());
+        let stream = tokio::net::TcpStream::connect(("127.0.0.1", bridge.port()))
+            .await
+            .unwrap();
+        let (mut ws, _) = tokio_tungstenite::client_async(request, stream)
+            .await
+            .unwrap();
+
+        let ping = r#"{"jsonrpc":"2.0","id":1,"method":"ping"}"#;
+        ws.send(Message::text(ping)).await.unwrap();
+        let reply = match ws.next().await.unwrap().unwrap() {
+            Message::Text(text) => serde_json::from_str::<serde_json::Value>(&text).unwrap(),
+            other => panic!("Unexpected frame {:?}", other),
+        };
+        assert_eq!(reply["result"]["received"]["method"], "ping");
+        assert_eq!(reply["result"]["authorization"], "Bearer token");
+        assert_eq!(reply["result"]["timestamp"], "1700000000");
+        assert!(reply["result"]["cookie"].is_null());
+    }
+
+    // Streamable HTTP server holding tool calls until it gets a `release` request
+    async fn hold_calls(
+        State(release): State<Arc<Notify>>,
+        body: String,
+    ) -> Json<serde_json::Value> {
+        let message = serde_json::from_str::<serde_json::Value>(&body).unwrap();
+        match message["method"].as_str() {
+            Some("tools/call") => release.notified().await,
+            Some("release") => release.notify_one(),
+            _ => {}
+        }
+        Json(serde_json::json!({ "jsonrpc": "2.0", "id": message["id"], "result": {} }))
+    }
+
+    #[tokio::test]
+    async fn it_keeps_bridging_while_a_call_is_in_flight() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let upstream = format!("http://{}", listener.local_addr().unwrap());
+        let app = axum::Router::new()
+            .route("/mcp", post(hold_calls))
+            .with_state(Arc::new(Notify::new()));
+        tokio::spawn(axum::serve(listener, app).into_future());
+
+        let bridge = WsBridge::spawn(upstream, McpTransport::StreamableHttp, None)
+            .await
+            .unwrap();
+        let stream = tokio::net::TcpStream::connect(("127.0.0.1", bridge.port()))
+            .await
+            .unwrap();
+        let (ws, _) = tokio_tungstenite::client_async(bridge.ws_url("127.0.0.1"), stream)
+            .await
+            .unwrap();
+        let (mut sink, mut incoming) = ws.split();
+        let mut reply = async || match incoming.next().await.unwrap().unwrap() {
+            Message::Text(text) => serde_json::from_str::<serde_json::Value>(&text).unwrap(),
+            other => panic!("Unexpected frame {:?}", other),
+        };
+
+        let messages = [
+            r#"{"jsonrpc":"2.0","id":1,"method":"initialize"}"#,
+            r#"{"jsonrpc":"2.0","id":2,"method":"tools/call"}"#,
+            r#"{"jsonrpc":"2.0","id":3,"method":"release"}"#,
+        ];
+        let replies = tokio::time::timeout(Duration::from_secs(5), async {
+            sink.send(Message::text(messages[0])).await.unwrap();
+            let initialized = reply().await;
+            sink.send(Message::text(messages[1])).await.unwrap();
+            sink.send(Message::text(messages[2])).await.unwrap();
+            [initialized, reply().await, reply().await]
+        })
+        .await
+        .unwrap();
+        let ids: Vec<_> = replies.iter().map(|reply| reply["id"].clone()).collect();
+        assert_eq!(ids, [1, 3, 2]);
+    }
+}
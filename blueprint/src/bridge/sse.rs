@@ -0,0 +1,138 @@
+use bytes::Bytes;
+use futures_util::{Stream, StreamExt};
+use std::collections::VecDeque;
+use std::pin::Pin;
+
+// A single dispatched server-sent event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SseEvent {
+    pub event: String,
+    pub data: String,
+}
+
+// Incremental `text/event-stream` decoder, fed with arbitrary chunks of the body
+#[derive(Debug, Default)]
+pub(crate) struct SseDecoder {
+    // Bytes of the current line, only decoded once complete so UTF-8 characters split
+    // across chunks survive
+    buffer: Vec<u8>,
+    event: Option<String>,
+    data: Vec<String>,
+}
+
+impl SseDecoder {
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut events = Vec::new();
+        while let Some(pos) = self.buffer.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+
+            if line.is_empty() {
+                // A blank line dispatches the pending event
+                if !self.data.is_empty() {
+                    events.push(SseEvent {
+                        event: self.event.take().unwrap_or_else(|| "message".to_string()),
+                        data: self.data.join("\n"),
+                    });
+                }
+                self.event = None;
+                self.data.clear();
+                continue;
+            }
+
+            // Lines starting with a colon are comments (used as keep-alives)
+            if line.starts_with(':') {
+                continue;
+            }
+
+            let (field, value) = line.split_once(':').unwrap_or((line, ""));
+            let value = value.strip_prefix(' ').unwrap_or(value);
+            match field {
+                "event" => self.event = Some(value.to_string()),
+                "data" => self.data.push(value.to_string()),
+                _ => {}
+            }
+        }
+
+        events
+    }
+}
+
+// Server-sent events read from an upstream HTTP response body
+pub(crate) struct EventStream {
+    body: Pin<Box<dyn Stream<Item = reqwest::Result<Bytes>> + Send>>,
+    decoder: SseDecoder,
+    pending: VecDeque<SseEvent>,
+}
+
+impl EventStream {
+    pub fn new(response: reqwest::Response) -> Self {
+        Self {
+            body: Box::pin(response.bytes_stream()),
+            decoder: SseDecoder::default(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    pub async fn next_event(&mut self) -> Option<reqwest::Result<SseEvent>> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(Ok(event));
+            }
+
+            match self.body.next().await? {
+                Ok(chunk) => self.pending.extend(self.decoder.push(&chunk)),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_decodes_events_split_across_chunks() {
+        let mut decoder = SseDecoder::default();
+        assert!(
+            decoder
+                .push(b"event: endpoint\r\ndata: /messages?se")
+                .is_empty()
+        );
+        let events =
+            decoder.push(b"ssionId=1\r\n\r\n: keep-alive\n\ndata: {\"a\":1}\ndata: {\"b\":2}\n\n");
+        assert_eq!(
+            events,
+            vec![
+                SseEvent {
+                    event: "endpoint".to_string(),
+                    data: "/messages?sessionId=1".to_string(),
+                },
+                SseEvent {
+                    event: "message".to_string(),
+                    data: "{\"a\":1}\n{\"b\":2}".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn it_decodes_characters_split_across_chunks() {
+        let mut decoder = SseDecoder::default();
+        let message = "data: {\"text\":\"héllo\"}\n\n".as_bytes();
+        // Split inside the two bytes of `é`
+        let split = message.iter().position(|&byte| byte == 0xc3).unwrap() + 1;
+        assert!(decoder.push(&message[..split]).is_empty());
+        assert_eq!(
+            decoder.push(&message[split..]),
+            vec![SseEvent {
+                event: "message".to_string(),
+                data: "{\"text\":\"héllo\"}".to_string(),
+            }]
+        );
+    }
+}
@@ -0,0 +1,831 @@
+//! Re-exposes a stdio-only MCP server running inside a workspace container over the
+//! SSE and Streamable HTTP transports.
+//!
+//! The operator attaches to the container's stdin/stdout through the backend's attach API.
+//! A stdio server only knows a single client, so request IDs are rewritten per HTTP
+//! session and responses are routed back to the session that issued the request. The
+//! `initialize` handshake is forwarded once and its result replayed to later sessions.
+//! Requests the server makes itself (`sampling/createMessage`, `roots/list`...) go to a
+//! single session, the oldest one listening on an event stream whose `initialize` declared
+//! the capability they need. Sessions without a stream expire once idle for 30 minutes.
+//!
+//! Only the workspace owner may open sessions, signing [`session_message`] as described in
+//! [`crate::auth`]. The session ID then authenticates the session's later requests.
+
+use super::{BoxError, SESSION_HEADER};
+use crate::auth::{session_message, verify_owner};
+use crate::backend::{AttachedStdio, StdioOutput, StdioStream};
+use crate::error::WorkspaceError;
+use axum::Router;
+use axum::extract::{Query, State};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use blueprint_sdk::crypto::sp_core::SpSr25519Public;
+use blueprint_sdk::std::{Rng, rand};
+use futures_util::{Stream, StreamExt};
+use serde_json::{Value, json};
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+// HTTP front for a stdio MCP server, stopped when dropped
+pub struct StdioBridge {
+    mux: Arc<StdioMux>,
+    task: JoinHandle<()>,
+}
+
+impl StdioBridge {
+    /// Serve the stdio of an attached container on `addr` to the owner of workspace
+    /// `service_id`.
+    ///
+    /// The container must be attached to before it is started so no output is lost.
+    pub async fn spawn(
+        stdio: AttachedStdio,
+        addr: SocketAddr,
+        service_id: u64,
+        owner: SpSr25519Public,
+    ) -> Result<Self, BoxError> {
+        let AttachedStdio { output, input } = stdio;
+        let listener = TcpListener::bind(addr).await?;
+
+        let mux = Arc::new(StdioMux::new(input, service_id, owner));
+        let router = Router::new()
+            .route("/sse", get(sse_connect))
+            .route("/message", post(sse_message))
+            .route("/mcp", post(mcp_post).get(mcp_get).delete(mcp_delete))
+            .with_state(mux.clone());
+
+        let reader = mux.clone();
+        let task = tokio::spawn(async move {
+            tokio::select! {
+                () = reader.read_output(output) => {
+                    tracing::warn!("stdio MCP server closed its output");
+                    reader.fail_pending();
+                }
+                result = axum::serve(listener, router) => {
+                    if let Err(e) = result {
+                        tracing::warn!("stdio bridge HTTP server failed: {}", e);
+                    }
+                }
+                () = reader.expire_sessions() => {}
+            }
+        });
+
+        Ok(Self { mux, task })
+    }
+}
+
+impl Drop for StdioBridge {
+    fn drop(&mut self) {
+        // Dropping the session senders ends any open event streams
+        self.mux.close_all_sessions();
+        self.task.abort();
+    }
+}
+
+// Messages buffered for a session's client, beyond which they are dropped
+const SESSION_BUFFER: usize = 256;
+// Sessions without an event stream are closed after this long without a request, clients
+// going away without a DELETE would keep them forever otherwise
+const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+const SESSION_EXPIRY_INTERVAL: Duration = Duration::from_secs(60);
+
+struct Session {
+    tx: mpsc::Sender<Value>,
+    // Taken by whichever stream delivers server messages to the client
+    rx: Option<mpsc::Receiver<Value>>,
+    /// Order the session was opened in.
+    opened: u64,
+    /// Client capabilities declared by the session's `initialize` request.
+    capabilities: HashSet<String>,
+    /// When the client last sent a request.
+    last_seen: Instant,
+}
+
+impl Session {
+    // Whether a client is listening on the session's event stream
+    fn is_listening(&self) -> bool {
+        self.rx.is_none() && !self.tx.is_closed()
+    }
+}
+
+enum Reply {
+    Session(String),
+    Waiter(oneshot::Sender<Value>),
+}
+
+struct PendingRequest {
+    original_id: Value,
+    is_initialize: bool,
+    reply: Reply,
+}
+
+struct StdioMux {
+    stdin: tokio::sync::Mutex<Pin<Box<dyn AsyncWrite + Send>>>,
+    service_id: u64,
+    owner: SpSr25519Public,
+    next_id: AtomicU64,
+    next_session: AtomicU64,
+    pending: Mutex<HashMap<u64, PendingRequest>>,
+    sessions: Mutex<HashMap<String, Session>>,
+    initialize_result: Mutex<Option<Value>>,
+    initialized: AtomicBool,
+    // Set once the server's output ended, no response will arrive anymore
+    closed: AtomicBool,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> Result<MutexGuard<'_, T>, BoxError> {
+    mutex
+        .lock()
+        .map_err(|_| "stdio bridge state poisoned".into())
+}
+
+impl StdioMux {
+    fn new(
+        stdin: Pin<Box<dyn AsyncWrite + Send>>,
+        service_id: u64,
+        owner: SpSr25519Public,
+    ) -> Self {
+        Self {
+            stdin: tokio::sync::Mutex::new(stdin),
+            service_id,
+            owner,
+            next_id: AtomicU64::new(1),
+            next_session: AtomicU64::new(0),
+            pending: Mutex::new(HashMap::new()),
+            sessions: Mutex::new(HashMap::new()),
+            initialize_result: Mutex::new(None),
+            initialized: AtomicBool::new(false),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    // Check a request opening a session is signed by the workspace owner
+    fn authorize(&self, headers: &HeaderMap) -> Result<(), WorkspaceError> {
+        verify_owner(&self.owner, headers, |timestamp| {
+            session_message(self.service_id, timestamp)
+        })
+    }
+
+    fn open_session(&self) -> Result<String, BoxError> {
+        let mut rng = rand::rngs::OsRng;
+        let session_id = format!("{:032x}", rng.r#gen::<u128>());
+        let (tx, rx) = mpsc::channel(SESSION_BUFFER);
+        let session = Session {
+            tx,
+            rx: Some(rx),
+            opened: self.next_session.fetch_add(1, Ordering::SeqCst),
+            capabilities: HashSet::new(),
+            last_seen: Instant::now(),
+        };
+        lock(&self.sessions)?.insert(session_id.clone(), session);
+        Ok(session_id)
+    }
+
+    // Whether a session exists, marking it active when it does
+    fn touch_session(&self, session_id: &str) -> bool {
+        let Ok(mut sessions) = lock(&self.sessions) else {
+            return false;
+        };
+        match sessions.get_mut(session_id) {
+            Some(session) => {
+                session.last_seen = Instant::now();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Close the sessions nobody listens on that have been idle since before `now`
+    fn expire_idle_sessions(&self, now: Instant) {
+        let idle: Vec<String> = match lock(&self.sessions) {
+            Ok(sessions) => sessions
+                .iter()
+                .filter(|(_, session)| {
+                    !session.is_listening()
+                        && now.saturating_duration_since(session.last_seen) > SESSION_IDLE_TIMEOUT
+                })
+                .map(|(session_id, _)| session_id.clone())
+                .collect(),
+            Err(_) => return,
+        };
+        for session_id in idle {
+            tracing::debug!("Closing idle stdio bridge session {}", session_id);
+            self.close_session(&session_id);
+        }
+    }
+
+    async fn expire_sessions(&self) {
+        let mut interval = tokio::time::interval(SESSION_EXPIRY_INTERVAL);
+        loop {
+            interval.tick().await;
+            self.expire_idle_sessions(Instant::now());
+        }
+    }
+
+    fn take_receiver(&self, session_id: &str) -> Option<mpsc::Receiver<Value>> {
+        lock(&self.sessions)
+            .ok()?
+            .get_mut(session_id)
+            .and_then(|session| session.rx.take())
+    }
+
+    fn close_session(&self, session_id: &str) {
+        if let Ok(mut sessions) = lock(&self.sessions) {
+            sessions.remove(session_id);
+        }
+        if let Ok(mut pending) = lock(&self.pending) {
+            pending.retain(
+                |_, pending| !matches!(&pending.reply, Reply::Session(id) if id == session_id),
+            );
+        }
+    }
+
+    fn close_all_sessions(&self) {
+        if let Ok(mut sessions) = lock(&self.sessions) {
+            sessions.clear();
+        }
+        if let Ok(mut pending) = lock(&self.pending) {
+            pending.clear();
+        }
+    }
+
+    // Answer every request still waiting for the server with an error, once it is gone
+    fn fail_pending(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        let Ok(pending) = lock(&self.pending).map(|mut pending| std::mem::take(&mut *pending))
+        else {
+            return;
+        };
+        for (_, pending) in pending {
+            let response = json!({
+                "jsonrpc": "2.0",
+                "id": pending.original_id,
+                "error": { "code": -32603, "message": "stdio MCP server exited" },
+            });
+            match pending.reply {
+                Reply::Waiter(tx) => {
+                    let _ = tx.send(response);
+                }
+                Reply::Session(session_id) => self.send_to(&session_id, response),
+            }
+        }
+    }
+
+    fn send_to(&self, session_id: &str, message: Value) {
+        if let Ok(sessions) = lock(&self.sessions)
+            && let Some(session) = sessions.get(session_id)
+        {
+            send(session_id, session, message);
+        }
+    }
+
+    // Keep the client capabilities an `initialize` request declares for its session
+    fn record_capabilities(&self, session_id: &str, initialize: &Value) -> Result<(), BoxError> {
+        let capabilities = initialize
+            .pointer("/params/capabilities")
+            .and_then(Value::as_object)
+            .map(|capabilities| capabilities.keys().cloned().collect())
+            .unwrap_or_default();
+        if let Some(session) = lock(&self.sessions)?.get_mut(session_id) {
+            session.capabilities = capabilities;
+        }
+        Ok(())
+    }
+
+    // Session a server request goes to: the oldest one listening on its event stream that
+    // declared the capability the request needs. Clients can't tell whose request they are
+    // answering, sending it to several sessions would have each of them answer it.
+    fn designated_session(&self, method: &str) -> Result<Option<String>, BoxError> {
+        let capability = required_capability(method);
+        Ok(lock(&self.sessions)?
+            .iter()
+            .filter(|(_, session)| session.is_listening())
+            .filter(|(_, session)| {
+                capability.is_none_or(|capability| session.capabilities.contains(capability))
+            })
+            .min_by_key(|(_, session)| session.opened)
+            .map(|(session_id, _)| session_id.clone()))
+    }
+
+    async fn write(&self, message: &Value) -> Result<(), BoxError> {
+        let mut line = serde_json::to_vec(message)?;
+        line.push(b'\n');
+        let mut stdin = self.stdin.lock().await;
+        stdin.write_all(&line).await?;
+        stdin.flush().await?;
+        Ok(())
+    }
+
+    /// Forward a client message to the server.
+    ///
+    /// Responses to requests are either returned (when `wait` is set) or delivered to the
+    /// session's event stream.
+    async fn forward(
+        &self,
+        session_id: &str,
+        mut message: Value,
+        wait: bool,
+    ) -> Result<Option<Value>, BoxError> {
+        let method = message
+            .get("method")
+            .and_then(Value::as_str)
+            .map(str::to_owned);
+        let id = message.get("id").cloned();
+
+        let (Some(method), Some(original_id)) = (method.as_deref(), id) else {
+            // Notifications and responses to server requests pass through as-is, except
+            // for repeated `initialized` notifications from later sessions
+            if method.as_deref() == Some("notifications/initialized")
+                && self.initialized.swap(true, Ordering::SeqCst)
+            {
+                return Ok(None);
+            }
+            self.write(&message).await?;
+            return Ok(None);
+        };
+
+        let is_initialize = method == "initialize";
+        if is_initialize {
+            self.record_capabilities(session_id, &message)?;
+            let cached = lock(&self.initialize_result)?.clone();
+            if let Some(result) = cached {
+                let response = json!({ "jsonrpc": "2.0", "id": original_id, "result": result });
+                return self.deliver(session_id, response, wait);
+            }
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let (reply, waiter) = if wait {
+            let (tx, rx) = oneshot::channel();
+            (Reply::Waiter(tx), Some(rx))
+        } else {
+            (Reply::Session(session_id.to_string()), None)
+        };
+        lock(&self.pending)?.insert(
+            id,
+            PendingRequest {
+                original_id,
+                is_initialize,
+                reply,
+            },
+        );
+
+        message["id"] = json!(id);
+        let result = if self.closed.load(Ordering::SeqCst) {
+            Err("stdio MCP server went away".into())
+        } else {
+            self.write(&message).await
+        };
+        if let Err(e) = result {
+            lock(&self.pending)?.remove(&id);
+            return Err(e);
+        }
+
+        match waiter {
+            Some(rx) => Ok(Some(rx.await.map_err(|_| "stdio MCP server went away")?)),
+            None => Ok(None),
+        }
+    }
+
+    fn deliver(
+        &self,
+        session_id: &str,
+        message: Value,
+        wait: bool,
+    ) -> Result<Option<Value>, BoxError> {
+        if wait {
+            return Ok(Some(message));
+        }
+        self.send_to(session_id, message);
+        Ok(None)
+    }
+
+    async fn read_output(&self, mut output: StdioStream) {
+        let mut buffer = Vec::new();
+        while let Some(chunk) = output.next().await {
+            let message = match chunk {
+                Ok(StdioOutput::Stdout(message)) => message,
+                Ok(StdioOutput::Stderr(message)) => {
+                    tracing::debug!("stdio MCP server: {}", String::from_utf8_lossy(&message));
+                    continue;
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to read stdio MCP server output: {}", e);
+                    break;
+                }
+            };
+
+            buffer.extend_from_slice(&message);
+            while let Some(pos) = buffer.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=pos).collect();
+                if line.iter().all(u8::is_ascii_whitespace) {
+                    continue;
+                }
+                match serde_json::from_slice::<Value>(&line) {
+                    Ok(message) => match self.dispatch(message) {
+                        Ok(Some(reply)) => {
+                            if let Err(e) = self.write(&reply).await {
+                                tracing::warn!("Failed to answer stdio MCP server: {}", e);
+                            }
+                        }
+                        Ok(None) => {}
+                        Err(e) => {
+                            tracing::warn!("Failed to dispatch stdio MCP server message: {}", e)
+                        }
+                    },
+                    Err(_) => tracing::debug!(
+                        "Ignoring non JSON-RPC output: {}",
+                        String::from_utf8_lossy(&line)
+                    ),
+                }
+            }
+        }
+    }
+
+    // Route a message of the server to the clients, returning what to answer the server
+    // with itself when no client can
+    fn dispatch(&self, mut message: Value) -> Result<Option<Value>, BoxError> {
+        let method = message
+            .get("method")
+            .and_then(Value::as_str)
+            .map(str::to_owned);
+        let is_response =
+            method.is_none() && (message.get("result").is_some() || message.get("error").is_some());
+        let pending = match message.get("id").and_then(Value::as_u64) {
+            Some(id) if is_response => lock(&self.pending)?.remove(&id),
+            _ => None,
+        };
+
+        let Some(pending) = pending else {
+            match (method, message.get("id").cloned()) {
+                (Some(method), Some(id)) => match self.designated_session(&method)? {
+                    Some(session_id) => self.send_to(&session_id, message),
+                    None => {
+                        return Ok(Some(json!({
+                            "jsonrpc": "2.0",
+                            "id": id,
+                            "error": {
+                                "code": -32601,
+                                "message": format!("No client session supports {}", method),
+                            },
+                        })));
+                    }
+                },
+                // Notifications go to every connected session
+                (Some(_), None) => {
+                    for (session_id, session) in lock(&self.sessions)?.iter() {
+                        send(session_id, session, message.clone());
+                    }
+                }
+                (None, _) => tracing::debug!("Ignoring response to no pending request"),
+            }
+            return Ok(None);
+        };
+
+        if pending.is_initialize
+            && let Some(result) = message.get("result")
+        {
+            *lock(&self.initialize_result)? = Some(result.clone());
+        }
+
+        message["id"] = pending.original_id;
+        match pending.reply {
+            Reply::Waiter(tx) => {
+                let _ = tx.send(message);
+            }
+            Reply::Session(session_id) => self.send_to(&session_id, message),
+        }
+        Ok(None)
+    }
+}
+
+// Queue a message for the client of a session, dropping it rather than buffering without
+// bounds when the client doesn't keep up
+fn send(session_id: &str, session: &Session, message: Value) {
+    if let Err(mpsc::error::TrySendError::Full(_)) = session.tx.try_send(message) {
+        tracing::warn!(
+            "Dropping a message for stdio bridge session {}, its client isn't reading",
+            session_id
+        );
+    }
+}
+
+// Client capability a server request needs (`sampling` for `sampling/createMessage`), `None`
+// for those every client answers (`ping`)
+fn required_capability(method: &str) -> Option<&str> {
+    method.split_once('/').map(|(capability, _)| capability)
+}
+
+// Closes the session once the client's event stream is dropped
+struct SessionGuard {
+    mux: Arc<StdioMux>,
+    session_id: String,
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.mux.close_session(&self.session_id);
+    }
+}
+
+fn event_stream(
+    rx: mpsc::Receiver<Value>,
+    guard: Option<SessionGuard>,
+) -> impl Stream<Item = Result<Event, Infallible>> {
+    futures_util::stream::unfold((rx, guard), |(mut rx, guard)| async move {
+        let message = rx.recv().await?;
+        let event = Event::default().event("message").data(message.to_string());
+        Some((Ok(event), (rx, guard)))
+    })
+}
+
+// Legacy SSE transport: `GET /sse` opens a session and announces its POST endpoint
+async fn sse_connect(State(mux): State<Arc<StdioMux>>, headers: HeaderMap) -> Response {
+    if let Err(e) = mux.authorize(&headers) {
+        return (StatusCode::UNAUTHORIZED, e.to_string()).into_response();
+    }
+    let session_id = match mux.open_session() {
+        Ok(session_id) => session_id,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    let Some(rx) = mux.take_receiver(&session_id) else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let endpoint = Event::default()
+        .event("endpoint")
+        .data(format!("/message?sessionId={}", session_id));
+    let guard = SessionGuard {
+        mux: mux.clone(),
+        session_id,
+    };
+
+    let stream = futures_util::stream::once(async move { Ok(endpoint) })
+        .chain(event_stream(rx, Some(guard)));
+    Sse::new(stream)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+#[derive(serde::Deserialize)]
+struct MessageQuery {
+    #[serde(rename = "sessionId")]
+    session_id: String,
+}
+
+async fn sse_message(
+    State(mux): State<Arc<StdioMux>>,
+    Query(query): Query<MessageQuery>,
+    body: axum::body::Bytes,
+) -> Response {
+    if !mux.touch_session(&query.session_id) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let Ok(message) = serde_json::from_slice::<Value>(&body) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    match mux.forward(&query.session_id, message, false).await {
+        Ok(_) => StatusCode::ACCEPTED.into_response(),
+        Err(e) => (StatusCode::BAD_GATEWAY, e.to_string()).into_response(),
+    }
+}
+
+fn session_header(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(SESSION_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned)
+}
+
+fn is_initialize(message: &Value) -> bool {
+    message.get("method").and_then(Value::as_str) == Some("initialize")
+}
+
+// Streamable HTTP transport: every client message is POSTed to `/mcp`
+async fn mcp_post(
+    State(mux): State<Arc<StdioMux>>,
+    headers: HeaderMap,
+    body: axum::body::Bytes,
+) -> Response {
+    let Ok(payload) = serde_json::from_slice::<Value>(&body) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    let (messages, batch) = match payload {
+        Value::Array(messages) => (messages, true),
+        message => (vec![message], false),
+    };
+
+    let session_id = match session_header(&headers) {
+        Some(session_id) if mux.touch_session(&session_id) => session_id,
+        Some(_) => return StatusCode::NOT_FOUND.into_response(),
+        None if messages.iter().any(is_initialize) => {
+            if let Err(e) = mux.authorize(&headers) {
+                return (StatusCode::UNAUTHORIZED, e.to_string()).into_response();
+            }
+            match mux.open_session() {
+                Ok(session_id) => session_id,
+                Err(e) => {
+                    return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+                }
+            }
+        }
+        None => return (StatusCode::BAD_REQUEST, "Missing Mcp-Session-Id").into_response(),
+    };
+
+    let mut responses = Vec::new();
+    for message in messages {
+        match mux.forward(&session_id, message, true).await {
+            Ok(Some(response)) => responses.push(response),
+            Ok(None) => {}
+            Err(e) => return (StatusCode::BAD_GATEWAY, e.to_string()).into_response(),
+        }
+    }
+
+    let mut response = if responses.is_empty() {
+        StatusCode::ACCEPTED.into_response()
+    } else if batch {
+        axum::Json(Value::Array(responses)).into_response()
+    } else {
+        axum::Json(responses.remove(0)).into_response()
+    };
+    if let Ok(value) = HeaderValue::from_str(&session_id) {
+        response.headers_mut().insert(SESSION_HEADER, value);
+    }
+    response
+}
+
+// Optional stream of server-initiated messages for a Streamable HTTP session
+async fn mcp_get(State(mux): State<Arc<StdioMux>>, headers: HeaderMap) -> Response {
+    let Some(session_id) = session_header(&headers) else {
+        return (StatusCode::BAD_REQUEST, "Missing Mcp-Session-Id").into_response();
+    };
+    if !mux.touch_session(&session_id) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let Some(rx) = mux.take_receiver(&session_id) else {
+        // Only one stream per session is allowed
+        return StatusCode::CONFLICT.into_response();
+    };
+
+    Sse::new(event_stream(rx, None))
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+async fn mcp_delete(State(mux): State<Arc<StdioMux>>, headers: HeaderMap) -> StatusCode {
+    match session_header(&headers) {
+        Some(session_id) if mux.touch_session(&session_id) => {
+            mux.close_session(&session_id);
+            StatusCode::OK
+        }
+        Some(_) => StatusCode::NOT_FOUND,
+        None => StatusCode::BAD_REQUEST,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::TIMESTAMP_HEADER;
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD as BASE64;
+    use blueprint_sdk::crypto::sp_core::SpSr25519;
+    use blueprint_sdk::crypto::{BytesEncoding, KeyType};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[tokio::test]
+    async fn it_routes_server_requests_to_one_session_that_opted_in() {
+        let mut owner = SpSr25519::generate_with_seed(Some(&[1u8; 32])).unwrap();
+        let mut stranger = SpSr25519::generate_with_seed(Some(&[2u8; 32])).unwrap();
+        let (stdin, _server_stdin) = tokio::io::duplex(4096);
+        let mux = StdioMux::new(Box::pin(stdin), 1, owner.public());
+
+        // Only the owner opens sessions
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let mut headers = HeaderMap::new();
+        headers.insert(TIMESTAMP_HEADER, timestamp.to_string().parse().unwrap());
+        let mut sign = |signer| {
+            let signature =
+                SpSr25519::sign_with_secret(signer, &session_message(1, timestamp)).unwrap();
+            let value = format!("Sr25519 {}", BASE64.encode(signature.to_bytes()));
+            headers.insert("authorization", value.parse().unwrap());
+            headers.clone()
+        };
+        assert!(mux.authorize(&sign(&mut stranger)).is_err());
+        assert!(mux.authorize(&sign(&mut owner)).is_ok());
+
+        // Two concurrent sessions, only the second of which can sample
+        let first = mux.open_session().unwrap();
+        let second = mux.open_session().unwrap();
+        let mut first_rx = mux.take_receiver(&first).unwrap();
+        let mut second_rx = mux.take_receiver(&second).unwrap();
+        let initialize = |id, capabilities| {
+            json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "method": "initialize",
+                "params": { "capabilities": capabilities },
+            })
+        };
+        let first_init = initialize(1, json!({ "roots": {} }));
+        mux.forward(&first, first_init, false).await.unwrap();
+        let second_init = initialize(1, json!({ "roots": {}, "sampling": {} }));
+        mux.forward(&second, second_init, false).await.unwrap();
+
+        // The first session having the latest request in flight doesn't matter
+        let call = json!({ "jsonrpc": "2.0", "id": 7, "method": "tools/call" });
+        mux.forward(&first, call, false).await.unwrap();
+        let sampling = json!({ "jsonrpc": "2.0", "id": 1, "method": "sampling/createMessage" });
+        assert!(mux.dispatch(sampling.clone()).unwrap().is_none());
+        assert_eq!(second_rx.try_recv().unwrap(), sampling);
+        assert!(first_rx.try_recv().is_err());
+
+        // Requests both sessions can answer go to the oldest one only
+        let roots = json!({ "jsonrpc": "2.0", "id": 2, "method": "roots/list" });
+        assert!(mux.dispatch(roots.clone()).unwrap().is_none());
+        assert_eq!(first_rx.try_recv().unwrap(), roots);
+        assert!(second_rx.try_recv().is_err());
+
+        // Without a session that can answer, the server gets an error instead
+        mux.close_session(&second);
+        let unanswered = mux.dispatch(sampling).unwrap().unwrap();
+        assert_eq!(unanswered["id"], 1);
+        assert!(unanswered["error"].is_object());
+        assert!(first_rx.try_recv().is_err());
+
+        // The server exiting answers the call instead of leaving it pending
+        mux.fail_pending();
+        let failed: Vec<Value> = std::iter::from_fn(|| first_rx.try_recv().ok()).collect();
+        let failed_call = failed.iter().find(|message| message["id"] == 7).unwrap();
+        assert!(failed_call["error"].is_object());
+        let call = json!({ "jsonrpc": "2.0", "id": 8, "method": "tools/call" });
+        assert!(mux.forward(&first, call, true).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn it_sends_server_requests_only_to_sessions_with_a_live_stream() {
+        let owner = SpSr25519::generate_with_seed(Some(&[1u8; 32])).unwrap();
+        let (stdin, _server_stdin) = tokio::io::duplex(4096);
+        let mux = StdioMux::new(Box::pin(stdin), 1, owner.public());
+
+        // A client that went away, one that never opened a stream and a live one, all of
+        // which can sample
+        let gone = mux.open_session().unwrap();
+        let streamless = mux.open_session().unwrap();
+        let live = mux.open_session().unwrap();
+        let initialize = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "initialize",
+            "params": { "capabilities": { "sampling": {} } },
+        });
+        for session in [&gone, &streamless, &live] {
+            mux.forward(session, initialize.clone(), false)
+                .await
+                .unwrap();
+        }
+        drop(mux.take_receiver(&gone).unwrap());
+        let mut live_rx = mux.take_receiver(&live).unwrap();
+
+        let sampling = json!({ "jsonrpc": "2.0", "id": 1, "method": "sampling/createMessage" });
+        assert!(mux.dispatch(sampling.clone()).unwrap().is_none());
+        assert_eq!(live_rx.try_recv().unwrap(), sampling);
+
+        drop(live_rx);
+        let unanswered = mux.dispatch(sampling).unwrap().unwrap();
+        assert!(unanswered["error"].is_object());
+    }
+
+    #[tokio::test]
+    async fn it_expires_idle_sessions_nobody_listens_on() {
+        let owner = SpSr25519::generate_with_seed(Some(&[1u8; 32])).unwrap();
+        let (stdin, _server_stdin) = tokio::io::duplex(4096);
+        let mux = StdioMux::new(Box::pin(stdin), 1, owner.public());
+
+        let idle = mux.open_session().unwrap();
+        let listening = mux.open_session().unwrap();
+        let _rx = mux.take_receiver(&listening).unwrap();
+
+        mux.expire_idle_sessions(Instant::now());
+        assert!(mux.touch_session(&idle));
+
+        let later = Instant::now() + SESSION_IDLE_TIMEOUT + Duration::from_secs(1);
+        mux.expire_idle_sessions(later);
+        assert!(!mux.touch_session(&idle));
+        assert!(mux.touch_session(&listening));
+    }
+}
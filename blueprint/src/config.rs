@@ -0,0 +1,295 @@
+//! Operator configuration, read from `MCP_*` environment variables at startup.
+
+use crate::archive::{ArchiveConfig, DEFAULT_RETENTION};
+use crate::backup::BackupConfig;
+use crate::egress::{EgressMode, EgressPolicy, host_resolvers};
+use crate::runtime::RuntimeConfig;
+use crate::security::{SecurityConfig, read_seccomp_profile};
+use crate::storage::{StorageDriver, VolumeConfig};
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+
+// How workspace endpoints are secured
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub enum TlsMode {
+    /// Plain `http://` and `ws://` endpoints.
+    #[default]
+    Disabled,
+    /// Per-workspace certificates issued by a CA generated and kept in the data directory.
+    SelfSigned,
+    /// A single certificate chain and key provided by the operator, in PEM format.
+    Operator {
+        cert_path: PathBuf,
+        key_path: PathBuf,
+    },
+}
+
+// Container engine workspaces run on
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    /// Docker when configured or listening on its default socket, Podman otherwise.
+    #[default]
+    Auto,
+    Docker,
+    Podman,
+}
+
+// Client certificate and CA of a TLS-protected Docker daemon, in PEM format
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DockerTls {
+    pub ca_path: PathBuf,
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+}
+
+// How to reach the container engine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DockerConnection {
+    /// `unix:///path/to.sock` or `tcp://host:port`, auto-detected when unset (`MCP_DOCKER_HOST`).
+    pub host: Option<String>,
+    /// Mutual TLS for TCP endpoints (`MCP_DOCKER_TLS_CA`, `MCP_DOCKER_TLS_CERT`,
+    /// `MCP_DOCKER_TLS_KEY`).
+    pub tls: Option<DockerTls>,
+    /// API request timeout in seconds (`MCP_DOCKER_TIMEOUT`).
+    pub timeout: u64,
+}
+
+impl Default for DockerConnection {
+    fn default() -> Self {
+        Self {
+            host: None,
+            tls: None,
+            timeout: 120,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OperatorConfig {
+    /// Host name or IP address clients use to reach this operator (`MCP_PUBLIC_HOST`).
+    pub public_host: String,
+    /// TLS termination for workspace endpoints (`MCP_TLS_MODE`, `MCP_TLS_CERT`, `MCP_TLS_KEY`).
+    pub tls: TlsMode,
+    /// Name or ID of the operator's own container, attached to every workspace network
+    /// when the operator runs inside Docker (`MCP_OPERATOR_CONTAINER`).
+    pub operator_container: Option<String>,
+    /// Default egress policy of workspaces, which they may only tighten
+    /// (`MCP_EGRESS_MODE`, `open` unless set, `MCP_EGRESS_ALLOW` as a comma separated list).
+    pub egress: EgressPolicy,
+    /// DNS servers workspaces may query even when they are in a private range, the host's
+    /// nameservers unless set (`MCP_EGRESS_DNS` as a comma separated list of IPv4 addresses).
+    pub egress_resolvers: Vec<Ipv4Addr>,
+    /// Hardened container profile settings (`MCP_SECCOMP_PROFILE`, `MCP_APPARMOR_PROFILE`,
+    /// `MCP_IMAGE_PROFILES`).
+    pub security: SecurityConfig,
+    /// OCI runtime of workspace containers, operator-wide and per tier (`MCP_RUNTIME*`).
+    pub runtimes: RuntimeConfig,
+    /// Container engine (`MCP_BACKEND`: `auto`, `docker` or `podman`).
+    pub backend: BackendKind,
+    /// Connection to the container engine.
+    pub docker: DockerConnection,
+    /// Where workspace data is kept (`MCP_STORAGE_DRIVER`: `bind` or `volume`).
+    pub storage: StorageDriver,
+    /// Maximum number of workspaces this operator runs at once, unlimited when unset
+    /// (`MCP_MAX_WORKSPACES`).
+    pub max_workspaces: Option<usize>,
+    /// Where workspace data is archived on destroy and for how long (`MCP_ARCHIVE_DIR`,
+    /// `MCP_ARCHIVE_RETENTION` in seconds), deleted right away when unset.
+    pub archive: Option<ArchiveConfig>,
+    /// Seconds destroyed workspaces are kept stopped before being purged, during which they
+    /// can be undeleted (`MCP_DELETE_GRACE`). Destroyed right away when unset.
+    pub delete_grace: Option<u64>,
+    /// Directory of the blob store workspaces can be seeded from, blobs named by the SHA-256
+    /// of their contents (`MCP_BLOB_DIR`).
+    pub blob_dir: Option<PathBuf>,
+    /// Port of the API workspace owners manage their files through, disabled when unset
+    /// (`MCP_FILES_PORT`).
+    pub files_port: Option<u16>,
+    /// Encrypted off-host backups of workspace data (`MCP_BACKUP_*`), none when unset.
+    pub backup: Option<BackupConfig>,
+}
+
+impl Default for OperatorConfig {
+    fn default() -> Self {
+        Self {
+            public_host: "localhost".to_string(),
+            tls: TlsMode::default(),
+            operator_container: None,
+            egress: EgressPolicy::default(),
+            egress_resolvers: Vec::new(),
+            security: SecurityConfig::default(),
+            runtimes: RuntimeConfig::default(),
+            backend: BackendKind::default(),
+            docker: DockerConnection::default(),
+            storage: StorageDriver::default(),
+            max_workspaces: None,
+            archive: None,
+            delete_grace: None,
+            blob_dir: None,
+            files_port: None,
+            backup: None,
+        }
+    }
+}
+
+impl OperatorConfig {
+    pub fn from_env() -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let mut config = Self::default();
+
+        if let Ok(public_host) = std::env::var("MCP_PUBLIC_HOST") {
+            config.public_host = public_host;
+        }
+
+        config.operator_container = std::env::var("MCP_OPERATOR_CONTAINER").ok();
+
+        config.tls = match std::env::var("MCP_TLS_MODE").as_deref() {
+            Err(_) | Ok("disabled") => TlsMode::Disabled,
+            Ok("self-signed") => TlsMode::SelfSigned,
+            Ok("operator") => TlsMode::Operator {
+                cert_path: required_var("MCP_TLS_CERT")?.into(),
+                key_path: required_var("MCP_TLS_KEY")?.into(),
+            },
+            Ok(other) => return Err(format!("Invalid MCP_TLS_MODE: {}", other).into()),
+        };
+
+        config.egress.mode = match std::env::var("MCP_EGRESS_MODE").as_deref() {
+            Err(_) | Ok("open") => EgressMode::Open,
+            Ok("block-private") => EgressMode::BlockPrivate,
+            Ok("allowlist") => EgressMode::Allowlist,
+            Ok("deny-all") => EgressMode::DenyAll,
+            Ok(other) => return Err(format!("Invalid MCP_EGRESS_MODE: {}", other).into()),
+        };
+        if let Ok(allow) = std::env::var("MCP_EGRESS_ALLOW") {
+            config.egress.allow = allow
+                .split(',')
+                .map(str::trim)
+                .filter(|entry| !entry.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        // Validates the allowlist entries
+        EgressPolicy::for_workspace(None, &config.egress)?;
+        config.egress_resolvers = match std::env::var("MCP_EGRESS_DNS") {
+            Ok(resolvers) => resolvers
+                .split(',')
+                .map(str::trim)
+                .filter(|resolver| !resolver.is_empty())
+                .map(|resolver| {
+                    resolver
+                        .parse()
+                        .map_err(|_| format!("Invalid MCP_EGRESS_DNS entry: {}", resolver))
+                })
+                .collect::<Result<_, _>>()?,
+            Err(_) => host_resolvers(),
+        };
+
+        if let Ok(path) = std::env::var("MCP_SECCOMP_PROFILE") {
+            config.security.seccomp_profile = Some(read_seccomp_profile(&path)?);
+        }
+        config.security.apparmor_profile = std::env::var("MCP_APPARMOR_PROFILE").ok();
+        if let Ok(path) = std::env::var("MCP_IMAGE_PROFILES") {
+            let profiles = std::fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read MCP_IMAGE_PROFILES {}: {}", path, e))?;
+            config.security.image_profiles = serde_json::from_str(&profiles)
+                .map_err(|e| format!("Invalid MCP_IMAGE_PROFILES {}: {}", path, e))?;
+        }
+
+        config.runtimes = RuntimeConfig::from_env();
+
+        config.backend = match std::env::var("MCP_BACKEND").as_deref() {
+            Err(_) | Ok("auto") => BackendKind::Auto,
+            Ok("docker") => BackendKind::Docker,
+            Ok("podman") => BackendKind::Podman,
+            Ok(other) => return Err(format!("Invalid MCP_BACKEND: {}", other).into()),
+        };
+
+        config.docker.host = std::env::var("MCP_DOCKER_HOST").ok();
+        if std::env::var("MCP_DOCKER_TLS_CA").is_ok() {
+            if config.docker.host.is_none() {
+                return Err("MCP_DOCKER_TLS_CA requires MCP_DOCKER_HOST".into());
+            }
+            config.docker.tls = Some(DockerTls {
+                ca_path: required_var("MCP_DOCKER_TLS_CA")?.into(),
+                cert_path: required_var("MCP_DOCKER_TLS_CERT")?.into(),
+                key_path: required_var("MCP_DOCKER_TLS_KEY")?.into(),
+            });
+        }
+        config.storage = match std::env::var("MCP_STORAGE_DRIVER").as_deref() {
+            Err(_) | Ok("bind") => StorageDriver::Bind,
+            Ok("volume") => StorageDriver::Volume(VolumeConfig {
+                driver: std::env::var("MCP_VOLUME_DRIVER").ok(),
+                options: match std::env::var("MCP_VOLUME_OPTS") {
+                    Ok(options) => parse_options(&options)?,
+                    Err(_) => Default::default(),
+                },
+                size_option: std::env::var("MCP_VOLUME_SIZE_OPT").ok(),
+            }),
+            Ok(other) => return Err(format!("Invalid MCP_STORAGE_DRIVER: {}", other).into()),
+        };
+
+        if let Ok(max_workspaces) = std::env::var("MCP_MAX_WORKSPACES") {
+            config.max_workspaces = Some(
+                max_workspaces
+                    .parse()
+                    .map_err(|_| format!("Invalid MCP_MAX_WORKSPACES: {}", max_workspaces))?,
+            );
+        }
+
+        if let Ok(timeout) = std::env::var("MCP_DOCKER_TIMEOUT") {
+            config.docker.timeout = timeout
+                .parse()
+                .map_err(|_| format!("Invalid MCP_DOCKER_TIMEOUT: {}", timeout))?;
+        }
+
+        if let Ok(dir) = std::env::var("MCP_ARCHIVE_DIR") {
+            let retention = match std::env::var("MCP_ARCHIVE_RETENTION") {
+                Ok(retention) => retention
+                    .parse()
+                    .map_err(|_| format!("Invalid MCP_ARCHIVE_RETENTION: {}", retention))?,
+                Err(_) => DEFAULT_RETENTION,
+            };
+            config.archive = Some(ArchiveConfig {
+                dir: dir.into(),
+                retention,
+            });
+        }
+
+        if let Ok(grace) = std::env::var("MCP_DELETE_GRACE") {
+            let grace: u64 = grace
+                .parse()
+                .map_err(|_| format!("Invalid MCP_DELETE_GRACE: {}", grace))?;
+            config.delete_grace = (grace > 0).then_some(grace);
+        }
+
+        config.blob_dir = std::env::var("MCP_BLOB_DIR").ok().map(PathBuf::from);
+        if let Ok(port) = std::env::var("MCP_FILES_PORT") {
+            config.files_port = Some(
+                port.parse()
+                    .map_err(|_| format!("Invalid MCP_FILES_PORT: {}", port))?,
+            );
+        }
+        config.backup = BackupConfig::from_env()?;
+
+        Ok(config)
+    }
+}
+
+// `key=value,key=value` pairs
+fn parse_options(
+    options: &str,
+) -> Result<HashMap<String, String>, Box<dyn std::error::Error + Send + Sync>> {
+    options
+        .split(',')
+        .map(str::trim)
+        .filter(|option| !option.is_empty())
+        .map(|option| match option.split_once('=') {
+            Some((key, value)) => Ok((key.trim().to_string(), value.trim().to_string())),
+            None => Err(format!("Invalid MCP_VOLUME_OPTS entry: {}", option).into()),
+        })
+        .collect()
+}
+
+pub(crate) fn required_var(name: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    std::env::var(name).map_err(|_| format!("{} must be set", name).into())
+}
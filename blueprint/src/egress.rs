@@ -0,0 +1,488 @@
+//! Outbound traffic policy of workspaces.
+//!
+//! Workspaces are unrestricted unless the operator or the workspace picks a policy. Policies
+//! are enforced with iptables on the host: traffic entering the host from a
+//! workspace's bridge is sent to a per-workspace chain (`MCP-EG-{service_id}`) from both the
+//! backend's forward chain (`DOCKER-USER` on Docker, `FORWARD` on Podman, which has no
+//! `DOCKER-USER`) and `INPUT` (traffic to the host itself, e.g. a local Tangle RPC). Replies
+//! to connections made *to* the workspace are always let through.
+//!
+//! Domains in an allowlist are resolved once, when the policy is applied. For other names,
+//! workspaces may only query the operator's resolvers, which default to the host's
+//! nameservers. These stay reachable even when they are in a private range.
+//!
+//! Only IPv4 is filtered, as workspace networks are created without IPv6.
+
+use crate::error::WorkspaceError;
+use std::net::Ipv4Addr;
+use tokio::process::Command;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// Ranges blocked by every policy except `open`, including cloud metadata (169.254.169.254)
+const PRIVATE_RANGES: &[&str] = &[
+    "0.0.0.0/8",
+    "10.0.0.0/8",
+    "100.64.0.0/10",
+    "127.0.0.0/8",
+    "169.254.0.0/16",
+    "172.16.0.0/12",
+    "192.168.0.0/16",
+];
+
+/// Chain Docker filters forwarded container traffic in before its own rules.
+pub const DOCKER_USER_CHAIN: &str = "DOCKER-USER";
+/// The host's own chain for forwarded traffic, for engines without a chain of their own.
+pub const FORWARD_CHAIN: &str = "FORWARD";
+
+// Chains the workspace chain may be jumped to from, whichever backend applied it
+const PARENT_CHAINS: &[&str] = &[DOCKER_USER_CHAIN, FORWARD_CHAIN, "INPUT"];
+
+// Outbound traffic a workspace is allowed, from least to most restrictive
+#[derive(
+    Default,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    serde::Deserialize,
+    serde::Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum EgressMode {
+    /// No restrictions, and no iptables needed on the host.
+    #[default]
+    Open,
+    /// Anything but private, loopback and link-local ranges.
+    BlockPrivate,
+    /// Only the domains and CIDRs in the allowlist, plus DNS through the operator's resolvers.
+    /// Private ranges stay blocked unless a CIDR of the allowlist covers them.
+    Allowlist,
+    /// No outbound connections at all.
+    DenyAll,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct EgressPolicy {
+    pub mode: EgressMode,
+    /// Domains (`api.github.com`) and IPv4 addresses or CIDRs (`1.1.1.1`, `8.8.8.0/24`)
+    /// reachable in allowlist mode.
+    #[serde(default)]
+    pub allow: Vec<String>,
+}
+
+impl EgressPolicy {
+    /// The policy of a new workspace, given the one it requested and the operator's default.
+    ///
+    /// Workspaces may tighten the operator's policy but never loosen it.
+    pub fn for_workspace(
+        requested: Option<&EgressPolicy>,
+        operator: &EgressPolicy,
+    ) -> Result<Self, BoxError> {
+        let policy = match requested {
+            None => operator,
+            Some(requested) if requested.mode < operator.mode => {
+                return Err(WorkspaceError::Unauthorized(format!(
+                    "Egress mode {:?} is less restrictive than the operator's {:?}",
+                    requested.mode, operator.mode
+                ))
+                .into());
+            }
+            Some(requested) => requested,
+        };
+
+        if policy.mode == EgressMode::Allowlist
+            && operator.mode == EgressMode::Allowlist
+            && let Some(entry) = policy
+                .allow
+                .iter()
+                .find(|entry| !operator.allow.contains(entry))
+        {
+            return Err(WorkspaceError::Unauthorized(format!(
+                "Egress destination {} is not allowed by the operator",
+                entry
+            ))
+            .into());
+        }
+        for entry in &policy.allow {
+            parse_entry(entry)?;
+        }
+
+        Ok(policy.clone())
+    }
+}
+
+enum AllowEntry {
+    Cidr(String),
+    Domain(String),
+}
+
+fn parse_entry(entry: &str) -> Result<AllowEntry, BoxError> {
+    let (address, prefix) = match entry.split_once('/') {
+        Some((address, prefix)) => (address, Some(prefix)),
+        None => (entry, None),
+    };
+    if let Ok(address) = address.parse::<Ipv4Addr>() {
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= 32)
+                .ok_or_else(|| {
+                    WorkspaceError::InvalidParams(format!("Invalid egress CIDR: {}", entry))
+                })?,
+            None => 32,
+        };
+        return Ok(AllowEntry::Cidr(format!("{}/{}", address, prefix)));
+    }
+
+    let is_domain = prefix.is_none()
+        && !entry.is_empty()
+        && entry.len() <= 253
+        && entry.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+    if is_domain {
+        Ok(AllowEntry::Domain(entry.to_ascii_lowercase()))
+    } else {
+        Err(WorkspaceError::InvalidParams(format!("Invalid egress destination: {}", entry)).into())
+    }
+}
+
+fn chain_name(service_id: u64) -> String {
+    format!("MCP-EG-{}", service_id)
+}
+
+/// Nameservers of the host, which Docker's embedded resolver forwards the queries of
+/// workspaces to. Loopback ones are skipped as workspaces can't reach them, in favor of
+/// those systemd-resolved uses when it runs.
+pub fn host_resolvers() -> Vec<Ipv4Addr> {
+    ["/etc/resolv.conf", "/run/systemd/resolve/resolv.conf"]
+        .iter()
+        .filter_map(|path| std::fs::read_to_string(path).ok())
+        .map(|contents| {
+            contents
+                .lines()
+                .filter_map(|line| line.trim().strip_prefix("nameserver"))
+                .filter_map(|address| address.trim().parse::<Ipv4Addr>().ok())
+                .filter(|address| !address.is_loopback())
+                .collect::<Vec<_>>()
+        })
+        .find(|resolvers| !resolvers.is_empty())
+        .unwrap_or_default()
+}
+
+// Jump from `parent` to the workspace chain for traffic coming from the bridge. `-o` is
+// only allowed when forwarding, traffic to the host has no output interface.
+fn jump_rule(parent: &str, interface: &str, chain: &str) -> Vec<String> {
+    let mut rule = args(&["-i", interface]);
+    if parent != "INPUT" {
+        rule.extend(args(&["!", "-o", interface]));
+    }
+    rule.extend(args(&["-j", chain]));
+    rule
+}
+
+// Destinations of an allowlist: its own CIDRs, which may be private (e.g. an internal
+// registry), and the addresses its domains resolve to, which may not
+#[derive(Default)]
+struct Allowed {
+    cidrs: Vec<String>,
+    resolved: Vec<String>,
+}
+
+// Rules of the workspace chain for `mode`, given the resolvers workspaces may query and the
+// destinations of an allowlist
+fn policy_rules(mode: EgressMode, resolvers: &[Ipv4Addr], allowed: &Allowed) -> Vec<Vec<String>> {
+    let mut rules: Vec<Vec<String>> = vec![args(&[
+        "-m",
+        "conntrack",
+        "--ctstate",
+        "ESTABLISHED,RELATED",
+        "-j",
+        "RETURN",
+    ])];
+    // Name resolution has to keep working through the resolvers, which may well be in a
+    // private range (e.g. a cloud provider's)
+    let dns = resolvers.iter().flat_map(|resolver| {
+        ["udp", "tcp"].map(|protocol| {
+            args(&[
+                "-d",
+                &format!("{}/32", resolver),
+                "-p",
+                protocol,
+                "--dport",
+                "53",
+                "-j",
+                "RETURN",
+            ])
+        })
+    });
+    let private = PRIVATE_RANGES
+        .iter()
+        .map(|range| args(&["-d", range, "-j", "DROP"]));
+
+    match mode {
+        EgressMode::Open => {}
+        EgressMode::BlockPrivate => {
+            rules.extend(dns);
+            rules.extend(private);
+            rules.push(args(&["-j", "RETURN"]));
+        }
+        EgressMode::Allowlist => {
+            let allow = |destination: &String| args(&["-d", destination, "-j", "RETURN"]);
+            rules.extend(dns);
+            rules.extend(allowed.cidrs.iter().map(allow));
+            rules.extend(private);
+            rules.extend(allowed.resolved.iter().map(allow));
+            rules.push(args(&["-j", "DROP"]));
+        }
+        EgressMode::DenyAll => rules.push(args(&["-j", "DROP"])),
+    }
+    rules
+}
+
+/// Enforce `policy` on traffic leaving the workspace bridge `interface`, replacing any
+/// rules a previous attempt left behind. Forwarded traffic is filtered from `forward_chain`.
+/// `resolvers` are the DNS servers workspaces may query, even when they are in a private
+/// range.
+pub async fn apply_egress_policy(
+    service_id: u64,
+    interface: &str,
+    forward_chain: &str,
+    policy: &EgressPolicy,
+    resolvers: &[Ipv4Addr],
+) -> Result<(), BoxError> {
+    remove_egress_policy(service_id).await?;
+    if policy.mode == EgressMode::Open {
+        return Ok(());
+    }
+
+    let allowed = match policy.mode {
+        EgressMode::Allowlist => resolve_allowlist(&policy.allow).await?,
+        _ => Allowed::default(),
+    };
+    let chain = chain_name(service_id);
+    let rules = policy_rules(policy.mode, resolvers, &allowed);
+
+    iptables(&["-N", &chain]).await?;
+    for rule in rules {
+        let mut command = args(&["-A", &chain]);
+        command.extend(rule);
+        iptables(&command.iter().map(String::as_str).collect::<Vec<_>>()).await?;
+    }
+    for parent in [forward_chain, "INPUT"] {
+        let mut command = args(&["-I", parent]);
+        command.extend(jump_rule(parent, interface, &chain));
+        iptables(&command.iter().map(String::as_str).collect::<Vec<_>>()).await?;
+    }
+
+    tracing::info!(
+        "Applied egress policy {:?} to workspace {} ({})",
+        policy.mode,
+        service_id,
+        interface
+    );
+    Ok(())
+}
+
+/// Remove the egress rules of a workspace, succeeding if there are none.
+pub async fn remove_egress_policy(service_id: u64) -> Result<(), BoxError> {
+    let chain = chain_name(service_id);
+
+    // Find the jumps by listing the parent chains, the bridge may be gone by now. Chains the
+    // host doesn't have fail to list.
+    for parent in PARENT_CHAINS {
+        let Ok(listing) = iptables(&["-S", parent]).await else {
+            continue;
+        };
+        for line in listing.lines() {
+            let rule: Vec<&str> = line.split_whitespace().collect();
+            if rule.first() == Some(&"-A") && rule.ends_with(&["-j", chain.as_str()]) {
+                let mut delete = vec!["-D"];
+                delete.extend(&rule[1..]);
+                iptables(&delete).await?;
+            }
+        }
+    }
+
+    if iptables(&["-S", &chain]).await.is_ok() {
+        iptables(&["-F", &chain]).await?;
+        iptables(&["-X", &chain]).await?;
+    }
+    Ok(())
+}
+
+async fn resolve_allowlist(allow: &[String]) -> Result<Allowed, BoxError> {
+    let mut cidrs = Vec::new();
+    let mut destinations = Vec::new();
+    for entry in allow {
+        match parse_entry(entry)? {
+            AllowEntry::Cidr(cidr) => cidrs.push(cidr),
+            AllowEntry::Domain(domain) => {
+                let addresses = tokio::net::lookup_host((domain.as_str(), 443))
+                    .await
+                    .map_err(|e| {
+                        format!("Failed to resolve egress destination {}: {}", domain, e)
+                    })?;
+                for address in addresses.filter(|address| address.is_ipv4()) {
+                    destinations.push(format!("{}/32", address.ip()));
+                }
+            }
+        }
+    }
+    for destinations in [&mut cidrs, &mut destinations] {
+        destinations.sort();
+        destinations.dedup();
+    }
+    Ok(Allowed {
+        cidrs,
+        resolved: destinations,
+    })
+}
+
+fn args(values: &[&str]) -> Vec<String> {
+    values.iter().map(|value| value.to_string()).collect()
+}
+
+async fn iptables(args: &[&str]) -> Result<String, BoxError> {
+    let output = Command::new("iptables")
+        .arg("-w")
+        .args(args)
+        .output()
+        .await
+        .map_err(|e| format!("Failed to run iptables: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "iptables {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_only_lets_workspaces_tighten_the_operator_policy() {
+        let operator = EgressPolicy {
+            mode: EgressMode::Allowlist,
+            allow: vec!["api.github.com".to_string(), "1.1.1.1/32".to_string()],
+        };
+
+        assert_eq!(
+            EgressPolicy::for_workspace(None, &operator).unwrap(),
+            operator
+        );
+
+        let narrower = EgressPolicy {
+            mode: EgressMode::Allowlist,
+            allow: vec!["api.github.com".to_string()],
+        };
+        assert_eq!(
+            EgressPolicy::for_workspace(Some(&narrower), &operator).unwrap(),
+            narrower
+        );
+
+        let wider = EgressPolicy {
+            mode: EgressMode::Allowlist,
+            allow: vec!["example.com".to_string()],
+        };
+        assert!(EgressPolicy::for_workspace(Some(&wider), &operator).is_err());
+
+        let open = EgressPolicy {
+            mode: EgressMode::Open,
+            allow: vec![],
+        };
+        assert!(EgressPolicy::for_workspace(Some(&open), &operator).is_err());
+
+        let invalid = EgressPolicy {
+            mode: EgressMode::Allowlist,
+            allow: vec!["10.0.0.0/33".to_string()],
+        };
+        assert!(EgressPolicy::for_workspace(Some(&invalid), &EgressPolicy::default()).is_err());
+    }
+
+    fn first_drop(rules: &[Vec<String>]) -> Option<usize> {
+        rules
+            .iter()
+            .position(|rule| rule.ends_with(&args(&["-j", "DROP"])))
+    }
+
+    #[test]
+    fn it_lets_dns_through_to_private_resolvers_before_blocking_private_ranges() {
+        let resolver = Ipv4Addr::new(10, 0, 0, 2);
+        let dns = [
+            args(&[
+                "-d",
+                "10.0.0.2/32",
+                "-p",
+                "udp",
+                "--dport",
+                "53",
+                "-j",
+                "RETURN",
+            ]),
+            args(&[
+                "-d",
+                "10.0.0.2/32",
+                "-p",
+                "tcp",
+                "--dport",
+                "53",
+                "-j",
+                "RETURN",
+            ]),
+        ];
+        let block_private = args(&["-d", "10.0.0.0/8", "-j", "DROP"]);
+
+        let rules = policy_rules(EgressMode::BlockPrivate, &[resolver], &Allowed::default());
+        assert_eq!(rules[1..3], dns);
+        assert_eq!(first_drop(&rules), Some(3));
+        assert!(rules.contains(&block_private));
+        assert_eq!(rules.last(), Some(&args(&["-j", "RETURN"])));
+
+        // Only CIDRs of the allowlist itself may open up private ranges
+        let allowed = Allowed {
+            cidrs: vec!["10.1.0.0/16".to_string()],
+            resolved: vec!["1.1.1.1/32".to_string()],
+        };
+        let rules = policy_rules(EgressMode::Allowlist, &[resolver], &allowed);
+        assert_eq!(rules[1..3], dns);
+        assert_eq!(rules[3], args(&["-d", "10.1.0.0/16", "-j", "RETURN"]));
+        assert_eq!(first_drop(&rules), Some(4));
+        let position = |rule: &Vec<String>| rules.iter().position(|other| other == rule);
+        let resolved = args(&["-d", "1.1.1.1/32", "-j", "RETURN"]);
+        assert!(position(&block_private) < position(&resolved));
+        assert_eq!(rules.last(), Some(&args(&["-j", "DROP"])));
+
+        let rules = policy_rules(EgressMode::DenyAll, &[resolver], &Allowed::default());
+        assert_eq!(rules.last(), Some(&args(&["-j", "DROP"])));
+        assert_eq!(rules.len(), 2);
+    }
+
+    #[test]
+    fn it_jumps_to_the_workspace_chain_from_every_parent() {
+        let rules: Vec<_> = PARENT_CHAINS
+            .iter()
+            .map(|parent| jump_rule(parent, "br-1234", "MCP-EG-1"))
+            .collect();
+        assert_eq!(
+            rules,
+            vec![
+                args(&["-i", "br-1234", "!", "-o", "br-1234", "-j", "MCP-EG-1"]),
+                args(&["-i", "br-1234", "!", "-o", "br-1234", "-j", "MCP-EG-1"]),
+                args(&["-i", "br-1234", "-j", "MCP-EG-1"]),
+            ]
+        );
+    }
+}
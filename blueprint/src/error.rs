@@ -0,0 +1,167 @@
+//! Typed job failures with stable codes.
+//!
+//! Failed jobs still produce a result on chain, with its `error` field set, so clients can
+//! tell what went wrong and whether retrying makes sense. Codes are part of the job ABI and
+//! must never be renumbered:
+//!
+//! | Code | Error                  | Retryable |
+//! |------|------------------------|-----------|
+//! | 0    | none                   |           |
+//! | 1    | `InvalidParams`        | no        |
+//! | 2    | `Unauthorized`         | no        |
+//! | 3    | `ImageMissing`         | no        |
+//! | 4    | `CapacityExceeded`     | yes       |
+//! | 5    | `PortExhausted`        | yes       |
+//! | 6    | `HealthTimeout`        | yes       |
+//! | 7    | `BackendUnavailable`   | yes       |
+//! | 8    | `Internal`             | no        |
+//! | 9    | `QuotaExceeded`        | no        |
+
+use docktopus::bollard::errors::Error as DockerError;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WorkspaceError {
+    /// The job parameters are malformed.
+    InvalidParams(String),
+    /// The caller isn't allowed to do this, e.g. loosen the operator's egress policy.
+    Unauthorized(String),
+    /// The workspace image isn't available on the operator.
+    ImageMissing(String),
+    /// The operator runs as many workspaces as it is configured for.
+    CapacityExceeded,
+    /// No free host port could be found.
+    PortExhausted,
+    /// The workspace didn't become healthy in time.
+    HealthTimeout,
+    /// The container engine can't be reached.
+    BackendUnavailable(String),
+    /// Anything else went wrong on the operator.
+    Internal(String),
+    /// The workspace would use more storage than its tier allows.
+    QuotaExceeded(String),
+}
+
+impl WorkspaceError {
+    pub fn code(&self) -> u16 {
+        match self {
+            WorkspaceError::InvalidParams(_) => 1,
+            WorkspaceError::Unauthorized(_) => 2,
+            WorkspaceError::ImageMissing(_) => 3,
+            WorkspaceError::CapacityExceeded => 4,
+            WorkspaceError::PortExhausted => 5,
+            WorkspaceError::HealthTimeout => 6,
+            WorkspaceError::BackendUnavailable(_) => 7,
+            WorkspaceError::Internal(_) => 8,
+            WorkspaceError::QuotaExceeded(_) => 9,
+        }
+    }
+
+    /// Whether the same call may succeed later without changes.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            WorkspaceError::CapacityExceeded
+                | WorkspaceError::PortExhausted
+                | WorkspaceError::HealthTimeout
+                | WorkspaceError::BackendUnavailable(_)
+        )
+    }
+}
+
+impl fmt::Display for WorkspaceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WorkspaceError::InvalidParams(message) => write!(f, "Invalid parameters: {}", message),
+            WorkspaceError::Unauthorized(message) => write!(f, "Unauthorized: {}", message),
+            WorkspaceError::ImageMissing(image) => write!(f, "Image {} is not available", image),
+            WorkspaceError::CapacityExceeded => f.write_str("Operator is at capacity"),
+            WorkspaceError::PortExhausted => f.write_str("No free host port available"),
+            WorkspaceError::HealthTimeout => {
+                f.write_str("Container failed to become healthy within timeout")
+            }
+            WorkspaceError::BackendUnavailable(message) => {
+                write!(f, "Container engine unavailable: {}", message)
+            }
+            WorkspaceError::Internal(message) => f.write_str(message),
+            WorkspaceError::QuotaExceeded(message) => {
+                write!(f, "Storage quota exceeded: {}", message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for WorkspaceError {}
+
+// Classify errors bubbling up from helpers, which return boxed errors
+impl From<Box<dyn std::error::Error + Send + Sync>> for WorkspaceError {
+    fn from(error: Box<dyn std::error::Error + Send + Sync>) -> Self {
+        let error = match error.downcast::<WorkspaceError>() {
+            Ok(error) => return *error,
+            Err(error) => error,
+        };
+
+        match error.downcast::<DockerError>() {
+            Ok(error) => match *error {
+                DockerError::DockerResponseServerError {
+                    status_code: 404,
+                    message,
+                } if message.contains("No such image") || message.contains("image not known") => {
+                    WorkspaceError::ImageMissing(message)
+                }
+                // The engine answered, so it's reachable
+                DockerError::DockerResponseServerError { message, .. } => {
+                    WorkspaceError::Internal(message)
+                }
+                error => WorkspaceError::BackendUnavailable(error.to_string()),
+            },
+            Err(error) => WorkspaceError::Internal(error.to_string()),
+        }
+    }
+}
+
+// Error part of every job result, all zero on success
+#[derive(Default, Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct JobError {
+    /// Stable error code, see [`WorkspaceError::code`]. `0` on success.
+    pub code: u16,
+    pub message: String,
+    pub retryable: bool,
+}
+
+impl From<&WorkspaceError> for JobError {
+    fn from(error: &WorkspaceError) -> Self {
+        Self {
+            code: error.code(),
+            message: error.to_string(),
+            retryable: error.is_retryable(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_classifies_boxed_errors() {
+        let boxed: Box<dyn std::error::Error + Send + Sync> = WorkspaceError::HealthTimeout.into();
+        assert_eq!(WorkspaceError::from(boxed), WorkspaceError::HealthTimeout);
+
+        let boxed: Box<dyn std::error::Error + Send + Sync> =
+            DockerError::DockerResponseServerError {
+                status_code: 404,
+                message: "No such image: tangle-mcp:0.1.0".to_string(),
+            }
+            .into();
+        let error = WorkspaceError::from(boxed);
+        assert_eq!(error.code(), 3);
+        assert!(!error.is_retryable());
+
+        let boxed: Box<dyn std::error::Error + Send + Sync> =
+            DockerError::RequestTimeoutError.into();
+        let error = JobError::from(&WorkspaceError::from(boxed));
+        assert_eq!(error.code, 7);
+        assert!(error.retryable);
+    }
+}
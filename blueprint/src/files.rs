@@ -0,0 +1,609 @@
+//! HTTP API over the files of workspaces, for their owners.
+//!
+//! `/workspaces/{service_id}/files?path={path}` lists a directory or downloads a file on
+//! `GET`, uploads a file on `PUT` (creating missing directories) and deletes a file or
+//! directory on `DELETE`. Paths are relative to the workspace's `/blueprint` directory and
+//! may neither leave it nor go through links. The workspace can change its files while a
+//! request is served, so paths are resolved one open directory at a time and files are
+//! opened without following links.
+//!
+//! Every request is signed by the owner (see [`crate::auth`]) over
+//! `{method}\n{service_id}\n{path}\n{timestamp}\n{hex SHA-256 of the body}`.
+
+use crate::auth::verify_owner;
+use crate::error::{JobError, WorkspaceError};
+use crate::security::{DataOwner, chown_workspace_fd};
+use crate::snapshot::{SnapshotStore, dir_size};
+use crate::storage;
+use crate::{MyContext, WORKSPACE_IMAGE};
+use axum::Router;
+use axum::body::{Body, Bytes};
+use axum::extract::{DefaultBodyLimit, Path as UrlPath, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use blueprint_sdk::std::rand::{self, RngCore};
+use sha2::{Digest, Sha256};
+use std::ffi::OsString;
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+pub use crate::auth::TIMESTAMP_HEADER;
+
+/// Largest file a single request may upload.
+pub const MAX_UPLOAD_SIZE: usize = 64 * 1024 * 1024;
+
+#[derive(Default, Debug, serde::Deserialize)]
+pub struct FileQuery {
+    /// Path under the workspace directory, the directory itself when empty.
+    #[serde(default)]
+    pub path: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileKind {
+    File,
+    Directory,
+    Symlink,
+}
+
+// Entry of a directory listing
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct FileEntry {
+    pub name: String,
+    pub kind: FileKind,
+    pub size: u64,
+    /// Seconds since the Unix epoch.
+    pub modified: u64,
+}
+
+// Failed request, answered with the job error of the failure
+struct ApiError(StatusCode, WorkspaceError);
+
+impl From<WorkspaceError> for ApiError {
+    fn from(error: WorkspaceError) -> Self {
+        let status = match error {
+            WorkspaceError::InvalidParams(_) => StatusCode::BAD_REQUEST,
+            WorkspaceError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            WorkspaceError::QuotaExceeded(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        Self(status, error)
+    }
+}
+
+impl From<BoxError> for ApiError {
+    fn from(error: BoxError) -> Self {
+        WorkspaceError::from(error).into()
+    }
+}
+
+impl From<std::io::Error> for ApiError {
+    fn from(error: std::io::Error) -> Self {
+        if error.kind() == std::io::ErrorKind::NotFound {
+            return not_found("No such file or directory");
+        }
+        WorkspaceError::Internal(error.to_string()).into()
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, axum::Json(JobError::from(&self.1))).into_response()
+    }
+}
+
+fn not_found(message: &str) -> ApiError {
+    ApiError(
+        StatusCode::NOT_FOUND,
+        WorkspaceError::InvalidParams(message.to_string()),
+    )
+}
+
+fn invalid_path(path: &str) -> ApiError {
+    WorkspaceError::InvalidParams(format!("Invalid path {}", path)).into()
+}
+
+/// Serve the file API on `port`, over TLS when the operator has it enabled.
+pub async fn spawn_file_api(ctx: MyContext, port: u16) -> Result<(), BoxError> {
+    let router = router(ctx.clone());
+    let Some(tls) = &ctx.tls else {
+        let listener = TcpListener::bind(("0.0.0.0", port)).await?;
+        tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, router).await {
+                tracing::error!("File API server failed: {}", e);
+            }
+        });
+        return Ok(());
+    };
+
+    // Terminate TLS like workspace endpoints do, in front of a loopback listener
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+    let upstream = listener.local_addr()?;
+    let acceptor = tls.workspace_tls(&ctx.config.public_host)?.acceptor;
+    let proxy = crate::TlsProxy::spawn(acceptor, port, upstream).await?;
+    tokio::spawn(async move {
+        // The proxy is stopped when dropped
+        let _proxy = proxy;
+        if let Err(e) = axum::serve(listener, router).await {
+            tracing::error!("File API server failed: {}", e);
+        }
+    });
+    Ok(())
+}
+
+pub fn router(ctx: MyContext) -> Router {
+    Router::new()
+        .route(
+            "/workspaces/{service_id}/files",
+            get(read).put(write).delete(remove),
+        )
+        .layer(DefaultBodyLimit::max(MAX_UPLOAD_SIZE))
+        .with_state(ctx)
+}
+
+/// Message a request is signed over, see the module documentation.
+pub fn signed_message(
+    method: &str,
+    service_id: u64,
+    path: &str,
+    timestamp: u64,
+    body: &[u8],
+) -> Vec<u8> {
+    let body_hash: String = Sha256::digest(body)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!(
+        "{}\n{}\n{}\n{}\n{}",
+        method, service_id, path, timestamp, body_hash
+    )
+    .into_bytes()
+}
+
+// Check the request is signed by the owner of the live workspace, returning its directory
+async fn authenticate(
+    ctx: &MyContext,
+    service_id: u64,
+    method: &str,
+    path: &str,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Result<PathBuf, ApiError> {
+    let record = match ctx.workspaces.get(service_id) {
+        Some(record) if record.deleted_at.is_none() => record,
+        _ => {
+            return Err(not_found(&format!(
+                "Workspace {} isn't running",
+                service_id
+            )));
+        }
+    };
+
+    verify_owner(&record.owner_key()?, headers, |timestamp| {
+        signed_message(method, service_id, path, timestamp, body)
+    })?;
+
+    storage::workspace_dir(ctx, service_id)
+        .await?
+        .ok_or_else(|| not_found("This workspace keeps no data"))
+}
+
+// Target of a request, as an open directory and the name of the target in it (`None` for the
+// directory itself). Holding the directory open pins it even if its path is swapped for a
+// link meanwhile.
+struct Resolved {
+    dir: OwnedFd,
+    name: Option<OsString>,
+}
+
+impl Resolved {
+    // Path the kernel resolves through the open directory rather than its former path
+    fn dir_path(&self) -> PathBuf {
+        PathBuf::from(format!("/proc/self/fd/{}", self.dir.as_raw_fd()))
+    }
+
+    fn path(&self) -> PathBuf {
+        let dir = self.dir_path();
+        match &self.name {
+            Some(name) => dir.join(name),
+            None => dir,
+        }
+    }
+}
+
+// Open the directory at `path`, failing on links rather than following them
+fn open_dir(path: &Path) -> std::io::Result<OwnedFd> {
+    let dir = std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC)
+        .open(path)?;
+    Ok(dir.into())
+}
+
+// Whether `error` comes from meeting a link or a file where a directory was expected
+fn is_not_a_directory(error: &std::io::Error) -> bool {
+    matches!(error.raw_os_error(), Some(libc::ELOOP | libc::ENOTDIR))
+}
+
+// Resolve `path` under `workspace_dir`, going down one open directory at a time so it may
+// only go through real directories. Missing directories are created for `owner` when
+// `create` is set.
+fn resolve(
+    workspace_dir: &Path,
+    path: &str,
+    create: bool,
+    owner: Option<DataOwner>,
+) -> Result<Resolved, ApiError> {
+    let components = Path::new(path)
+        .components()
+        .filter(|component| *component != Component::CurDir)
+        .map(|component| match component {
+            Component::Normal(name) => Ok(name.to_os_string()),
+            _ => Err(invalid_path(path)),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut resolved = Resolved {
+        dir: open_dir(workspace_dir)?,
+        name: None,
+    };
+    let Some((name, parents)) = components.split_last() else {
+        return Ok(resolved);
+    };
+    for parent in parents {
+        let parent = resolved.dir_path().join(parent);
+        resolved.dir = match open_dir(&parent) {
+            Ok(dir) => dir,
+            Err(e) if create && e.kind() == std::io::ErrorKind::NotFound => {
+                std::fs::create_dir(&parent)?;
+                let dir = open_dir(&parent).map_err(|e| match is_not_a_directory(&e) {
+                    true => invalid_path(path),
+                    false => e.into(),
+                })?;
+                chown_workspace_fd(&dir, owner)?;
+                dir
+            }
+            Err(e) if is_not_a_directory(&e) => return Err(invalid_path(path)),
+            Err(e) => return Err(e.into()),
+        };
+    }
+    resolved.name = Some(name.clone());
+    Ok(resolved)
+}
+
+async fn read(
+    State(ctx): State<MyContext>,
+    UrlPath(service_id): UrlPath<u64>,
+    Query(query): Query<FileQuery>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let workspace_dir = authenticate(&ctx, service_id, "GET", &query.path, &headers, &[]).await?;
+    // Restores swap the workspace directory, the file is opened before they can
+    let _guard = ctx.locks.lock(service_id).await;
+    let target = resolve(&workspace_dir, &query.path, false, None)?;
+    if target.name.is_none() {
+        return Ok(axum::Json(list(&target.path())?).into_response());
+    }
+
+    // Links fail to open, and the file checked is the very one served. Opening doesn't
+    // block on a fifo the workspace may have left there.
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW | libc::O_NONBLOCK | libc::O_CLOEXEC)
+        .open(target.path())
+        .map_err(|e| match e.raw_os_error() {
+            Some(libc::ELOOP) => invalid_path(&query.path),
+            _ => e.into(),
+        })?;
+    let metadata = file.metadata()?;
+    if metadata.is_dir() {
+        let dir = Resolved {
+            dir: file.into(),
+            name: None,
+        };
+        return Ok(axum::Json(list(&dir.path())?).into_response());
+    }
+    if !metadata.is_file() {
+        return Err(invalid_path(&query.path));
+    }
+
+    let file = tokio::fs::File::from_std(file);
+    let chunks = futures_util::stream::try_unfold(file, |mut file| async move {
+        let mut chunk = vec![0; 64 * 1024];
+        let read = file.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok::<_, std::io::Error>(None);
+        }
+        chunk.truncate(read);
+        Ok(Some((Bytes::from(chunk), file)))
+    });
+    Ok((
+        [
+            (axum::http::header::CONTENT_TYPE, "application/octet-stream"),
+            (
+                axum::http::header::CONTENT_LENGTH,
+                &metadata.len().to_string(),
+            ),
+        ],
+        Body::from_stream(chunks),
+    )
+        .into_response())
+}
+
+fn list(dir: &Path) -> Result<Vec<FileEntry>, ApiError> {
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.path().symlink_metadata()?;
+        let kind = if metadata.is_symlink() {
+            FileKind::Symlink
+        } else if metadata.is_dir() {
+            FileKind::Directory
+        } else {
+            FileKind::File
+        };
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |modified| modified.as_secs());
+        entries.push(FileEntry {
+            name: entry.file_name().to_string_lossy().into_owned(),
+            kind,
+            size: metadata.len(),
+            modified,
+        });
+    }
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(entries)
+}
+
+async fn write(
+    State(ctx): State<MyContext>,
+    UrlPath(service_id): UrlPath<u64>,
+    Query(query): Query<FileQuery>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<StatusCode, ApiError> {
+    let workspace_dir = authenticate(&ctx, service_id, "PUT", &query.path, &headers, &body).await?;
+    let _guard = ctx.locks.lock(service_id).await;
+    let record = ctx
+        .workspaces
+        .get(service_id)
+        .ok_or_else(|| not_found(&format!("Workspace {} isn't running", service_id)))?;
+
+    let owner = ctx.config.security.data_owner(WORKSPACE_IMAGE);
+    let target = resolve(&workspace_dir, &query.path, true, owner)?;
+    let Some(name) = target
+        .name
+        .as_ref()
+        .map(|name| name.to_string_lossy().into_owned())
+    else {
+        return Err(invalid_path(&query.path));
+    };
+    let replaced = match target.path().symlink_metadata() {
+        Ok(metadata) if metadata.is_dir() => return Err(invalid_path(&query.path)),
+        Ok(metadata) => metadata.len(),
+        Err(_) => 0,
+    };
+
+    // Snapshots count against the same limit as the workspace itself
+    let snapshots = match &ctx.env.data_dir {
+        Some(data_dir) => SnapshotStore::new(data_dir, service_id).used()?,
+        None => 0,
+    };
+    let used = (dir_size(&workspace_dir)? + snapshots).saturating_sub(replaced);
+    let limit = record.tier.storage_limit();
+    if used + body.len() as u64 > limit {
+        return Err(WorkspaceError::QuotaExceeded(format!(
+            "workspace {} uses {} of {} bytes, the upload takes {}",
+            service_id,
+            used,
+            limit,
+            body.len()
+        ))
+        .into());
+    }
+
+    // Write next to the target then rename, so a failed upload never leaves half a file. The
+    // workspace can write to the same directory, the upload gets a name it can't predict and
+    // is only ever a new file.
+    let mut suffix = [0u8; 8];
+    rand::rngs::OsRng.fill_bytes(&mut suffix);
+    let suffix: String = suffix.iter().map(|byte| format!("{:02x}", byte)).collect();
+    let upload = target
+        .dir_path()
+        .join(format!(".{}.{}.upload", name, suffix));
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .custom_flags(libc::O_NOFOLLOW | libc::O_CLOEXEC)
+        .open(&upload)?;
+    let written = async {
+        chown_workspace_fd(&file, owner)?;
+        let mut file = tokio::fs::File::from_std(file);
+        file.write_all(&body).await?;
+        file.flush().await?;
+        tokio::fs::rename(&upload, target.path()).await
+    };
+    if let Err(e) = written.await {
+        let _ = tokio::fs::remove_file(&upload).await;
+        return Err(e.into());
+    }
+    tracing::info!(
+        "Uploaded {} bytes to {} of workspace {}",
+        body.len(),
+        query.path,
+        service_id
+    );
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn remove(
+    State(ctx): State<MyContext>,
+    UrlPath(service_id): UrlPath<u64>,
+    Query(query): Query<FileQuery>,
+    headers: HeaderMap,
+) -> Result<StatusCode, ApiError> {
+    let workspace_dir =
+        authenticate(&ctx, service_id, "DELETE", &query.path, &headers, &[]).await?;
+    let _guard = ctx.locks.lock(service_id).await;
+    let target = resolve(&workspace_dir, &query.path, false, None)?;
+    if target.name.is_none() {
+        return Err(WorkspaceError::InvalidParams(
+            "The workspace directory itself can't be deleted".to_string(),
+        )
+        .into());
+    }
+
+    // Links are removed themselves, never what they point to
+    let target = target.path();
+    if target.symlink_metadata()?.is_dir() {
+        tokio::fs::remove_dir_all(&target).await?;
+    } else {
+        tokio::fs::remove_file(&target).await?;
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::fake::TestEnv;
+    use crate::{CreateWorkspaceParams, create_workspace};
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD as BASE64;
+    use blueprint_sdk::crypto::sp_core::{SpSr25519, SpSr25519Pair};
+    use blueprint_sdk::crypto::{BytesEncoding, KeyType};
+    use blueprint_sdk::extract::Context;
+    use blueprint_sdk::tangle::extract::{CallId, ServiceId, TangleArg};
+    use std::time::SystemTime;
+
+    // Create workspace 1 for `owner` and serve the file API of `env`, returning its URL
+    async fn serve(env: &TestEnv, owner: &SpSr25519Pair) -> String {
+        let created = create_workspace(
+            Context(env.ctx.clone()),
+            ServiceId(1),
+            CallId(1),
+            TangleArg(CreateWorkspaceParams {
+                owner_public_key: owner.public(),
+                workspace_name: "files".to_string(),
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(created.0.error.code, 0);
+
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let url = format!(
+            "http://{}/workspaces/1/files",
+            listener.local_addr().unwrap()
+        );
+        tokio::spawn(axum::serve(listener, router(env.ctx.clone())).into_future());
+        url
+    }
+
+    async fn send(
+        url: &str,
+        signer: &mut SpSr25519Pair,
+        method: &str,
+        path: &str,
+        body: &[u8],
+    ) -> reqwest::Response {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let message = signed_message(method, 1, path, timestamp, body);
+        let signature = SpSr25519::sign_with_secret(signer, &message).unwrap();
+        reqwest::Client::new()
+            .request(method.parse::<reqwest::Method>().unwrap(), url)
+            .query(&[("path", path)])
+            .header(TIMESTAMP_HEADER, timestamp.to_string())
+            .header(
+                "authorization",
+                format!("Sr25519 {}", BASE64.encode(signature.to_bytes())),
+            )
+            .body(body.to_vec())
+            .send()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn it_serves_files_to_the_owner_only() {
+        let env = TestEnv::new();
+        let mut owner = SpSr25519::generate_with_seed(Some(&[1u8; 32])).unwrap();
+        let mut stranger = SpSr25519::generate_with_seed(Some(&[2u8; 32])).unwrap();
+        let url = serve(&env, &owner).await;
+
+        let uploaded = send(&url, &mut owner, "PUT", "config/settings.json", b"{}").await;
+        assert_eq!(uploaded.status(), StatusCode::NO_CONTENT);
+        let downloaded = send(&url, &mut owner, "GET", "config/settings.json", b"").await;
+        assert_eq!(downloaded.text().await.unwrap(), "{}");
+        let listed = send(&url, &mut owner, "GET", "config", b"").await;
+        let entries: Vec<FileEntry> = serde_json::from_str(&listed.text().await.unwrap()).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, "settings.json");
+        assert_eq!(entries[0].size, 2);
+
+        let stolen = send(&url, &mut stranger, "GET", "config/settings.json", b"").await;
+        assert_eq!(stolen.status(), StatusCode::UNAUTHORIZED);
+        let escaped = send(&url, &mut owner, "GET", "../../state", b"").await;
+        assert_eq!(escaped.status(), StatusCode::BAD_REQUEST);
+
+        std::os::unix::fs::symlink("/etc", env.root().join("workspaces/1/etc")).unwrap();
+        let followed = send(&url, &mut owner, "PUT", "etc/evil", b"").await;
+        assert_eq!(followed.status(), StatusCode::BAD_REQUEST);
+
+        let deleted = send(&url, &mut owner, "DELETE", "config", b"").await;
+        assert_eq!(deleted.status(), StatusCode::NO_CONTENT);
+        assert!(!env.root().join("workspaces/1/config").exists());
+    }
+
+    #[tokio::test]
+    async fn it_never_follows_links_planted_by_the_workspace() {
+        let env = TestEnv::new();
+        let mut owner = SpSr25519::generate_with_seed(Some(&[1u8; 32])).unwrap();
+        let url = serve(&env, &owner).await;
+        let workspace_dir = env.root().join("workspaces/1");
+        let outside = env.root().join("host-file");
+        std::fs::write(&outside, "host").unwrap();
+
+        // Links where uploads used to be written, and in place of a file to read
+        std::os::unix::fs::symlink(&outside, workspace_dir.join(".x.upload")).unwrap();
+        std::os::unix::fs::symlink(&outside, workspace_dir.join("leak")).unwrap();
+
+        let uploaded = send(&url, &mut owner, "PUT", "x", b"tenant").await;
+        assert_eq!(uploaded.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            std::fs::read_to_string(workspace_dir.join("x")).unwrap(),
+            "tenant"
+        );
+        assert_eq!(std::fs::read_to_string(&outside).unwrap(), "host");
+
+        let leaked = send(&url, &mut owner, "GET", "leak", b"").await;
+        assert_eq!(leaked.status(), StatusCode::BAD_REQUEST);
+
+        // Uploading over a link replaces the link, not what it points to
+        let replaced = send(&url, &mut owner, "PUT", "leak", b"tenant").await;
+        assert_eq!(replaced.status(), StatusCode::NO_CONTENT);
+        assert!(!workspace_dir.join("leak").is_symlink());
+        assert_eq!(std::fs::read_to_string(&outside).unwrap(), "host");
+
+        // No upload is left behind next to the files
+        let listed = send(&url, &mut owner, "GET", "", b"").await;
+        let entries: Vec<FileEntry> = serde_json::from_str(&listed.text().await.unwrap()).unwrap();
+        let names: Vec<_> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, [".x.upload", "leak", "x"]);
+    }
+}
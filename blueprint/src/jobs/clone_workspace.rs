@@ -0,0 +1,365 @@
+use super::create_workspace::create;
+use crate::archive::{pack, unpack};
+use crate::backend::ContainerState;
+use crate::error::{JobError, WorkspaceError};
+use crate::snapshot::dir_size;
+use crate::storage;
+use crate::{CreateWorkspaceParams, CreateWorkspaceResult, MyContext, ResourceTier};
+use blueprint_sdk::crypto::BytesEncoding;
+use blueprint_sdk::crypto::sp_core::SpSr25519Public;
+use blueprint_sdk::extract::Context;
+use blueprint_sdk::tangle::extract::{CallId, Caller, ServiceId, TangleArg, TangleResult};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// Input parameters for the clone workspace job
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct CloneWorkspaceParams {
+    /// Service whose workspace is cloned, it must run on this operator.
+    pub source_service_id: u64,
+    /// Substrate Sr25519 Public Key of the clone's owner.
+    pub owner_public_key: SpSr25519Public,
+    pub workspace_name: String,
+    /// Tier of the clone, the source's when unset.
+    #[serde(default)]
+    pub tier: Option<ResourceTier>,
+}
+
+impl Default for CloneWorkspaceParams {
+    fn default() -> Self {
+        Self {
+            source_service_id: Default::default(),
+            owner_public_key: SpSr25519Public::from_bytes(&[0u8; 32]).unwrap(),
+            workspace_name: Default::default(),
+            tier: Default::default(),
+        }
+    }
+}
+
+/// Create this service's workspace from a copy of another workspace's data, with the same
+/// transport, command, egress policy and image.
+///
+/// Only the owner of the source workspace may clone it, which is paused while its data is
+/// copied.
+#[blueprint_sdk::macros::debug_job]
+pub async fn clone_workspace(
+    Context(ctx): Context<MyContext>,
+    ServiceId(service_id): ServiceId,
+    CallId(call_id): CallId,
+    Caller(caller): Caller,
+    TangleArg(params): TangleArg<CloneWorkspaceParams>,
+) -> Result<TangleResult<CreateWorkspaceResult>, BoxError> {
+    let source_id = params.source_service_id;
+    if source_id == service_id {
+        let error = WorkspaceError::InvalidParams("A workspace can't be cloned onto itself".into());
+        return Ok(TangleResult(CreateWorkspaceResult {
+            error: JobError::from(&error),
+            ..Default::default()
+        }));
+    }
+
+    // Lock both workspaces in the same order as any other clone would
+    let _first = ctx.locks.lock(service_id.min(source_id)).await;
+    let _second = ctx.locks.lock(service_id.max(source_id)).await;
+    match clone(&ctx, service_id, call_id, caller.as_ref(), &params).await {
+        Ok(result) => Ok(TangleResult(result)),
+        Err(e) => {
+            // Report the failure on chain instead of dropping the result
+            let error = WorkspaceError::from(e);
+            tracing::error!(
+                "Failed to clone workspace {} into {}: {}",
+                source_id,
+                service_id,
+                error
+            );
+            Ok(TangleResult(CreateWorkspaceResult {
+                error: JobError::from(&error),
+                ..Default::default()
+            }))
+        }
+    }
+}
+
+async fn clone(
+    ctx: &MyContext,
+    service_id: u64,
+    call_id: u64,
+    caller: &[u8],
+    params: &CloneWorkspaceParams,
+) -> Result<CreateWorkspaceResult, BoxError> {
+    let source_id = params.source_service_id;
+    if !storage::is_persistent(ctx) {
+        return Err(WorkspaceError::InvalidParams(
+            "This operator doesn't keep workspace data".to_string(),
+        )
+        .into());
+    }
+    let source = match ctx.workspaces.get(source_id) {
+        Some(record) if record.deleted_at.is_none() => record,
+        _ => {
+            return Err(WorkspaceError::InvalidParams(format!(
+                "Workspace {} isn't running",
+                source_id
+            ))
+            .into());
+        }
+    };
+
+    let mut create_params = CreateWorkspaceParams::from_record(&source)?;
+    if create_params.owner_public_key.to_bytes() != caller {
+        return Err(WorkspaceError::Unauthorized(format!(
+            "Only the owner of workspace {} may clone it",
+            source_id
+        ))
+        .into());
+    }
+    create_params.owner_public_key = params.owner_public_key;
+    create_params.workspace_name = params.workspace_name.clone();
+    if let Some(tier) = &params.tier {
+        create_params.tier = tier.clone();
+    }
+
+    // A redelivered call finds the copy in place, `create` tells it apart from a clash
+    let copied = ctx.workspaces.get(service_id).is_none();
+    let workspace_dir = match storage::workspace_dir(ctx, service_id).await? {
+        // Data copied by an earlier delivery of this call that didn't get to finish
+        Some(dir) if copied && copied_by(&dir).await == Some(call_id) => Some(dir),
+        Some(_) if copied => {
+            return Err(WorkspaceError::InvalidParams(format!(
+                "Workspace {} still has data, destroy it before cloning into it",
+                service_id
+            ))
+            .into());
+        }
+        Some(dir) => Some(dir),
+        None if copied => {
+            let source_dir = storage::workspace_dir(ctx, source_id)
+                .await?
+                .ok_or_else(|| {
+                    WorkspaceError::InvalidParams(format!(
+                        "Workspace {} has no data to clone",
+                        source_id
+                    ))
+                })?;
+            let (size, limit) = (dir_size(&source_dir)?, create_params.tier.storage_limit());
+            if size > limit {
+                return Err(WorkspaceError::QuotaExceeded(format!(
+                    "workspace {} holds {} bytes, the clone's tier allows {}",
+                    source_id, size, limit
+                ))
+                .into());
+            }
+            let workspace_dir =
+                storage::create_workspace_dir(ctx, service_id, &create_params.tier).await?;
+            // Tag the copy before making it, so a redelivery knows the data is its own
+            tokio::fs::write(clone_marker(&workspace_dir), call_id.to_string()).await?;
+            if let Err(e) = copy_paused(ctx, source_id, &source_dir, &workspace_dir).await {
+                remove_clone_marker(&workspace_dir).await;
+                storage::remove_workspace_dir(ctx, service_id).await?;
+                return Err(e);
+            }
+            Some(workspace_dir)
+        }
+        None => None,
+    };
+
+    match create(ctx, service_id, call_id, &create_params, source.image).await {
+        Ok(result) => {
+            if let Some(dir) = &workspace_dir {
+                remove_clone_marker(dir).await;
+            }
+            tracing::info!("Cloned workspace {} into {}", source_id, service_id);
+            Ok(result)
+        }
+        Err(e) => {
+            // The data was only copied for this attempt
+            if copied {
+                if let Some(dir) = &workspace_dir {
+                    remove_clone_marker(dir).await;
+                }
+                if let Err(e) = storage::remove_workspace_dir(ctx, service_id).await {
+                    tracing::warn!("Failed to remove the copied data: {}", e);
+                }
+            }
+            Err(e)
+        }
+    }
+}
+
+// File next to the data of a clone naming the call that copied it, until the clone is created
+fn clone_marker(workspace_dir: &std::path::Path) -> std::path::PathBuf {
+    workspace_dir.with_extension("clone")
+}
+
+// Call that copied the data in `workspace_dir`, if a clone is still being created from it
+async fn copied_by(workspace_dir: &std::path::Path) -> Option<u64> {
+    let marker = tokio::fs::read_to_string(clone_marker(workspace_dir))
+        .await
+        .ok()?;
+    marker.trim().parse().ok()
+}
+
+async fn remove_clone_marker(workspace_dir: &std::path::Path) {
+    let marker = clone_marker(workspace_dir);
+    if let Err(e) = tokio::fs::remove_file(&marker).await
+        && e.kind() != std::io::ErrorKind::NotFound
+    {
+        tracing::warn!("Failed to remove {}: {}", marker.display(), e);
+    }
+}
+
+// Copy the data with the source's container paused, so the copy doesn't catch files it is in
+// the middle of writing
+async fn copy_paused(
+    ctx: &MyContext,
+    source_id: u64,
+    source_dir: &std::path::Path,
+    workspace_dir: &std::path::Path,
+) -> Result<(), BoxError> {
+    let running = ctx
+        .backend
+        .find_workspace(ctx.blueprint_id(), source_id)
+        .await?
+        .filter(|container| container.state == ContainerState::Running);
+    if let Some(container) = &running {
+        ctx.backend.pause(&container.id).await?;
+    }
+    let copied = copy_data(source_dir, workspace_dir).await;
+    if let Some(container) = &running {
+        ctx.backend.unpause(&container.id).await?;
+    }
+    copied
+}
+
+// Copy through an archive, which keeps links, permissions and ownership as they are
+async fn copy_data(
+    source_dir: &std::path::Path,
+    workspace_dir: &std::path::Path,
+) -> Result<(), BoxError> {
+    let archive = workspace_dir.with_extension("clone.tar.gz");
+    let copied = match pack(source_dir, &archive).await {
+        Ok(_) => unpack(&archive, workspace_dir).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = tokio::fs::remove_file(&archive).await
+        && e.kind() != std::io::ErrorKind::NotFound
+    {
+        tracing::warn!("Failed to remove {}: {}", archive.display(), e);
+    }
+    copied
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::WorkspaceBackend;
+    use crate::backend::fake::TestEnv;
+    use crate::create_workspace;
+
+    #[tokio::test]
+    async fn it_clones_data_for_the_owner_only() {
+        let env = TestEnv::new();
+        let (ctx, root) = (&env.ctx, env.root());
+
+        let created = create_workspace(
+            Context(ctx.clone()),
+            ServiceId(1),
+            CallId(1),
+            TangleArg(CreateWorkspaceParams {
+                workspace_name: "team".to_string(),
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(created.0.error.code, 0);
+        std::fs::write(root.join("workspaces/1/config.json"), "{}").unwrap();
+
+        let clone = |caller: [u8; 32]| {
+            clone_workspace(
+                Context(ctx.clone()),
+                ServiceId(2),
+                CallId(5),
+                Caller(caller.into()),
+                TangleArg(CloneWorkspaceParams {
+                    source_service_id: 1,
+                    owner_public_key: SpSr25519Public::from_bytes(&[7u8; 32]).unwrap(),
+                    workspace_name: "fork".to_string(),
+                    tier: Some(ResourceTier::Small),
+                }),
+            )
+        };
+        assert_eq!(clone([9u8; 32]).await.unwrap().0.error.code, 2);
+        assert!(ctx.workspaces.get(2).is_none());
+
+        let cloned = clone([0u8; 32]).await.unwrap().0;
+        assert_eq!(cloned.error.code, 0);
+        assert_eq!(
+            std::fs::read_to_string(root.join("workspaces/2/config.json")).unwrap(),
+            "{}"
+        );
+        let record = ctx.workspaces.get(2).unwrap();
+        assert_eq!(record.workspace_name, "fork");
+        assert!(matches!(record.tier, ResourceTier::Small));
+
+        // The source was only paused while its data was copied
+        let source = env
+            .backend
+            .find_workspace(ctx.blueprint_id(), 1)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(source.state, ContainerState::Running);
+        let events = env.backend.events();
+        let position = |event: &str| {
+            let event = format!("{} {}", event, source.id);
+            events.iter().position(|other| *other == event)
+        };
+        assert!(position("pause").is_some());
+        assert!(position("pause") < position("unpause"));
+    }
+
+    #[tokio::test]
+    async fn it_reuses_data_copied_by_a_redelivered_call() {
+        let env = TestEnv::new();
+        let (ctx, root) = (&env.ctx, env.root());
+        let created = create_workspace(
+            Context(ctx.clone()),
+            ServiceId(1),
+            CallId(1),
+            TangleArg(CreateWorkspaceParams {
+                workspace_name: "team".to_string(),
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(created.0.error.code, 0);
+
+        // An earlier delivery of call 5 copied the data, then the operator went down
+        std::fs::create_dir_all(root.join("workspaces/2")).unwrap();
+        std::fs::write(root.join("workspaces/2/notes.md"), "copied").unwrap();
+        std::fs::write(root.join("workspaces/2.clone"), "5").unwrap();
+
+        let clone = |call_id| {
+            clone_workspace(
+                Context(ctx.clone()),
+                ServiceId(2),
+                CallId(call_id),
+                Caller([0u8; 32].into()),
+                TangleArg(CloneWorkspaceParams {
+                    source_service_id: 1,
+                    workspace_name: "fork".to_string(),
+                    ..Default::default()
+                }),
+            )
+        };
+        assert_eq!(clone(6).await.unwrap().0.error.code, 1);
+        assert_eq!(clone(5).await.unwrap().0.error.code, 0);
+        assert_eq!(
+            std::fs::read_to_string(root.join("workspaces/2/notes.md")).unwrap(),
+            "copied"
+        );
+        assert!(!root.join("workspaces/2.clone").exists());
+    }
+}
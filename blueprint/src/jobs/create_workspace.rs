@@ -1,17 +1,28 @@
-use crate::MyContext;
+use crate::backend::{
+    ContainerInfo, ContainerSpec, ContainerState, HealthState, PortMapping, WorkspaceBackend,
+    workspace_container_name,
+};
+use crate::egress::{EgressMode, EgressPolicy, apply_egress_policy, remove_egress_policy};
+use crate::error::{JobError, WorkspaceError};
+use crate::labels::{Labels, WorkspaceLabels};
+use crate::rollback::Rollback;
+use crate::security::{DataOwner, chown_workspace_dir};
+use crate::seed::{WorkspaceSeed, unpack_seed};
+use crate::state::WorkspaceRecord;
+use crate::storage;
+use crate::{MyContext, StdioBridge, TlsProxy, WorkspaceBridges, WorkspaceTls, WsBridge};
 use blueprint_sdk::crypto::BytesEncoding;
 use blueprint_sdk::crypto::sp_core::SpSr25519Public;
 use blueprint_sdk::extract::Context;
 use blueprint_sdk::std::{Rng, rand};
-use blueprint_sdk::tangle::extract::{ServiceId, TangleArg, TangleResult};
-use docktopus::bollard::Docker;
-use docktopus::bollard::container::{InspectContainerOptions, RemoveContainerOptions};
-use docktopus::bollard::models::PortBinding;
-use docktopus::bollard::secret::{ContainerStateStatusEnum, HealthStatusEnum};
-use docktopus::container::Container;
-use std::collections::HashMap;
+use blueprint_sdk::tangle::extract::{CallId, ServiceId, TangleArg, TangleResult};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::ops::Range;
 use std::sync::Arc;
 
+/// Image every workspace container runs.
+pub const WORKSPACE_IMAGE: &str = "tangle-mcp:0.1.0";
+
 // Resource tiers for container allocation
 #[derive(Default, Debug, Clone, serde::Deserialize, serde::Serialize)]
 #[serde(rename_all = "lowercase")]
@@ -22,6 +33,7 @@ pub enum ResourceTier {
     Large,
 }
 
+#[allow(dead_code)]
 impl ResourceTier {
     fn cpu_limit(&self) -> f64 {
         match self {
@@ -39,7 +51,8 @@ impl ResourceTier {
         }
     }
 
-    fn storage_limit(&self) -> u64 {
+    /// Bytes of storage a workspace of this tier may use, snapshots included.
+    pub fn storage_limit(&self) -> u64 {
         match self {
             ResourceTier::Small => 5 * 1024 * 1024 * 1024,   // 5GB
             ResourceTier::Medium => 10 * 1024 * 1024 * 1024, // 10GB
@@ -48,6 +61,35 @@ impl ResourceTier {
     }
 }
 
+// MCP transports a workspace can be served over
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum McpTransport {
+    /// Legacy HTTP+SSE transport (`GET /sse` plus a POST message endpoint).
+    #[default]
+    Sse,
+    /// Streamable HTTP transport (single `/mcp` endpoint, POST with optional SSE
+    /// streaming and `Mcp-Session-Id` sessions).
+    StreamableHttp,
+}
+
+impl McpTransport {
+    fn path(&self) -> &'static str {
+        match self {
+            McpTransport::Sse => "/sse",
+            McpTransport::StreamableHttp => "/mcp",
+        }
+    }
+
+    // Value passed to the MCP server through the `MCP_TRANSPORT` env variable
+    fn env_value(&self) -> &'static str {
+        match self {
+            McpTransport::Sse => "sse",
+            McpTransport::StreamableHttp => "streamable-http",
+        }
+    }
+}
+
 // Input parameters for create workspace job
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 pub struct CreateWorkspaceParams {
@@ -55,6 +97,35 @@ pub struct CreateWorkspaceParams {
     pub owner_public_key: SpSr25519Public,
     pub tier: ResourceTier,
     pub workspace_name: String,
+    /// Transport the MCP server is exposed over, defaults to SSE.
+    #[serde(default)]
+    pub transport: McpTransport,
+    /// Command of a stdio-only MCP server (e.g. `["npx", "-y", "@modelcontextprotocol/server-memory"]`).
+    ///
+    /// When set, the container runs this command and the operator serves its stdin/stdout
+    /// over `transport` instead of expecting an HTTP server on port 3000.
+    #[serde(default)]
+    pub stdio_command: Vec<String>,
+    /// Outbound traffic policy, defaults to the operator's. Can only be stricter than it.
+    #[serde(default)]
+    pub egress: Option<EgressPolicy>,
+    /// Initial contents of `/blueprint`, unpacked before the container starts. Ignored when
+    /// the workspace already has data.
+    #[serde(default)]
+    pub seed: Option<WorkspaceSeed>,
+}
+
+// Output of the create workspace job
+#[derive(Default, Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct CreateWorkspaceResult {
+    /// MCP endpoint URL for the requested transport.
+    pub url: String,
+    /// WebSocket URL of the operator-side bridge to the same MCP server.
+    pub ws_url: String,
+    /// SHA-256 fingerprint of the TLS certificate served for both URLs, empty without TLS.
+    pub tls_fingerprint: String,
+    /// Why the workspace couldn't be created, code `0` on success.
+    pub error: JobError,
 }
 
 impl Default for CreateWorkspaceParams {
@@ -63,16 +134,56 @@ impl Default for CreateWorkspaceParams {
             owner_public_key: SpSr25519Public::from_bytes(&[0u8; 32]).unwrap(),
             tier: Default::default(),
             workspace_name: Default::default(),
+            transport: Default::default(),
+            stdio_command: Default::default(),
+            egress: Default::default(),
+            seed: Default::default(),
+        }
+    }
+}
+
+impl CreateWorkspaceParams {
+    /// Parameters the workspace of `record` was created with.
+    pub(super) fn from_record(
+        record: &WorkspaceRecord,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(Self {
+            owner_public_key: record.owner_key()?,
+            tier: record.tier.clone(),
+            workspace_name: record.workspace_name.clone(),
+            transport: record.transport,
+            stdio_command: record.stdio_command.clone(),
+            egress: Some(record.egress.clone()),
+            seed: None,
+        })
+    }
+}
+
+// Pick a random free host port in `range`, retrying on collisions
+fn allocate_port(range: Range<u16>) -> Result<u16, WorkspaceError> {
+    let mut rng = rand::rngs::OsRng;
+    for _ in 0..10 {
+        let port = rng.gen_range(range.clone());
+        if std::net::TcpListener::bind((Ipv4Addr::UNSPECIFIED, port)).is_ok() {
+            return Ok(port);
         }
     }
+    Err(WorkspaceError::PortExhausted)
 }
 
 // Project container configuration
 struct WorkspaceContainer {
-    container: Container,
+    id: String,
     service_id: u64,
     port: u16,
-    docker: Arc<Docker>,
+    // Where the operator reaches the MCP server, its host port (equal to `port` unless TLS is
+    // terminated) until `resolve_upstream` finds a better route
+    upstream: SocketAddr,
+    network: String,
+    transport: McpTransport,
+    stdio: Option<StdioBridge>,
+    tls: Option<WorkspaceTls>,
+    backend: Arc<dyn WorkspaceBackend>,
 }
 
 impl WorkspaceContainer {
@@ -80,63 +191,158 @@ impl WorkspaceContainer {
         ctx: &MyContext,
         service_id: u64,
         params: &CreateWorkspaceParams,
+        egress: &EgressPolicy,
+        labels: &Labels,
+        image: Option<&str>,
+        rollback: &mut Rollback,
     ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
-        let mut rng = rand::rngs::OsRng;
         // Allocate a random port between 10000-20000
-        let port = rng.gen_range(10000..20000);
-
-        // Set up port bindings
-        let mut port_bindings = HashMap::new();
-        port_bindings.insert(
-            "3000/tcp".to_string(),
-            Some(vec![PortBinding {
-                host_ip: Some("0.0.0.0".into()),
-                host_port: Some(port.to_string()),
-            }]),
-        );
+        let port = allocate_port(10000..20000)?;
 
-        // Set up environment variables
-        let env = vec![
-            format!("OWNER_PUBLIC_KEY={}", params.owner_public_key),
-            "PORT=3000".to_string(),
-        ];
+        // With TLS the server only listens locally and the operator terminates TLS on `port`
+        let tls = match &ctx.tls {
+            Some(provider) => Some(provider.workspace_tls(&ctx.config.public_host)?),
+            None => None,
+        };
+        let (bind_ip, upstream_port) = if tls.is_some() {
+            (Ipv4Addr::LOCALHOST, allocate_port(30000..40000)?)
+        } else {
+            (Ipv4Addr::UNSPECIFIED, port)
+        };
 
-        // Create a new container - first create with the image
-        let mut container = Container::new(ctx.docker.clone(), "tangle-mcp:0.1.0");
+        let stdio = !params.stdio_command.is_empty();
 
-        // Add environment variables
-        container = container.env(&env);
+        // Set up environment variables
+        let mut env = vec![format!("OWNER_PUBLIC_KEY={}", params.owner_public_key)];
+        if stdio {
+            env.push("MCP_TRANSPORT=stdio".to_string());
+        } else {
+            env.push("PORT=3000".to_string());
+            env.push(format!("MCP_TRANSPORT={}", params.transport.env_value()));
+        }
 
-        // Add port bindings
-        container = container.port_bindings(port_bindings);
+        // Keep the workspace off the shared default bridge
+        let network = ctx.backend.create_network(service_id, labels).await?;
+        let backend = ctx.backend.clone();
+        rollback.push("network", async move {
+            backend.remove_network(service_id).await
+        });
+
+        // Restrict outbound traffic before anything runs on the network
+        match &network.interface {
+            Some(interface) => {
+                // Registered first, a failed apply can leave part of the chain behind
+                rollback.push("egress rules", remove_egress_policy(service_id));
+                let resolvers = &ctx.config.egress_resolvers;
+                let forward_chain = network.forward_chain;
+                apply_egress_policy(service_id, interface, forward_chain, egress, resolvers).await?
+            }
+            None if egress.mode != EgressMode::Open => {
+                return Err(
+                    "Egress policies can't be enforced on this backend (e.g. rootless Podman), \
+                     set MCP_EGRESS_MODE=open to run workspaces without one"
+                        .into(),
+                );
+            }
+            None => {}
+        }
 
-        // Add container name
-        container = container.with_name(format!("mcp-svc-{}", service_id));
+        let mut spec = ContainerSpec {
+            name: workspace_container_name(service_id),
+            image: WORKSPACE_IMAGE.to_string(),
+            env,
+            network: Some(network.name.clone()),
+            labels: labels.clone(),
+            runtime: ctx
+                .config
+                .runtimes
+                .for_tier(&params.tier)
+                .map(str::to_string),
+            ..Default::default()
+        };
+
+        // Set up port bindings, stdio servers are reached through the operator instead
+        if stdio {
+            spec.cmd = params.stdio_command.clone();
+            spec.interactive = true;
+        } else {
+            spec.ports.push(PortMapping {
+                container_port: 3000,
+                host_ip: IpAddr::V4(bind_ip),
+                host_port: upstream_port,
+            });
+        }
 
-        // Set up bind volumes
-        let project_id = format!("workspaces/{}", service_id);
+        // Run unprivileged, minus whatever the operator relaxed for this image
+        ctx.config.security.apply(&mut spec)?;
+        // Snapshot images keep the profile of the image they were committed from
+        if let Some(image) = image {
+            spec.image = image.to_string();
+        }
+        let owner = spec.user.as_deref().and_then(DataOwner::of_user);
+
+        // Set up the persistent workspace directory
+        let host_path = match storage::workspace_dir(ctx, service_id).await? {
+            Some(host_path) => Some(host_path),
+            None if storage::is_persistent(ctx) => {
+                let host_path =
+                    storage::create_workspace_dir(ctx, service_id, &params.tier).await?;
+                // Only delete what this attempt created, never data a workspace already had
+                let storage_ctx = ctx.clone();
+                rollback.push("data directory", async move {
+                    storage::remove_workspace_dir(&storage_ctx, service_id).await
+                });
+                std::fs::create_dir_all(&host_path)?;
+                if let Some(seed) = &params.seed {
+                    let blob_dir = ctx.config.blob_dir.as_deref();
+                    let limit = params.tier.storage_limit();
+                    let size = unpack_seed(seed, blob_dir, &host_path, limit, owner).await?;
+                    blueprint_sdk::info!("Seeded workspace {} with {} bytes", service_id, size);
+                }
+                Some(host_path)
+            }
+            None => None,
+        };
 
-        if let Some(ref data_dir) = ctx.env.data_dir {
-            let host_path = format!("{}/{}", data_dir.display(), project_id);
-            std::fs::create_dir_all(&host_path)?;
+        if let Some(host_path) = host_path {
             let host_path = std::fs::canonicalize(&host_path)?;
-            let host_path = host_path.display().to_string();
+            chown_workspace_dir(&host_path, owner);
             // Set up the container path
-            let bind = format!("{}:/blueprint:rw", host_path);
-
-            // Add bind volumes
-            container = container.binds(&[bind]);
+            let source = storage::mount_source(ctx, service_id, &host_path);
+            spec.binds.push(format!("{}:/blueprint:rw", source));
+        } else if params.seed.is_some() {
+            return Err(WorkspaceError::InvalidParams(
+                "This operator doesn't keep workspace data to seed".to_string(),
+            )
+            .into());
         }
 
         // Create the container
-        container.create().await?;
+        let id = ctx.backend.create(spec).await?;
+        let (backend, container) = (ctx.backend.clone(), id.clone());
+        rollback.push("container", async move { backend.remove(&container).await });
+
+        // Attach to the stdio server before it starts so no output is lost
+        let stdio = if stdio {
+            let addr = SocketAddr::new(IpAddr::V4(bind_ip), upstream_port);
+            let attached = ctx.backend.attach(&id).await?;
+            let owner = params.owner_public_key;
+            Some(StdioBridge::spawn(attached, addr, service_id, owner).await?)
+        } else {
+            None
+        };
 
         // Return the object after successful container creation
         Ok(Self {
-            container,
+            id,
             service_id,
             port,
-            docker: ctx.docker.clone(),
+            upstream: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), upstream_port),
+            network: network.name,
+            transport: params.transport,
+            stdio,
+            tls,
+            backend: ctx.backend.clone(),
         })
     }
 
@@ -145,7 +351,7 @@ impl WorkspaceContainer {
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         // Start the container
         blueprint_sdk::info!("Starting container for service ID: {}", self.service_id);
-        self.container.start(false).await?;
+        self.backend.start(&self.id).await?;
 
         blueprint_sdk::info!("Container started, waiting for health check...");
         // Wait for container to be healthy (timeout after 30 seconds)
@@ -153,36 +359,17 @@ impl WorkspaceContainer {
         let timeout = std::time::Duration::from_secs(30);
 
         while start_time.elapsed() < timeout {
-            // Get container status using Docker API directly
-            if let Some(container_id) = self.container.id() {
-                blueprint_sdk::info!("Inspecting container: {}", container_id);
-                let inspect_options = InspectContainerOptions { size: false };
-
-                if let Ok(info) = self
-                    .docker
-                    .inspect_container(container_id, Some(inspect_options))
-                    .await
-                {
-                    // Check if running and healthy
-                    if let Some(state) = &info.state {
-                        if let Some(status) = &state.status {
-                            // Use proper enum comparison for container status
-                            if *status == ContainerStateStatusEnum::RUNNING {
-                                if let Some(health) = &state.health {
-                                    if let Some(health_status) = &health.status {
-                                        // Use proper enum comparison for health status
-                                        if *health_status == HealthStatusEnum::HEALTHY {
-                                            return Ok(());
-                                        }
-                                    }
-                                }
-                            } else if *status == ContainerStateStatusEnum::EXITED
-                                || *status == ContainerStateStatusEnum::DEAD
-                            {
-                                break;
-                            }
-                        }
+            blueprint_sdk::info!("Inspecting container: {}", self.id);
+            if let Ok(Some(info)) = self.backend.inspect(&self.id).await {
+                match info.state {
+                    // Stdio servers have no health check, running is ready
+                    ContainerState::Running
+                        if self.stdio.is_some() || info.health == Some(HealthState::Healthy) =>
+                    {
+                        return Ok(());
                     }
+                    ContainerState::Exited | ContainerState::Dead => break,
+                    _ => {}
                 }
             }
 
@@ -190,31 +377,54 @@ impl WorkspaceContainer {
             tokio::time::sleep(std::time::Duration::from_secs(1)).await;
         }
 
-        // Container didn't become healthy in time, clean up with force
-        blueprint_sdk::error!(
-            "Container failed to become healthy within timeout, stopping and removing..."
-        );
-        self.container.stop().await?;
-
-        // Use a cloned reference to the container ID for removal
-        if let Some(container_id) = self.container.id() {
-            // Use the Docker client directly for removal to avoid borrowing issues
-            self.docker
-                .remove_container(
-                    container_id,
-                    Some(RemoveContainerOptions {
-                        force: true,
-                        ..Default::default()
-                    }),
-                )
-                .await?;
+        // Container didn't become healthy in time, the caller rolls it back
+        blueprint_sdk::error!("Container failed to become healthy within timeout");
+        if let Ok(logs) = self.backend.logs(&self.id, 20).await {
+            blueprint_sdk::error!("Last container output:\n{}", logs);
         }
 
-        Err("Container failed to become healthy within timeout".into())
+        Err(WorkspaceError::HealthTimeout.into())
+    }
+
+    fn get_endpoint_url(&self, domain: &str) -> String {
+        let scheme = if self.tls.is_some() { "https" } else { "http" };
+        format!(
+            "{}://{}:{}{}",
+            scheme,
+            domain,
+            self.port,
+            self.transport.path()
+        )
+    }
+
+    // Reach the MCP server over the workspace network when the operator runs in a container,
+    // where the host's loopback ports aren't reachable. Stdio servers are served by the
+    // operator itself.
+    async fn resolve_upstream(
+        &mut self,
+        ctx: &MyContext,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if ctx.config.operator_container.is_none() || self.stdio.is_some() {
+            return Ok(());
+        }
+        let address = self
+            .backend
+            .inspect(&self.id)
+            .await?
+            .and_then(|info| info.addresses.get(&self.network).copied())
+            .ok_or_else(|| {
+                format!(
+                    "Container {} has no address on network {}",
+                    self.id, self.network
+                )
+            })?;
+        self.upstream = SocketAddr::new(address, 3000);
+        Ok(())
     }
 
-    fn get_sse_url(&self, domain: &str) -> String {
-        format!("http://{}:{}/sse", domain, self.port)
+    // Base URL the operator uses to reach the MCP server
+    fn upstream_url(&self) -> String {
+        format!("http://{}", self.upstream)
     }
 }
 
@@ -222,19 +432,292 @@ impl WorkspaceContainer {
 pub async fn create_workspace(
     Context(ctx): Context<MyContext>,
     ServiceId(service_id): ServiceId,
+    CallId(call_id): CallId,
     TangleArg(params): TangleArg<CreateWorkspaceParams>,
-) -> Result<TangleResult<String>, Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<TangleResult<CreateWorkspaceResult>, Box<dyn std::error::Error + Send + Sync>> {
     blueprint_sdk::info!("Creating workspace with params: {:?}", params);
     blueprint_sdk::info!("Service ID: {}", service_id);
-    let mut workspace = WorkspaceContainer::new(&ctx, service_id, &params).await?;
+
+    // Wait for earlier jobs of this workspace, e.g. a destroy still tearing it down
+    let _guard = ctx.locks.lock(service_id).await;
+    match create(&ctx, service_id, call_id, &params, None).await {
+        Ok(result) => Ok(TangleResult(result)),
+        Err(e) => {
+            // Report the failure on chain instead of dropping the result
+            let error = WorkspaceError::from(e);
+            blueprint_sdk::error!("Failed to create workspace {}: {}", service_id, error);
+            Ok(TangleResult(CreateWorkspaceResult {
+                error: JobError::from(&error),
+                ..Default::default()
+            }))
+        }
+    }
+}
+
+/// Create the workspace, running `image` instead of [`WORKSPACE_IMAGE`] if set.
+///
+/// Creating is idempotent per job call: a redelivered call gets the running workspace's
+/// endpoint back, or finishes the creation it interrupted.
+pub(super) async fn create(
+    ctx: &MyContext,
+    service_id: u64,
+    call_id: u64,
+    params: &CreateWorkspaceParams,
+    image: Option<String>,
+) -> Result<CreateWorkspaceResult, Box<dyn std::error::Error + Send + Sync>> {
+    let existing = ctx.workspaces.get(service_id);
+    match &existing {
+        Some(record) if record.deleted_at.is_some() => {
+            return Err(WorkspaceError::InvalidParams(format!(
+                "Workspace {} is pending deletion, undelete it or wait until it is purged",
+                service_id
+            ))
+            .into());
+        }
+        Some(record)
+            if record.call_id != call_id || record.workspace_name != params.workspace_name =>
+        {
+            return Err(WorkspaceError::InvalidParams(format!(
+                "Service {} already runs workspace {}, destroy it first",
+                service_id, record.workspace_name
+            ))
+            .into());
+        }
+        Some(record) => {
+            if let Some(result) = existing_endpoint(ctx, record).await? {
+                blueprint_sdk::info!("Workspace {} already created by this call", service_id);
+                return Ok(result);
+            }
+            blueprint_sdk::info!("Finishing interrupted creation of workspace {}", service_id);
+        }
+        None => {
+            // Workspaces pending deletion don't take up a slot
+            let live = || {
+                ctx.workspaces
+                    .list()
+                    .iter()
+                    .filter(|record| record.deleted_at.is_none())
+                    .count()
+            };
+            if let Some(max_workspaces) = ctx.config.max_workspaces
+                && live() >= max_workspaces
+            {
+                return Err(WorkspaceError::CapacityExceeded.into());
+            }
+            restore_backup(ctx, service_id, params).await?;
+        }
+    }
+
+    // Keep running the image a snapshot was restored from
+    let image = existing.map_or(image, |record| record.image);
+    bring_up(ctx, service_id, call_id, params, image.as_deref()).await
+}
+
+// Bring back the data of a workspace from its backup when the local copy is gone, e.g. after
+// the operator moved to a new host
+async fn restore_backup(
+    ctx: &MyContext,
+    service_id: u64,
+    params: &CreateWorkspaceParams,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let Some(backups) = &ctx.backups else {
+        return Ok(());
+    };
+    if !storage::is_persistent(ctx) || storage::workspace_dir(ctx, service_id).await?.is_some() {
+        return Ok(());
+    }
+    let workspace_dir = storage::create_workspace_dir(ctx, service_id, &params.tier).await?;
+    let owner = params.owner_public_key.to_string();
+    match backups.restore(service_id, &owner, &workspace_dir).await {
+        Ok(Some(backed_up_at)) => {
+            blueprint_sdk::info!(
+                "Restored workspace {} from its backup of {}",
+                service_id,
+                backed_up_at
+            );
+            Ok(())
+        }
+        // Don't leave an empty volume behind, it would pass for the workspace's data
+        result => {
+            storage::remove_workspace_dir(ctx, service_id).await?;
+            result.map(|_| ())
+        }
+    }
+}
+
+/// Run the workspace from scratch, reusing its data directory if it has one, and undo every
+/// step taken so far when one fails. `image` overrides [`WORKSPACE_IMAGE`].
+pub(super) async fn bring_up(
+    ctx: &MyContext,
+    service_id: u64,
+    call_id: u64,
+    params: &CreateWorkspaceParams,
+    image: Option<&str>,
+) -> Result<CreateWorkspaceResult, Box<dyn std::error::Error + Send + Sync>> {
+    // An interrupted attempt leaves its container behind, which would clash on the name
+    remove_leftovers(ctx, service_id).await?;
+
+    let mut rollback = Rollback::default();
+    match create_steps(ctx, service_id, call_id, params, image, &mut rollback).await {
+        Ok(result) => {
+            rollback.commit();
+            Ok(result)
+        }
+        Err(e) => {
+            blueprint_sdk::warn!("Rolling back workspace {}: {}", service_id, e);
+            rollback.run().await;
+            Err(e)
+        }
+    }
+}
+
+// Endpoint of a workspace that is up and served by this operator, `None` when it needs to be
+// brought up again, e.g. after the runner restarted without its bridges
+async fn existing_endpoint(
+    ctx: &MyContext,
+    record: &WorkspaceRecord,
+) -> Result<Option<CreateWorkspaceResult>, Box<dyn std::error::Error + Send + Sync>> {
+    let serving = ctx
+        .bridges
+        .lock()
+        .map_err(|_| "Workspace bridge registry poisoned")?
+        .contains_key(&record.service_id);
+    let container = ctx
+        .backend
+        .find_workspace(ctx.blueprint_id(), record.service_id)
+        .await?;
+
+    // Stdio containers have no health check
+    let healthy = matches!(
+        container,
+        Some(ContainerInfo {
+            state: ContainerState::Running,
+            health: None | Some(HealthState::Healthy),
+            ..
+        })
+    );
+    Ok((serving && healthy).then(|| CreateWorkspaceResult {
+        url: record.url.clone(),
+        ws_url: record.ws_url.clone(),
+        tls_fingerprint: record.tls_fingerprint.clone(),
+        error: JobError::default(),
+    }))
+}
+
+// Drop the container and bridges of a previous attempt, keeping its data
+async fn remove_leftovers(
+    ctx: &MyContext,
+    service_id: u64,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if let Ok(mut bridges) = ctx.bridges.lock() {
+        bridges.remove(&service_id);
+    }
+    if let Some(container) = ctx
+        .backend
+        .find_workspace(ctx.blueprint_id(), service_id)
+        .await?
+    {
+        blueprint_sdk::info!("Removing leftover container {}", container.name);
+        ctx.backend.remove(&container.id).await?;
+    }
+    Ok(())
+}
+
+async fn create_steps(
+    ctx: &MyContext,
+    service_id: u64,
+    call_id: u64,
+    params: &CreateWorkspaceParams,
+    image: Option<&str>,
+    rollback: &mut Rollback,
+) -> Result<CreateWorkspaceResult, Box<dyn std::error::Error + Send + Sync>> {
+    let egress = EgressPolicy::for_workspace(params.egress.as_ref(), &ctx.config.egress)?;
+    let created_at = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs();
+    let owner = params.owner_public_key.to_string();
+    let labels = WorkspaceLabels {
+        blueprint_id: ctx.blueprint_id(),
+        service_id,
+        workspace_name: &params.workspace_name,
+        owner: &owner,
+        tier: &params.tier,
+        created_at,
+    }
+    .to_labels();
+    let mut workspace =
+        WorkspaceContainer::new(ctx, service_id, params, &egress, &labels, image, rollback).await?;
 
     // Wait for container to be healthy
     workspace.start_and_wait_healthy().await?;
+    workspace.resolve_upstream(ctx).await?;
+
+    let domain = ctx.config.public_host.as_str();
+    let acceptor = workspace.tls.as_ref().map(|tls| tls.acceptor.clone());
 
-    // Return the SSE URL
-    let sse = workspace.get_sse_url("localhost");
-    blueprint_sdk::info!("SSE URL: {}", sse);
-    Ok(TangleResult(sse))
+    // Terminate TLS in front of the MCP server
+    let tls_proxy = match &acceptor {
+        Some(acceptor) => {
+            Some(TlsProxy::spawn(acceptor.clone(), workspace.port, workspace.upstream).await?)
+        }
+        None => None,
+    };
+
+    // Expose the MCP server over WebSocket as well
+    let websocket =
+        WsBridge::spawn(workspace.upstream_url(), workspace.transport, acceptor).await?;
+    let ws_url = websocket.ws_url(domain);
+    let bridges = WorkspaceBridges {
+        websocket,
+        stdio: workspace.stdio.take(),
+        tls_proxy,
+    };
+    ctx.bridges
+        .lock()
+        .map_err(|_| "Workspace bridge registry poisoned")?
+        .insert(service_id, bridges);
+    // Dropping the bridges closes their listeners and frees the ports
+    let registry = ctx.bridges.clone();
+    rollback.push("bridges", async move {
+        if let Ok(mut bridges) = registry.lock() {
+            bridges.remove(&service_id);
+        }
+        Ok(())
+    });
+
+    // Return the endpoint URLs for the requested transport
+    let url = workspace.get_endpoint_url(domain);
+    let tls_fingerprint = workspace
+        .tls
+        .as_ref()
+        .map(|tls| tls.fingerprint.clone())
+        .unwrap_or_default();
+    blueprint_sdk::info!("MCP endpoint URL: {}, WebSocket URL: {}", url, ws_url);
+
+    ctx.workspaces.insert(WorkspaceRecord {
+        service_id,
+        workspace_name: params.workspace_name.clone(),
+        call_id,
+        owner_public_key: owner,
+        tier: params.tier.clone(),
+        transport: workspace.transport,
+        stdio_command: params.stdio_command.clone(),
+        url: url.clone(),
+        ws_url: ws_url.clone(),
+        tls_fingerprint: tls_fingerprint.clone(),
+        egress,
+        runtime: ctx.config.runtimes.resolved(&params.tier),
+        created_at,
+        image: image.map(str::to_string),
+        deleted_at: None,
+    })?;
+
+    Ok(CreateWorkspaceResult {
+        url,
+        ws_url,
+        tls_fingerprint,
+        error: JobError::default(),
+    })
 }
 
 #[cfg(test)]
@@ -249,5 +732,14 @@ mod tests {
             !parsed_args.is_empty(),
             "Parsed arguments should not be empty"
         );
+        assert_eq!(parsed_args[0].transport, McpTransport::Sse);
+        assert_eq!(parsed_args[1].transport, McpTransport::StreamableHttp);
+        assert!(parsed_args[1].stdio_command.is_empty());
+        assert_eq!(parsed_args[2].stdio_command[0], "npx");
+        assert!(parsed_args[0].egress.is_none());
+        assert_eq!(
+            parsed_args[1].egress.as_ref().unwrap().mode,
+            crate::egress::EgressMode::Allowlist
+        );
     }
 }
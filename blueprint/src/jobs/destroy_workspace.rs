@@ -1,110 +1,361 @@
 use crate::MyContext;
+use crate::backend::ContainerState;
+use crate::egress::remove_egress_policy;
+use crate::error::{JobError, WorkspaceError};
+use crate::snapshot::SnapshotStore;
+use crate::state::WorkspaceRecord;
+use crate::storage;
 use blueprint_sdk::extract::Context;
 use blueprint_sdk::tangle::extract::{ServiceId, TangleArg, TangleResult};
-use docktopus::bollard::container::RemoveContainerOptions;
-use std::fs;
-use std::io;
-use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Output of the destroy workspace job
+#[derive(Default, Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct DestroyWorkspaceResult {
+    /// Whether the workspace is gone, also when it never existed.
+    pub destroyed: bool,
+    /// When a workspace destroyed with a grace period gets purged, in seconds since the Unix
+    /// epoch. `0` when it was destroyed right away.
+    pub purge_at: u64,
+    /// Why the workspace couldn't be destroyed, code `0` on success.
+    pub error: JobError,
+}
 
 #[blueprint_sdk::macros::debug_job]
 pub async fn destroy_workspace(
     Context(ctx): Context<MyContext>,
     ServiceId(service_id): ServiceId,
     TangleArg(_): TangleArg<bool>,
-) -> Result<TangleResult<bool>, Box<dyn std::error::Error + Send + Sync>> {
-    // The container name follows the pattern: mcp-svc-{service_id}
-    let container_name = format!("mcp-svc-{}", service_id);
-
-    // Get all running containers that match our pattern
-    let containers = ctx
-        .docker
-        .list_containers::<String>(None)
-        .await
-        .map_err(|e| {
-            Box::<dyn std::error::Error + Send + Sync>::from(format!(
-                "Failed to list containers: {}",
-                e
-            ))
-        })?;
-
-    let mut container_found = false;
-
-    // Find and remove the container by name
-    for container in containers {
-        if let Some(names) = container.names {
-            for name in names {
-                // Container names from Docker API include a leading slash
-                let normalized_name = name.trim_start_matches('/');
-                if normalized_name == container_name {
-                    container_found = true;
-
-                    if let Some(container_id) = container.id {
-                        // First try to stop the container
-                        let _ = ctx.docker.stop_container(&container_id, None).await;
-
-                        // Then remove it with force option to ensure it's gone
-                        ctx.docker
-                            .remove_container(
-                                &container_id,
-                                Some(RemoveContainerOptions {
-                                    force: true,
-                                    ..Default::default()
-                                }),
-                            )
-                            .await
-                            .map_err(|e| {
-                                Box::<dyn std::error::Error + Send + Sync>::from(format!(
-                                    "Failed to remove container: {}",
-                                    e
-                                ))
-                            })?;
-
-                        tracing::info!(
-                            "Container {} successfully stopped and removed",
-                            container_name
-                        );
-                        break;
-                    }
+) -> Result<TangleResult<DestroyWorkspaceResult>, Box<dyn std::error::Error + Send + Sync>> {
+    let _guard = ctx.locks.lock(service_id).await;
+    let result = match (ctx.config.delete_grace, ctx.workspaces.get(service_id)) {
+        (Some(grace), Some(record)) => soft_delete(&ctx, record, grace).await,
+        _ => destroy(&ctx, service_id).await.map(|()| 0),
+    };
+    match result {
+        Ok(purge_at) => Ok(TangleResult(DestroyWorkspaceResult {
+            destroyed: true,
+            purge_at,
+            error: JobError::default(),
+        })),
+        Err(e) => {
+            // Report the failure on chain instead of dropping the result
+            let error = WorkspaceError::from(e);
+            tracing::error!("Failed to destroy workspace {}: {}", service_id, error);
+            Ok(TangleResult(DestroyWorkspaceResult {
+                destroyed: false,
+                purge_at: 0,
+                error: JobError::from(&error),
+            }))
+        }
+    }
+}
+
+// How often workspaces past their grace period are looked for
+const PURGE_INTERVAL: Duration = Duration::from_secs(60);
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+// Stop the workspace and disable its endpoint, keeping everything else until the grace
+// period is over. Returns when the workspace gets purged.
+async fn soft_delete(
+    ctx: &MyContext,
+    mut record: WorkspaceRecord,
+    grace: u64,
+) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(deleted_at) = record.deleted_at {
+        return Ok(deleted_at + grace);
+    }
+    let service_id = record.service_id;
+
+    if let Ok(mut bridges) = ctx.bridges.lock() {
+        bridges.remove(&service_id);
+    }
+    if let Some(container) = ctx
+        .backend
+        .find_workspace(ctx.blueprint_id(), service_id)
+        .await?
+        && container.state == ContainerState::Running
+    {
+        ctx.backend.stop(&container.id).await?;
+    }
+
+    let deleted_at = now();
+    record.deleted_at = Some(deleted_at);
+    ctx.workspaces.insert(record)?;
+    tracing::info!(
+        "Workspace {} pending deletion for {} seconds",
+        service_id,
+        grace
+    );
+    Ok(deleted_at + grace)
+}
+
+/// Purge the workspaces whose grace period is over, in the background until the runtime
+/// shuts down.
+pub fn spawn_deletion_purger(ctx: MyContext) {
+    let Some(grace) = ctx.config.delete_grace else {
+        return;
+    };
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            for record in ctx.workspaces.list() {
+                let service_id = record.service_id;
+                let Some(deleted_at) = record.deleted_at else {
+                    continue;
+                };
+                if deleted_at + grace > now() {
+                    continue;
+                }
+
+                let _guard = ctx.locks.lock(service_id).await;
+                // Undeleted while waiting for the lock
+                let pending = ctx.workspaces.get(service_id).and_then(|r| r.deleted_at);
+                if pending != Some(deleted_at) {
+                    continue;
                 }
+                match destroy(&ctx, service_id).await {
+                    Ok(()) => tracing::info!("Purged workspace {}", service_id),
+                    Err(e) => tracing::warn!("Failed to purge workspace {}: {}", service_id, e),
+                }
+            }
+        }
+    });
+}
+
+async fn destroy(
+    ctx: &MyContext,
+    service_id: u64,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Stop accepting WebSocket connections for this workspace
+    if let Ok(mut bridges) = ctx.bridges.lock() {
+        bridges.remove(&service_id);
+    }
+
+    // Look the container up by its labels, whatever state it is in: crashed or stopped
+    // leftovers would otherwise block the next create with the same name
+    match ctx
+        .backend
+        .find_workspace(ctx.blueprint_id(), service_id)
+        .await?
+    {
+        Some(container) => {
+            // First try to stop the container gracefully
+            if container.state == ContainerState::Running {
+                let _ = ctx.backend.stop(&container.id).await;
             }
+
+            // Then remove it with force option to ensure it's gone
+            ctx.backend.remove(&container.id).await?;
+
+            tracing::info!(
+                "Container {} ({}) successfully removed",
+                container.name,
+                container.state
+            );
         }
+        None => tracing::warn!("No container found for workspace {}", service_id),
+    }
+
+    if let Err(e) = remove_egress_policy(service_id).await {
+        tracing::warn!("Failed to remove workspace egress rules: {}", e);
     }
 
-    if !container_found {
-        tracing::warn!("No container found with name: {}", container_name);
+    // Remove the workspace network now that nothing is attached to it
+    if let Err(e) = ctx.backend.remove_network(service_id).await {
+        tracing::warn!("Failed to remove workspace network: {}", e);
     }
 
     // Clean up any persistent data associated with this service
+    if let Some(workspace_dir) = storage::workspace_dir(ctx, service_id).await? {
+        // Keep the data when it can't be archived, the destroy can be retried
+        if let Some(archive) = &ctx.config.archive {
+            archive.archive(service_id, &workspace_dir).await?;
+        }
+        match storage::remove_workspace_dir(ctx, service_id).await {
+            Ok(_) => tracing::info!("Removed data directory for service: {}", service_id),
+            Err(e) => tracing::warn!("Failed to remove data directory: {}", e),
+        }
+    }
+
+    // Snapshots go with the workspace, along with the images committed for them
     if let Some(data_dir) = ctx.env.data_dir.as_ref() {
-        let service_data_dir = data_dir.join("workspaces").join(service_id.to_string());
-        // Check if the directory exists before attempting to remove it
-        if service_data_dir.exists() {
-            match remove_dir_all(&service_data_dir) {
-                Ok(_) => tracing::info!("Removed data directory for service: {}", service_id),
-                Err(e) => tracing::warn!("Failed to remove data directory: {}", e),
+        for image in SnapshotStore::new(data_dir, service_id).remove_all()? {
+            if let Err(e) = ctx.backend.remove_image(&image).await {
+                tracing::warn!("Failed to remove snapshot image {}: {}", image, e);
             }
         }
     }
 
+    // Backups go too, or the next workspace of this service would be restored from them
+    if let Some(backups) = &ctx.backups {
+        let removed = backups.remove(service_id).await?;
+        tracing::info!("Deleted {} backups of workspace {}", removed, service_id);
+    }
+
+    ctx.workspaces.remove(service_id)?;
+
     // Return success even if container wasn't found, to ensure idempotency
-    Ok(TangleResult(true))
+    Ok(())
 }
 
-// Helper function to recursively remove a directory
-fn remove_dir_all(path: &Path) -> io::Result<()> {
-    if path.exists() {
-        for entry in fs::read_dir(path)? {
-            let entry = entry?;
-            let entry_path = entry.path();
-
-            if entry_path.is_dir() {
-                remove_dir_all(&entry_path)?;
-            } else {
-                fs::remove_file(entry_path)?;
-            }
-        }
-        fs::remove_dir(path)
-    } else {
-        Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::WorkspaceBackend;
+    use crate::backend::fake::TestEnv;
+    use crate::backup::{
+        BackupConfig, Backups, DEFAULT_INTERVAL, DEFAULT_KEEP, DEFAULT_RETENTION, Secret,
+        TargetConfig,
+    };
+    use crate::labels::{self, Labels};
+    use crate::{CreateWorkspaceParams, create_workspace};
+    use blueprint_sdk::tangle::extract::{CallId, ServiceId};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn it_removes_stopped_containers() {
+        let env = TestEnv::new();
+        let TestEnv { backend, ctx, .. } = &env;
+        let params = CreateWorkspaceParams {
+            workspace_name: "crashed".to_string(),
+            ..Default::default()
+        };
+        create_workspace(
+            Context(ctx.clone()),
+            ServiceId(7),
+            CallId(1),
+            TangleArg(params),
+        )
+        .await
+        .unwrap();
+        backend.stop("mcp-svc-7").await.unwrap();
+
+        let result = destroy_workspace(Context(ctx.clone()), ServiceId(7), TangleArg(true))
+            .await
+            .unwrap();
+        assert!(result.0.destroyed);
+        assert!(backend.containers().is_empty());
+        assert!(ctx.workspaces.get(7).is_none());
+    }
+
+    #[tokio::test]
+    async fn it_removes_containers_from_before_labels() {
+        let env = TestEnv::new();
+        let TestEnv { backend, ctx, .. } = &env;
+        backend.add_foreign("mcp-svc-9", Labels::new());
+        backend.stop("mcp-svc-9").await.unwrap();
+
+        let result = destroy_workspace(Context(ctx.clone()), ServiceId(9), TangleArg(true))
+            .await
+            .unwrap();
+        assert!(result.0.destroyed);
+        assert!(backend.containers().is_empty());
+
+        // A legacy leftover doesn't block the next create on its name either
+        backend.add_foreign("mcp-svc-9", Labels::new());
+        let created = create_workspace(
+            Context(ctx.clone()),
+            ServiceId(9),
+            CallId(1),
+            TangleArg(CreateWorkspaceParams::default()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(created.0.error.code, 0);
+        let container = backend.inspect("mcp-svc-9").await.unwrap().unwrap();
+        assert_eq!(labels::service_id(&container.labels), Some(9));
+    }
+
+    #[tokio::test]
+    async fn it_leaves_containers_of_other_blueprints_alone() {
+        let env = TestEnv::new();
+        let TestEnv { backend, ctx, .. } = &env;
+        let other = labels::ownership(ctx.blueprint_id() + 1, 9);
+        backend.add_foreign("mcp-svc-9", other);
+
+        let result = destroy_workspace(Context(ctx.clone()), ServiceId(9), TangleArg(true))
+            .await
+            .unwrap();
+        assert!(result.0.destroyed);
+        assert_eq!(backend.containers(), vec!["mcp-svc-9".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn it_deletes_backups_so_the_next_workspace_starts_empty() {
+        let mut env = TestEnv::new();
+        let config = BackupConfig {
+            target: TargetConfig::Dir(env.root().join("store")),
+            secret: Secret("operator secret".to_string()),
+            interval: DEFAULT_INTERVAL,
+            retention: DEFAULT_RETENTION,
+            keep: DEFAULT_KEEP,
+        };
+        let backups = Arc::new(Backups::new(&config).unwrap());
+        env.ctx.backups = Some(backups.clone());
+        let ctx = &env.ctx;
+        let params = || CreateWorkspaceParams {
+            workspace_name: "backed-up".to_string(),
+            ..Default::default()
+        };
+        let owner = params().owner_public_key.to_string();
+
+        let create = |call_id| {
+            create_workspace(
+                Context(ctx.clone()),
+                ServiceId(4),
+                CallId(call_id),
+                TangleArg(params()),
+            )
+        };
+        assert_eq!(create(1).await.unwrap().0.error.code, 0);
+        let workspace_dir = env.root().join("workspaces/4");
+        std::fs::write(workspace_dir.join("notes.md"), "destroyed").unwrap();
+        backups.backup(4, &owner, &workspace_dir).await.unwrap();
+
+        let result = destroy_workspace(Context(ctx.clone()), ServiceId(4), TangleArg(true))
+            .await
+            .unwrap();
+        assert!(result.0.destroyed);
+        assert!(backups.service_ids().await.unwrap().is_empty());
+
+        assert_eq!(create(2).await.unwrap().0.error.code, 0);
+        assert!(workspace_dir.exists());
+        assert!(!workspace_dir.join("notes.md").exists());
+    }
+
+    #[tokio::test]
+    async fn it_frees_the_slot_of_workspaces_pending_deletion() {
+        let mut env = TestEnv::new();
+        let config = Arc::make_mut(&mut env.ctx.config);
+        config.delete_grace = Some(60);
+        config.max_workspaces = Some(1);
+        let ctx = &env.ctx;
+        let create = |service_id| {
+            create_workspace(
+                Context(ctx.clone()),
+                ServiceId(service_id),
+                CallId(service_id),
+                TangleArg(CreateWorkspaceParams {
+                    workspace_name: format!("workspace-{}", service_id),
+                    ..Default::default()
+                }),
+            )
+        };
+        assert_eq!(create(1).await.unwrap().0.error.code, 0);
+        assert_eq!(create(2).await.unwrap().0.error.code, 4);
+
+        let result = destroy_workspace(Context(ctx.clone()), ServiceId(1), TangleArg(true))
+            .await
+            .unwrap();
+        assert_ne!(result.0.purge_at, 0);
+        assert_eq!(create(2).await.unwrap().0.error.code, 0);
     }
 }
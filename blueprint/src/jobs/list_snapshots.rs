@@ -0,0 +1,63 @@
+use crate::MyContext;
+use crate::error::{JobError, WorkspaceError};
+use crate::snapshot::{SnapshotInfo, SnapshotStore, dir_size};
+use crate::storage;
+use blueprint_sdk::extract::Context;
+use blueprint_sdk::tangle::extract::{ServiceId, TangleArg, TangleResult};
+
+// Output of the list snapshots job
+#[derive(Default, Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct ListSnapshots {
+    /// Snapshots of the workspace, by name and then version.
+    pub snapshots: Vec<SnapshotInfo>,
+    /// Bytes the workspace data and its snapshots take.
+    pub used: u64,
+    /// Bytes the workspace's tier allows.
+    pub limit: u64,
+    /// Why the snapshots couldn't be listed, code `0` on success.
+    pub error: JobError,
+}
+
+#[blueprint_sdk::macros::debug_job]
+pub async fn list_snapshots(
+    Context(ctx): Context<MyContext>,
+    ServiceId(service_id): ServiceId,
+    TangleArg(_): TangleArg<bool>,
+) -> Result<TangleResult<ListSnapshots>, Box<dyn std::error::Error + Send + Sync>> {
+    let (Some(record), Some(data_dir)) = (ctx.workspaces.get(service_id), &ctx.env.data_dir) else {
+        return Ok(TangleResult(ListSnapshots::default()));
+    };
+
+    let store = SnapshotStore::new(data_dir, service_id);
+    let listed = async {
+        let workspace_dir = storage::workspace_dir(&ctx, service_id).await?;
+        let snapshots = store.list()?;
+        let data = match &workspace_dir {
+            Some(workspace_dir) => dir_size(workspace_dir)?,
+            None => 0,
+        };
+        let used = data + snapshots.iter().map(|s| s.size).sum::<u64>();
+        Ok::<_, Box<dyn std::error::Error + Send + Sync>>((snapshots, used))
+    }
+    .await;
+    match listed {
+        Ok((snapshots, used)) => Ok(TangleResult(ListSnapshots {
+            snapshots,
+            used,
+            limit: record.tier.storage_limit(),
+            error: JobError::default(),
+        })),
+        Err(e) => {
+            let error = WorkspaceError::from(e);
+            tracing::error!(
+                "Failed to list snapshots of workspace {}: {}",
+                service_id,
+                error
+            );
+            Ok(TangleResult(ListSnapshots {
+                error: JobError::from(&error),
+                ..Default::default()
+            }))
+        }
+    }
+}
@@ -1,9 +1,38 @@
+mod clone_workspace;
 mod create_workspace;
 mod destroy_workspace;
+mod list_snapshots;
+mod restore_snapshot;
+mod restore_workspace;
+mod snapshot_workspace;
+mod undelete_workspace;
+mod workspace_status;
 
-pub use create_workspace::{CreateWorkspaceParams, ResourceTier, create_workspace};
-pub use destroy_workspace::destroy_workspace;
+#[cfg(test)]
+mod tests;
+
+pub use clone_workspace::{CloneWorkspaceParams, clone_workspace};
+pub use create_workspace::{
+    CreateWorkspaceParams, CreateWorkspaceResult, McpTransport, ResourceTier, WORKSPACE_IMAGE,
+    create_workspace,
+};
+pub use destroy_workspace::{DestroyWorkspaceResult, destroy_workspace, spawn_deletion_purger};
+pub use list_snapshots::{ListSnapshots, list_snapshots};
+pub use restore_snapshot::{RestoreSnapshotParams, restore_snapshot};
+pub use restore_workspace::{RestoreWorkspaceResult, restore_workspace};
+pub use snapshot_workspace::{
+    SnapshotWorkspaceParams, SnapshotWorkspaceResult, snapshot_workspace,
+};
+pub use undelete_workspace::undelete_workspace;
+pub use workspace_status::{WorkspaceStatus, workspace_status};
 
 // Re-export job IDs
 pub const CREATE_WORKSPACE_JOB_ID: u32 = 0;
 pub const DESTROY_WORKSPACE_JOB_ID: u32 = 1;
+pub const WORKSPACE_STATUS_JOB_ID: u32 = 2;
+pub const RESTORE_WORKSPACE_JOB_ID: u32 = 3;
+pub const UNDELETE_WORKSPACE_JOB_ID: u32 = 4;
+pub const SNAPSHOT_WORKSPACE_JOB_ID: u32 = 5;
+pub const LIST_SNAPSHOTS_JOB_ID: u32 = 6;
+pub const RESTORE_SNAPSHOT_JOB_ID: u32 = 7;
+pub const CLONE_WORKSPACE_JOB_ID: u32 = 8;
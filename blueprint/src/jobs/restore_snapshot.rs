@@ -0,0 +1,181 @@
+use super::create_workspace::bring_up;
+use crate::backend::ContainerState;
+use crate::error::{JobError, WorkspaceError};
+use crate::security::chown_workspace_dir;
+use crate::snapshot::SnapshotStore;
+use crate::storage;
+use crate::{CreateWorkspaceParams, CreateWorkspaceResult, MyContext, WORKSPACE_IMAGE};
+use blueprint_sdk::extract::Context;
+use blueprint_sdk::tangle::extract::{ServiceId, TangleArg, TangleResult};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// Input parameters for the restore snapshot job
+#[derive(Default, Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct RestoreSnapshotParams {
+    pub name: String,
+    /// Version of the snapshot, `0` for the latest one.
+    #[serde(default)]
+    pub version: u32,
+}
+
+/// Put a running workspace back to a snapshot, replacing its data and, when the snapshot
+/// committed one, its container.
+///
+/// Its endpoint is allocated anew, so clients need the URLs from the result.
+#[blueprint_sdk::macros::debug_job]
+pub async fn restore_snapshot(
+    Context(ctx): Context<MyContext>,
+    ServiceId(service_id): ServiceId,
+    TangleArg(params): TangleArg<RestoreSnapshotParams>,
+) -> Result<TangleResult<CreateWorkspaceResult>, BoxError> {
+    let _guard = ctx.locks.lock(service_id).await;
+    match restore(&ctx, service_id, &params).await {
+        Ok(result) => Ok(TangleResult(result)),
+        Err(e) => {
+            // Report the failure on chain instead of dropping the result
+            let error = WorkspaceError::from(e);
+            tracing::error!(
+                "Failed to restore workspace {} to a snapshot: {}",
+                service_id,
+                error
+            );
+            Ok(TangleResult(CreateWorkspaceResult {
+                error: JobError::from(&error),
+                ..Default::default()
+            }))
+        }
+    }
+}
+
+async fn restore(
+    ctx: &MyContext,
+    service_id: u64,
+    params: &RestoreSnapshotParams,
+) -> Result<CreateWorkspaceResult, BoxError> {
+    let Some(data_dir) = &ctx.env.data_dir else {
+        return Err(WorkspaceError::InvalidParams(
+            "This operator has no data directory to keep snapshots in".to_string(),
+        )
+        .into());
+    };
+    let record = match ctx.workspaces.get(service_id) {
+        Some(record) if record.deleted_at.is_none() => record,
+        _ => {
+            return Err(WorkspaceError::InvalidParams(format!(
+                "Workspace {} isn't running",
+                service_id
+            ))
+            .into());
+        }
+    };
+    let store = SnapshotStore::new(data_dir, service_id);
+    let snapshot = store.get(&params.name, params.version)?.ok_or_else(|| {
+        WorkspaceError::InvalidParams(format!(
+            "Workspace {} has no snapshot {} version {}",
+            service_id, params.name, params.version
+        ))
+    })?;
+
+    // Nothing may write to the data while it is swapped
+    if let Some(container) = ctx
+        .backend
+        .find_workspace(ctx.blueprint_id(), service_id)
+        .await?
+        && container.state == ContainerState::Running
+    {
+        ctx.backend.stop(&container.id).await?;
+    }
+    let workspace_dir = match storage::workspace_dir(ctx, service_id).await? {
+        Some(workspace_dir) => workspace_dir,
+        None => storage::create_workspace_dir(ctx, service_id, &record.tier).await?,
+    };
+    store.restore(&snapshot, &workspace_dir).await?;
+    let owner = ctx.config.security.data_owner(WORKSPACE_IMAGE);
+    chown_workspace_dir(&workspace_dir, owner);
+
+    // Data-only snapshots keep the image the workspace runs
+    let image = if snapshot.image.is_empty() {
+        record.image.clone()
+    } else {
+        Some(snapshot.image.clone())
+    };
+    let params = CreateWorkspaceParams::from_record(&record)?;
+    let result = bring_up(ctx, service_id, record.call_id, &params, image.as_deref()).await?;
+    tracing::info!(
+        "Restored workspace {} to snapshot {} version {}",
+        service_id,
+        snapshot.name,
+        snapshot.version
+    );
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::fake::TestEnv;
+    use crate::{SnapshotWorkspaceParams, create_workspace, destroy_workspace, snapshot_workspace};
+    use blueprint_sdk::tangle::extract::CallId;
+
+    #[tokio::test]
+    async fn it_restores_data_and_committed_image() {
+        let env = TestEnv::new();
+        let TestEnv { backend, ctx, .. } = &env;
+        let workspace = env.root().join("workspaces/2");
+
+        let created = create_workspace(
+            Context(ctx.clone()),
+            ServiceId(2),
+            CallId(1),
+            TangleArg(CreateWorkspaceParams {
+                workspace_name: "agent".to_string(),
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(created.0.error.code, 0);
+        std::fs::write(workspace.join("notes.md"), "before").unwrap();
+
+        let taken = snapshot_workspace(
+            Context(ctx.clone()),
+            ServiceId(2),
+            TangleArg(SnapshotWorkspaceParams {
+                name: "checkpoint".to_string(),
+                commit: true,
+            }),
+        )
+        .await
+        .unwrap()
+        .0;
+        assert_eq!(taken.error.code, 0);
+        assert_eq!(taken.snapshot.image, "mcp-snapshot-2:checkpoint-1");
+        std::fs::write(workspace.join("notes.md"), "after").unwrap();
+
+        let restored = restore_snapshot(
+            Context(ctx.clone()),
+            ServiceId(2),
+            TangleArg(RestoreSnapshotParams {
+                name: "checkpoint".to_string(),
+                version: 0,
+            }),
+        )
+        .await
+        .unwrap()
+        .0;
+        assert_eq!(restored.error.code, 0);
+        assert_eq!(
+            std::fs::read_to_string(workspace.join("notes.md")).unwrap(),
+            "before"
+        );
+        let record = ctx.workspaces.get(2).unwrap();
+        assert_eq!(record.image.as_deref(), Some("mcp-snapshot-2:checkpoint-1"));
+
+        destroy_workspace(Context(ctx.clone()), ServiceId(2), TangleArg(true))
+            .await
+            .unwrap();
+        assert!(backend.images().is_empty());
+        assert!(!env.root().join("snapshots/2").exists());
+    }
+}
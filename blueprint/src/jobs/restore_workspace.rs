@@ -0,0 +1,106 @@
+use crate::archive::restore;
+use crate::error::{JobError, WorkspaceError};
+use crate::security::chown_workspace_dir;
+use crate::storage;
+use crate::{MyContext, ResourceTier, WORKSPACE_IMAGE};
+use blueprint_sdk::extract::Context;
+use blueprint_sdk::tangle::extract::{ServiceId, TangleArg, TangleResult};
+
+// Output of the restore workspace job
+#[derive(Default, Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct RestoreWorkspaceResult {
+    /// Whether the data is back in place, for the next `create_workspace` to use.
+    pub restored: bool,
+    /// When the restored archive was taken, in seconds since the Unix epoch.
+    pub archived_at: u64,
+    /// Why the workspace couldn't be restored, code `0` on success.
+    pub error: JobError,
+}
+
+#[blueprint_sdk::macros::debug_job]
+pub async fn restore_workspace(
+    Context(ctx): Context<MyContext>,
+    ServiceId(service_id): ServiceId,
+    TangleArg(_): TangleArg<bool>,
+) -> Result<TangleResult<RestoreWorkspaceResult>, Box<dyn std::error::Error + Send + Sync>> {
+    let _guard = ctx.locks.lock(service_id).await;
+    match restore_data(&ctx, service_id).await {
+        Ok(archived_at) => Ok(TangleResult(RestoreWorkspaceResult {
+            restored: true,
+            archived_at,
+            error: JobError::default(),
+        })),
+        Err(e) => {
+            // Report the failure on chain instead of dropping the result
+            let error = WorkspaceError::from(e);
+            tracing::error!("Failed to restore workspace {}: {}", service_id, error);
+            Ok(TangleResult(RestoreWorkspaceResult {
+                error: JobError::from(&error),
+                ..Default::default()
+            }))
+        }
+    }
+}
+
+// Unpack the latest archive of a destroyed workspace, returning when it was taken
+async fn restore_data(
+    ctx: &MyContext,
+    service_id: u64,
+) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(config) = ctx
+        .config
+        .archive
+        .as_ref()
+        .filter(|_| storage::is_persistent(ctx))
+    else {
+        return Err(WorkspaceError::InvalidParams(
+            "This operator doesn't archive workspace data".to_string(),
+        )
+        .into());
+    };
+    match ctx.workspaces.get(service_id) {
+        Some(record) if record.deleted_at.is_some() => {
+            return Err(WorkspaceError::InvalidParams(format!(
+                "Workspace {} is pending deletion, undelete it instead",
+                service_id
+            ))
+            .into());
+        }
+        Some(_) => {
+            return Err(WorkspaceError::InvalidParams(format!(
+                "Workspace {} is running, destroy it before restoring its data",
+                service_id
+            ))
+            .into());
+        }
+        None => {}
+    }
+
+    if storage::workspace_dir(ctx, service_id).await?.is_some() {
+        return Err(WorkspaceError::InvalidParams(format!(
+            "Workspace {} still has data, create it to use that data",
+            service_id
+        ))
+        .into());
+    }
+    let archive = config.latest(service_id)?.ok_or_else(|| {
+        WorkspaceError::InvalidParams(format!("No retained archive of workspace {}", service_id))
+    })?;
+
+    // The tier is only known once the workspace is created again, don't cap the data below
+    // what any tier may hold
+    let workspace_dir =
+        storage::create_workspace_dir(ctx, service_id, &ResourceTier::Large).await?;
+    if let Err(e) = restore(&archive, &workspace_dir).await {
+        storage::remove_workspace_dir(ctx, service_id).await?;
+        return Err(e);
+    }
+    let owner = ctx.config.security.data_owner(WORKSPACE_IMAGE);
+    chown_workspace_dir(&workspace_dir, owner);
+    tracing::info!(
+        "Restored workspace {} from {}",
+        service_id,
+        archive.path.display()
+    );
+    Ok(archive.archived_at)
+}
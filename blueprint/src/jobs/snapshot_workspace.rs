@@ -0,0 +1,131 @@
+use crate::MyContext;
+use crate::backend::ContainerState;
+use crate::error::{JobError, WorkspaceError};
+use crate::snapshot::{SnapshotInfo, SnapshotStore, image_repository, validate_name};
+use crate::storage;
+use blueprint_sdk::extract::Context;
+use blueprint_sdk::tangle::extract::{ServiceId, TangleArg, TangleResult};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// Input parameters for the snapshot workspace job
+#[derive(Default, Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct SnapshotWorkspaceParams {
+    /// Name of the snapshot, each snapshot under the same name gets the next version.
+    pub name: String,
+    /// Also commit the container's filesystem to an image, so that restoring brings back
+    /// what was installed outside of `/blueprint` too.
+    #[serde(default)]
+    pub commit: bool,
+}
+
+// Output of the snapshot workspace job
+#[derive(Default, Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct SnapshotWorkspaceResult {
+    /// The snapshot taken.
+    pub snapshot: SnapshotInfo,
+    /// Why the snapshot couldn't be taken, code `0` on success.
+    pub error: JobError,
+}
+
+/// Snapshot the `/blueprint` data of a running workspace, and optionally its container.
+#[blueprint_sdk::macros::debug_job]
+pub async fn snapshot_workspace(
+    Context(ctx): Context<MyContext>,
+    ServiceId(service_id): ServiceId,
+    TangleArg(params): TangleArg<SnapshotWorkspaceParams>,
+) -> Result<TangleResult<SnapshotWorkspaceResult>, BoxError> {
+    let _guard = ctx.locks.lock(service_id).await;
+    match snapshot(&ctx, service_id, &params).await {
+        Ok(snapshot) => Ok(TangleResult(SnapshotWorkspaceResult {
+            snapshot,
+            error: JobError::default(),
+        })),
+        Err(e) => {
+            // Report the failure on chain instead of dropping the result
+            let error = WorkspaceError::from(e);
+            tracing::error!("Failed to snapshot workspace {}: {}", service_id, error);
+            Ok(TangleResult(SnapshotWorkspaceResult {
+                error: JobError::from(&error),
+                ..Default::default()
+            }))
+        }
+    }
+}
+
+async fn snapshot(
+    ctx: &MyContext,
+    service_id: u64,
+    params: &SnapshotWorkspaceParams,
+) -> Result<SnapshotInfo, BoxError> {
+    let Some(data_dir) = &ctx.env.data_dir else {
+        return Err(WorkspaceError::InvalidParams(
+            "This operator has no data directory to keep snapshots in".to_string(),
+        )
+        .into());
+    };
+    let record = match ctx.workspaces.get(service_id) {
+        Some(record) if record.deleted_at.is_none() => record,
+        _ => {
+            return Err(WorkspaceError::InvalidParams(format!(
+                "Workspace {} isn't running",
+                service_id
+            ))
+            .into());
+        }
+    };
+    validate_name(&params.name)?;
+    let workspace_dir = storage::workspace_dir(ctx, service_id)
+        .await?
+        .ok_or_else(|| {
+            WorkspaceError::InvalidParams(format!("Workspace {} has no data", service_id))
+        })?;
+
+    let store = SnapshotStore::new(data_dir, service_id);
+    let version = store.next_version(&params.name)?;
+
+    let image = if params.commit {
+        let container = ctx
+            .backend
+            .find_workspace(ctx.blueprint_id(), service_id)
+            .await?
+            .filter(|container| container.state == ContainerState::Running)
+            .ok_or_else(|| {
+                WorkspaceError::InvalidParams(format!(
+                    "Workspace {} has no running container to commit",
+                    service_id
+                ))
+            })?;
+        let tag = format!("{}-{}", params.name, version);
+        ctx.backend
+            .commit(&container.id, &image_repository(service_id), &tag)
+            .await?
+    } else {
+        String::new()
+    };
+
+    let limit = record.tier.storage_limit();
+    match store
+        .take(&params.name, version, &workspace_dir, limit, image.clone())
+        .await
+    {
+        Ok(snapshot) => {
+            tracing::info!(
+                "Took snapshot {} version {} of workspace {}",
+                snapshot.name,
+                snapshot.version,
+                service_id
+            );
+            Ok(snapshot)
+        }
+        Err(e) => {
+            // Don't leave an image behind that no snapshot refers to
+            if !image.is_empty()
+                && let Err(e) = ctx.backend.remove_image(&image).await
+            {
+                tracing::warn!("Failed to remove snapshot image {}: {}", image, e);
+            }
+            Err(e)
+        }
+    }
+}
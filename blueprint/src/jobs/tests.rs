@@ -0,0 +1,62 @@
+use crate::backend::fake::TestEnv;
+use crate::{
+    CREATE_WORKSPACE_JOB_ID, CreateWorkspaceParams, CreateWorkspaceResult, MyContext, ResourceTier,
+    WORKSPACE_STATUS_JOB_ID, WorkspaceStatus, create_workspace, workspace_status,
+};
+use blueprint_sdk::Job;
+use blueprint_sdk::tangle::layers::TangleLayer;
+use blueprint_sdk::tangle::serde::{from_field, to_field};
+use blueprint_sdk::testing::tempfile::TempDir;
+use blueprint_sdk::testing::utils::tangle::{InputValue, TangleTestHarness};
+use color_eyre::Result;
+
+#[tokio::test]
+async fn it_creates_workspaces_through_tangle() -> Result<()> {
+    color_eyre::install()?;
+    let env = TestEnv::new();
+
+    let harness = TangleTestHarness::<MyContext>::setup(TempDir::new()?).await?;
+    let (mut test_env, service_id, _) = harness.setup_services::<1>(false).await?;
+    test_env.initialize().await?;
+    test_env.add_job(create_workspace.layer(TangleLayer)).await;
+    test_env.add_job(workspace_status.layer(TangleLayer)).await;
+    test_env.start(env.ctx.clone()).await?;
+
+    let params = CreateWorkspaceParams {
+        tier: ResourceTier::Small,
+        workspace_name: "harness".to_string(),
+        ..Default::default()
+    };
+    let call = harness
+        .submit_job(
+            service_id,
+            CREATE_WORKSPACE_JOB_ID as u8,
+            vec![to_field(params)?],
+        )
+        .await?;
+    let result = harness.wait_for_job_execution(service_id, call).await?;
+    let created: CreateWorkspaceResult = from_field(result.result[0].clone())?;
+    assert_eq!(created.error.code, 0);
+    assert!(!created.url.is_empty());
+    assert_eq!(env.backend.containers().len(), 1);
+    assert!(
+        env.root()
+            .join("workspaces")
+            .join(service_id.to_string())
+            .is_dir()
+    );
+
+    let call = harness
+        .submit_job(
+            service_id,
+            WORKSPACE_STATUS_JOB_ID as u8,
+            vec![InputValue::Bool(true)],
+        )
+        .await?;
+    let result = harness.wait_for_job_execution(service_id, call).await?;
+    let status: WorkspaceStatus = from_field(result.result[0].clone())?;
+    assert!(status.exists);
+    assert_eq!(status.url, created.url);
+    assert_eq!(status.error.code, 0);
+    Ok(())
+}
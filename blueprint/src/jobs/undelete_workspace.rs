@@ -0,0 +1,132 @@
+use super::create_workspace::bring_up;
+use crate::error::{JobError, WorkspaceError};
+use crate::{CreateWorkspaceParams, CreateWorkspaceResult, MyContext};
+use blueprint_sdk::extract::Context;
+use blueprint_sdk::tangle::extract::{ServiceId, TangleArg, TangleResult};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Bring a workspace pending deletion back up, with the data and settings it had.
+///
+/// Its endpoint is allocated anew, so clients need the URLs from the result.
+#[blueprint_sdk::macros::debug_job]
+pub async fn undelete_workspace(
+    Context(ctx): Context<MyContext>,
+    ServiceId(service_id): ServiceId,
+    TangleArg(_): TangleArg<bool>,
+) -> Result<TangleResult<CreateWorkspaceResult>, BoxError> {
+    let _guard = ctx.locks.lock(service_id).await;
+    match undelete(&ctx, service_id).await {
+        Ok(result) => Ok(TangleResult(result)),
+        Err(e) => {
+            // Report the failure on chain instead of dropping the result
+            let error = WorkspaceError::from(e);
+            tracing::error!("Failed to undelete workspace {}: {}", service_id, error);
+            Ok(TangleResult(CreateWorkspaceResult {
+                error: JobError::from(&error),
+                ..Default::default()
+            }))
+        }
+    }
+}
+
+async fn undelete(ctx: &MyContext, service_id: u64) -> Result<CreateWorkspaceResult, BoxError> {
+    let record = match ctx.workspaces.get(service_id) {
+        Some(record) if record.deleted_at.is_some() => record,
+        _ => {
+            return Err(WorkspaceError::InvalidParams(format!(
+                "Workspace {} is not pending deletion",
+                service_id
+            ))
+            .into());
+        }
+    };
+
+    let purge_at =
+        record.deleted_at.unwrap_or_default() + ctx.config.delete_grace.unwrap_or_default();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    if purge_at <= now {
+        return Err(WorkspaceError::InvalidParams(format!(
+            "The grace period of workspace {} is over",
+            service_id
+        ))
+        .into());
+    }
+
+    let params = CreateWorkspaceParams::from_record(&record)?;
+    let result = bring_up(
+        ctx,
+        service_id,
+        record.call_id,
+        &params,
+        record.image.as_deref(),
+    )
+    .await?;
+    tracing::info!("Undeleted workspace {}", service_id);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::fake::TestEnv;
+    use crate::backend::{ContainerState, WorkspaceBackend};
+    use crate::{create_workspace, destroy_workspace, workspace_status};
+    use blueprint_sdk::tangle::extract::CallId;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn it_undeletes_within_the_grace_period() {
+        let mut env = TestEnv::new();
+        Arc::make_mut(&mut env.ctx.config).delete_grace = Some(60);
+        let TestEnv { backend, ctx, .. } = &env;
+        let create = |call_id| {
+            create_workspace(
+                Context(ctx.clone()),
+                ServiceId(3),
+                CallId(call_id),
+                TangleArg(CreateWorkspaceParams {
+                    workspace_name: "agent".to_string(),
+                    ..Default::default()
+                }),
+            )
+        };
+        let state = || async {
+            let container = backend.inspect("mcp-svc-3").await.unwrap().unwrap();
+            container.state
+        };
+        let purge_at = || async {
+            workspace_status(Context(ctx.clone()), ServiceId(3), TangleArg(true))
+                .await
+                .unwrap()
+                .0
+                .purge_at
+        };
+
+        assert_eq!(create(1).await.unwrap().0.error.code, 0);
+        let destroyed = destroy_workspace(Context(ctx.clone()), ServiceId(3), TangleArg(true))
+            .await
+            .unwrap()
+            .0;
+        assert!(destroyed.destroyed && destroyed.purge_at > 0);
+        assert_eq!(state().await, ContainerState::Exited);
+        assert_eq!(purge_at().await, destroyed.purge_at);
+        assert_eq!(create(2).await.unwrap().0.error.code, 1);
+
+        let undeleted = undelete_workspace(Context(ctx.clone()), ServiceId(3), TangleArg(true))
+            .await
+            .unwrap()
+            .0;
+        assert_eq!(undeleted.error.code, 0);
+        assert!(!undeleted.url.is_empty());
+        assert_eq!(state().await, ContainerState::Running);
+        assert_eq!(purge_at().await, 0);
+
+        let again = undelete_workspace(Context(ctx.clone()), ServiceId(3), TangleArg(true))
+            .await
+            .unwrap()
+            .0;
+        assert_eq!(again.error.code, 1);
+    }
+}
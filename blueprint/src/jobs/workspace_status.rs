@@ -0,0 +1,76 @@
+use crate::MyContext;
+use crate::egress::EgressPolicy;
+use crate::error::{JobError, WorkspaceError};
+use blueprint_sdk::extract::Context;
+use blueprint_sdk::tangle::extract::{ServiceId, TangleArg, TangleResult};
+
+// Output of the workspace status job
+#[derive(Default, Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct WorkspaceStatus {
+    /// Whether this operator has a record of the workspace.
+    pub exists: bool,
+    /// Container state as reported by the backend (`running`, `exited`, ...), `missing` when
+    /// the container is gone.
+    pub state: String,
+    /// Health check status (`healthy`, `starting`, ...), empty without a health check.
+    pub health: String,
+    pub url: String,
+    pub ws_url: String,
+    /// Outbound traffic policy applied to the workspace.
+    pub egress: EgressPolicy,
+    /// OCI runtime the workspace runs under.
+    pub runtime: String,
+    /// When the workspace gets purged if it is pending deletion, in seconds since the Unix
+    /// epoch. `0` while it is live.
+    pub purge_at: u64,
+    /// Why the status couldn't be determined, code `0` on success.
+    pub error: JobError,
+}
+
+#[blueprint_sdk::macros::debug_job]
+pub async fn workspace_status(
+    Context(ctx): Context<MyContext>,
+    ServiceId(service_id): ServiceId,
+    TangleArg(_): TangleArg<bool>,
+) -> Result<TangleResult<WorkspaceStatus>, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(record) = ctx.workspaces.get(service_id) else {
+        return Ok(TangleResult(WorkspaceStatus::default()));
+    };
+
+    let (state, health) = match ctx
+        .backend
+        .find_workspace(ctx.blueprint_id(), service_id)
+        .await
+        .map_err(WorkspaceError::from)
+    {
+        Err(error) => {
+            return Ok(TangleResult(WorkspaceStatus {
+                exists: true,
+                error: JobError::from(&error),
+                ..Default::default()
+            }));
+        }
+        Ok(Some(info)) => (
+            info.state.to_string(),
+            info.health
+                .map(|health| health.to_string())
+                .unwrap_or_default(),
+        ),
+        Ok(None) => ("missing".to_string(), String::new()),
+    };
+
+    Ok(TangleResult(WorkspaceStatus {
+        exists: true,
+        state,
+        health,
+        url: record.url,
+        ws_url: record.ws_url,
+        egress: record.egress,
+        runtime: record.runtime,
+        purge_at: record
+            .deleted_at
+            .map(|deleted_at| deleted_at + ctx.config.delete_grace.unwrap_or_default())
+            .unwrap_or_default(),
+        error: JobError::default(),
+    }))
+}
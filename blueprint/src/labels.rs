@@ -0,0 +1,78 @@
+//! Labels marking the engine resources this blueprint owns.
+//!
+//! Every workspace container and network carries them, and workspaces are looked up by
+//! their [`ownership`] labels rather than by name, so containers that merely share the
+//! `mcp-svc-` prefix are never touched. Only containers created before labels were added are
+//! still found by their exact name, as long as they carry no blueprint's labels.
+
+use crate::ResourceTier;
+use std::collections::HashMap;
+
+pub type Labels = HashMap<String, String>;
+
+pub const BLUEPRINT_ID: &str = "network.tangle.mcp.blueprint-id";
+pub const SERVICE_ID: &str = "network.tangle.mcp.service-id";
+pub const WORKSPACE_NAME: &str = "network.tangle.mcp.workspace-name";
+pub const OWNER: &str = "network.tangle.mcp.owner";
+pub const TIER: &str = "network.tangle.mcp.tier";
+/// Seconds since the Unix epoch.
+pub const CREATED_AT: &str = "network.tangle.mcp.created-at";
+/// Version of the blueprint that created the resource.
+pub const VERSION: &str = "network.tangle.mcp.version";
+
+/// Labels every resource of this blueprint carries, the filter used to find all of them.
+pub fn blueprint(blueprint_id: u64) -> Labels {
+    Labels::from([(BLUEPRINT_ID.to_string(), blueprint_id.to_string())])
+}
+
+/// Labels identifying the resources of one workspace.
+pub fn ownership(blueprint_id: u64, service_id: u64) -> Labels {
+    let mut labels = blueprint(blueprint_id);
+    labels.insert(SERVICE_ID.to_string(), service_id.to_string());
+    labels
+}
+
+// Descriptive labels of a workspace on top of its ownership ones
+pub struct WorkspaceLabels<'a> {
+    pub blueprint_id: u64,
+    pub service_id: u64,
+    pub workspace_name: &'a str,
+    pub owner: &'a str,
+    pub tier: &'a ResourceTier,
+    pub created_at: u64,
+}
+
+impl WorkspaceLabels<'_> {
+    pub fn to_labels(&self) -> Labels {
+        let tier = match self.tier {
+            ResourceTier::Small => "small",
+            ResourceTier::Medium => "medium",
+            ResourceTier::Large => "large",
+        };
+
+        let mut labels = ownership(self.blueprint_id, self.service_id);
+        labels.extend([
+            (WORKSPACE_NAME.to_string(), self.workspace_name.to_string()),
+            (OWNER.to_string(), self.owner.to_string()),
+            (TIER.to_string(), tier.to_string()),
+            (CREATED_AT.to_string(), self.created_at.to_string()),
+            (VERSION.to_string(), env!("CARGO_PKG_VERSION").to_string()),
+        ]);
+        labels
+    }
+}
+
+/// `key=value` filters matching resources that carry all of `labels`.
+pub fn filters(labels: &Labels) -> Vec<String> {
+    let mut filters: Vec<_> = labels
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect();
+    filters.sort();
+    filters
+}
+
+/// Service ID a resource belongs to, if it is labeled with one.
+pub fn service_id(labels: &Labels) -> Option<u64> {
+    labels.get(SERVICE_ID)?.parse().ok()
+}
@@ -1,27 +1,140 @@
 use blueprint_sdk::runner::config::BlueprintEnvironment;
-use docktopus::bollard::Docker;
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+pub mod archive;
+pub mod auth;
+pub mod backend;
+use backend::WorkspaceBackend;
+pub mod backup;
+use backup::Backups;
 
 // Re-export jobs
 mod jobs;
 pub use jobs::*;
 
+mod bridge;
+pub use bridge::{StdioBridge, WorkspaceBridges, WsBridge};
+
+pub mod config;
+use config::OperatorConfig;
+
+pub mod egress;
+pub mod error;
+pub mod files;
+pub use error::{JobError, WorkspaceError};
+pub mod labels;
+pub mod locks;
+use locks::WorkspaceLocks;
+pub mod network;
+pub mod rollback;
+pub mod runtime;
+pub mod security;
+pub mod seed;
+pub mod snapshot;
+pub mod storage;
+
+pub mod state;
+use state::WorkspaceStore;
+
+mod tls;
+pub use tls::{TlsProvider, TlsProxy, WorkspaceTls};
+
 // Blueprint context
 #[derive(Clone)]
 pub struct MyContext {
     pub env: BlueprintEnvironment,
-    pub docker: Arc<Docker>,
+    /// Container backend workspaces run on.
+    pub backend: Arc<dyn WorkspaceBackend>,
+    pub config: Arc<OperatorConfig>,
+    /// Issues workspace certificates, `None` when TLS is disabled.
+    pub tls: Option<Arc<TlsProvider>>,
+    /// Operator-side bridges of the running workspaces, keyed by service ID.
+    pub bridges: Arc<Mutex<HashMap<u64, WorkspaceBridges>>>,
+    /// Records of the workspaces created by this operator.
+    pub workspaces: Arc<WorkspaceStore>,
+    /// Serializes lifecycle jobs of each workspace.
+    pub locks: Arc<WorkspaceLocks>,
+    /// Backs workspace data up off the host, `None` when backups aren't configured.
+    pub backups: Option<Arc<Backups>>,
 }
 
 impl MyContext {
-    pub fn new(
+    /// ID of the blueprint this operator runs, `0` outside of Tangle (e.g. in tests).
+    pub fn blueprint_id(&self) -> u64 {
+        self.env
+            .protocol_settings
+            .tangle()
+            .map(|settings| settings.blueprint_id)
+            .unwrap_or_default()
+    }
+
+    pub async fn new(
         env: BlueprintEnvironment,
     ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
-        let docker = Docker::connect_with_local_defaults()?;
+        let mut config = OperatorConfig::from_env()?;
+        let backend = backend::connect(&config).await?;
+        // Fail at startup rather than on the first workspace using a missing runtime
+        config.runtimes.validate(backend.as_ref()).await?;
+        let tls = TlsProvider::new(&config.tls, env.data_dir.as_deref())?;
+        let workspaces = WorkspaceStore::open(env.data_dir.as_deref())?;
+        let backups = config.backup.as_ref().map(Backups::new).transpose()?;
 
-        Ok(Self {
+        let ctx = Self {
             env,
-            docker: Arc::new(docker),
-        })
+            backend,
+            config: Arc::new(config),
+            tls: tls.map(Arc::new),
+            bridges: Arc::new(Mutex::new(HashMap::new())),
+            workspaces: Arc::new(workspaces),
+            locks: Arc::new(WorkspaceLocks::default()),
+            backups: backups.map(Arc::new),
+        };
+        if let Some(archive) = &ctx.config.archive {
+            archive.spawn_purger();
+        }
+        spawn_deletion_purger(ctx.clone());
+        backup::spawn_backups(ctx.clone());
+        if let Some(port) = ctx.config.files_port {
+            files::spawn_file_api(ctx.clone(), port).await?;
+        }
+        // Without persisted records every workspace would look orphaned
+        if ctx.env.data_dir.is_some()
+            && let Err(e) = ctx.reconcile().await
+        {
+            tracing::warn!("Failed to clean up orphaned workspaces: {}", e);
+        }
+        Ok(ctx)
+    }
+
+    // Remove the containers of this blueprint that have no record, e.g. left behind by a
+    // crash before the workspace was recorded
+    async fn reconcile(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let containers = self
+            .backend
+            .list(&labels::blueprint(self.blueprint_id()))
+            .await?;
+        for container in containers {
+            let Some(service_id) = labels::service_id(&container.labels) else {
+                continue;
+            };
+            if self.workspaces.get(service_id).is_some() {
+                continue;
+            }
+
+            tracing::warn!(
+                "Removing orphaned container {} of workspace {}",
+                container.name,
+                service_id
+            );
+            self.backend.remove(&container.id).await?;
+            if let Err(e) = egress::remove_egress_policy(service_id).await {
+                tracing::warn!("Failed to remove workspace egress rules: {}", e);
+            }
+            if let Err(e) = self.backend.remove_network(service_id).await {
+                tracing::warn!("Failed to remove workspace network: {}", e);
+            }
+        }
+        Ok(())
     }
 }
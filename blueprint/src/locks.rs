@@ -0,0 +1,164 @@
+//! Serialization of lifecycle jobs per workspace.
+//!
+//! Jobs for the same service ID run one after the other, in the order they asked for the
+//! lock (Tokio's mutex is fair), while jobs for different workspaces run in parallel.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+
+#[derive(Default)]
+pub struct WorkspaceLocks {
+    locks: Mutex<HashMap<u64, Arc<AsyncMutex<()>>>>,
+}
+
+// Held for the duration of a job, frees the lock entry once nobody else waits for it
+pub struct WorkspaceGuard<'a> {
+    locks: &'a WorkspaceLocks,
+    service_id: u64,
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl WorkspaceLocks {
+    /// Wait until every job queued before for `service_id` is done.
+    pub async fn lock(&self, service_id: u64) -> WorkspaceGuard<'_> {
+        let lock = self
+            .locks
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .entry(service_id)
+            .or_default()
+            .clone();
+
+        WorkspaceGuard {
+            locks: self,
+            service_id,
+            guard: Some(lock.lock_owned().await),
+        }
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.locks
+            .lock()
+            .map(|locks| locks.len())
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for WorkspaceGuard<'_> {
+    fn drop(&mut self) {
+        let Some(guard) = self.guard.take() else {
+            return;
+        };
+        let mut locks = self
+            .locks
+            .locks
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        // Only the map and this guard hold the lock, waiters clone it under the map's lock
+        if Arc::strong_count(OwnedMutexGuard::mutex(&guard)) == 2 {
+            locks.remove(&self.service_id);
+        }
+        drop(guard);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::backend::fake::TestEnv;
+    use crate::{
+        CreateWorkspaceParams, MyContext, create_workspace, destroy_workspace, workspace_status,
+    };
+    use blueprint_sdk::extract::Context;
+    use blueprint_sdk::tangle::extract::{CallId, ServiceId, TangleArg};
+
+    fn create(ctx: &MyContext, service_id: u64) -> tokio::task::JoinHandle<String> {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            let params = CreateWorkspaceParams {
+                workspace_name: format!("workspace-{}", service_id),
+                ..Default::default()
+            };
+            let result = create_workspace(
+                Context(ctx),
+                ServiceId(service_id),
+                CallId(service_id),
+                TangleArg(params),
+            )
+            .await
+            .unwrap();
+            result.0.url
+        })
+    }
+
+    fn destroy(ctx: &MyContext, service_id: u64) -> tokio::task::JoinHandle<bool> {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            let result = destroy_workspace(Context(ctx), ServiceId(service_id), TangleArg(true))
+                .await
+                .unwrap();
+            result.0.destroyed
+        })
+    }
+
+    async fn exists(ctx: &MyContext, service_id: u64) -> bool {
+        workspace_status(Context(ctx.clone()), ServiceId(service_id), TangleArg(true))
+            .await
+            .unwrap()
+            .0
+            .exists
+    }
+
+    #[tokio::test]
+    async fn it_runs_jobs_of_a_workspace_in_order() {
+        let env = TestEnv::new();
+        let (backend, ctx) = (&env.backend, &env.ctx);
+
+        // Without the lock the destroy would run while the container is being created
+        let created = create(ctx, 1);
+        tokio::task::yield_now().await;
+        let destroyed = destroy(ctx, 1);
+        let recreated = {
+            tokio::task::yield_now().await;
+            create(ctx, 1)
+        };
+
+        assert!(!created.await.unwrap().is_empty());
+        assert!(destroyed.await.unwrap());
+        assert!(!recreated.await.unwrap().is_empty());
+        assert!(exists(ctx, 1).await);
+        assert_eq!(backend.containers(), vec!["mcp-svc-1".to_string()]);
+        assert_eq!(
+            backend.events(),
+            [
+                "create mcp-svc-1",
+                "start mcp-svc-1",
+                "stop mcp-svc-1",
+                "remove mcp-svc-1",
+                "create mcp-svc-1",
+                "start mcp-svc-1",
+            ]
+        );
+        assert_eq!(ctx.locks.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn it_runs_jobs_of_different_workspaces_in_parallel() {
+        let env = TestEnv::new();
+        let (backend, ctx) = (&env.backend, &env.ctx);
+
+        let jobs = [create(ctx, 1), create(ctx, 2)];
+        for job in jobs {
+            assert!(!job.await.unwrap().is_empty());
+        }
+        assert_eq!(backend.max_concurrent_creates(), 2);
+
+        let jobs = [destroy(ctx, 1), destroy(ctx, 2)];
+        for job in jobs {
+            assert!(job.await.unwrap());
+        }
+        assert!(backend.containers().is_empty());
+        assert!(!exists(ctx, 1).await && !exists(ctx, 2).await);
+    }
+}
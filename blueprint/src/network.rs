@@ -0,0 +1,222 @@
+//! Dedicated Docker network per workspace.
+//!
+//! Every workspace container gets its own bridge network instead of Docker's shared default
+//! bridge, so workspaces can't reach each other. If the operator itself runs in a container
+//! (`MCP_OPERATOR_CONTAINER`), it is attached as well so it can reach the workspace directly.
+
+use docktopus::bollard::Docker;
+use docktopus::bollard::errors::Error as DockerError;
+use docktopus::bollard::network::{
+    ConnectNetworkOptions, CreateNetworkOptions, DisconnectNetworkOptions, InspectNetworkOptions,
+};
+use std::collections::HashMap;
+
+pub fn workspace_network_name(service_id: u64) -> String {
+    format!("mcp-net-{}", service_id)
+}
+
+/// Create the network of a workspace, reusing it if a previous attempt left it behind.
+pub async fn create_workspace_network(
+    docker: &Docker,
+    service_id: u64,
+    operator_container: Option<&str>,
+    options: HashMap<String, String>,
+    labels: &HashMap<String, String>,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let name = workspace_network_name(service_id);
+
+    let exists = match docker
+        .inspect_network(&name, None::<InspectNetworkOptions<String>>)
+        .await
+    {
+        Ok(_) => true,
+        Err(DockerError::DockerResponseServerError {
+            status_code: 404, ..
+        }) => false,
+        Err(e) => return Err(e.into()),
+    };
+
+    if !exists {
+        docker
+            .create_network(CreateNetworkOptions {
+                name: name.clone(),
+                driver: "bridge".to_string(),
+                check_duplicate: true,
+                options,
+                labels: labels.clone(),
+                ..Default::default()
+            })
+            .await?;
+        tracing::info!("Created network {}", name);
+    }
+
+    if let Some(container) = operator_container {
+        match docker
+            .connect_network(
+                &name,
+                ConnectNetworkOptions {
+                    container: container.to_string(),
+                    ..Default::default()
+                },
+            )
+            .await
+        {
+            Ok(()) => {}
+            // Already connected
+            Err(DockerError::DockerResponseServerError {
+                status_code: 403, ..
+            }) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(name)
+}
+
+/// How an engine names the host interfaces of its bridge networks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BridgeNaming {
+    /// After the network ID (`br-{id}`) unless set in the network's options, like Docker.
+    NetworkId,
+    /// Only known from the network's options, like Podman's netavark (`podman1`, ...).
+    Reported,
+}
+
+/// Name of the host interface backing the network of a workspace.
+pub async fn workspace_bridge_interface(
+    docker: &Docker,
+    service_id: u64,
+    naming: BridgeNaming,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let name = workspace_network_name(service_id);
+    let network = docker
+        .inspect_network(&name, None::<InspectNetworkOptions<String>>)
+        .await?;
+
+    bridge_name(naming, network.options.as_ref(), network.id.as_deref())
+        .ok_or_else(|| format!("The engine doesn't report the bridge of network {}", name).into())
+}
+
+fn bridge_name(
+    naming: BridgeNaming,
+    options: Option<&HashMap<String, String>>,
+    id: Option<&str>,
+) -> Option<String> {
+    if let Some(name) = options.and_then(|options| options.get("com.docker.network.bridge.name")) {
+        return Some(name.clone());
+    }
+    match naming {
+        BridgeNaming::NetworkId => id.map(|id| format!("br-{}", &id[..id.len().min(12)])),
+        BridgeNaming::Reported => None,
+    }
+}
+
+/// Remove the network of a workspace, succeeding if it is already gone.
+pub async fn remove_workspace_network(
+    docker: &Docker,
+    service_id: u64,
+    operator_container: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let name = workspace_network_name(service_id);
+
+    // The network can't be removed while the operator is still attached to it
+    if let Some(container) = operator_container {
+        let _ = docker
+            .disconnect_network(
+                &name,
+                DisconnectNetworkOptions {
+                    container: container.to_string(),
+                    force: true,
+                },
+            )
+            .await;
+    }
+
+    match docker.remove_network(&name).await {
+        Ok(()) => {
+            tracing::info!("Removed network {}", name);
+            Ok(())
+        }
+        Err(DockerError::DockerResponseServerError {
+            status_code: 404, ..
+        }) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::fake::TestEnv;
+    use crate::labels;
+    use crate::{CreateWorkspaceParams, create_workspace, destroy_workspace};
+    use blueprint_sdk::extract::Context;
+    use blueprint_sdk::tangle::extract::{CallId, ServiceId, TangleArg};
+
+    #[test]
+    fn it_names_bridges_like_the_engine() {
+        let id = Some("0123456789abcdef");
+        assert_eq!(
+            bridge_name(BridgeNaming::NetworkId, None, id).as_deref(),
+            Some("br-0123456789ab")
+        );
+        assert_eq!(bridge_name(BridgeNaming::Reported, None, id), None);
+
+        let options = HashMap::from([(
+            "com.docker.network.bridge.name".to_string(),
+            "podman1".to_string(),
+        )]);
+        for naming in [BridgeNaming::NetworkId, BridgeNaming::Reported] {
+            assert_eq!(
+                bridge_name(naming, Some(&options), id).as_deref(),
+                Some("podman1")
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn it_creates_a_labeled_network_and_removes_it_on_destroy() {
+        let env = TestEnv::new();
+        let TestEnv { backend, ctx, .. } = &env;
+        let created = create_workspace(
+            Context(ctx.clone()),
+            ServiceId(3),
+            CallId(1),
+            TangleArg(CreateWorkspaceParams::default()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(created.0.error.code, 0);
+        let labels = backend.network("mcp-net-3").unwrap();
+        for (key, value) in labels::ownership(ctx.blueprint_id(), 3) {
+            assert_eq!(labels.get(&key), Some(&value));
+        }
+
+        destroy_workspace(Context(ctx.clone()), ServiceId(3), TangleArg(true))
+            .await
+            .unwrap();
+        assert!(backend.network("mcp-net-3").is_none());
+    }
+
+    #[tokio::test]
+    async fn it_removes_the_network_when_create_fails() {
+        let env = TestEnv::new();
+        let TestEnv { backend, ctx, .. } = &env;
+        // The fake backend can't attach to stdio servers
+        let params = CreateWorkspaceParams {
+            stdio_command: vec!["npx".to_string()],
+            ..Default::default()
+        };
+        let created = create_workspace(
+            Context(ctx.clone()),
+            ServiceId(4),
+            CallId(1),
+            TangleArg(params),
+        )
+        .await
+        .unwrap();
+        assert_ne!(created.0.error.code, 0);
+        assert!(backend.containers().is_empty());
+        assert!(backend.network("mcp-net-4").is_none());
+    }
+}
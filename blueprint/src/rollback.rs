@@ -0,0 +1,97 @@
+//! Compensating actions for multi-step operations.
+//!
+//! Every step that leaves something behind (a directory, a network, a container) registers
+//! how to undo it. On failure the actions run in reverse order; once the operation succeeds
+//! they are discarded with [`Rollback::commit`]. If the operation is dropped half way, e.g.
+//! because the runner shuts down, the pending actions are spawned onto the runtime.
+
+use std::future::Future;
+use std::pin::Pin;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+type Compensation = Pin<Box<dyn Future<Output = Result<(), BoxError>> + Send>>;
+
+#[derive(Default)]
+pub struct Rollback {
+    steps: Vec<(&'static str, Compensation)>,
+}
+
+impl Rollback {
+    /// Register how to undo the step that just succeeded.
+    pub fn push<F>(&mut self, step: &'static str, compensation: F)
+    where
+        F: Future<Output = Result<(), BoxError>> + Send + 'static,
+    {
+        self.steps.push((step, Box::pin(compensation)));
+    }
+
+    /// The operation succeeded, keep everything.
+    pub fn commit(mut self) {
+        self.steps.clear();
+    }
+
+    /// Undo every registered step, most recent first. Failures are logged and don't stop
+    /// the remaining steps from being undone.
+    pub async fn run(mut self) {
+        run_steps(std::mem::take(&mut self.steps)).await;
+    }
+}
+
+async fn run_steps(steps: Vec<(&'static str, Compensation)>) {
+    for (step, compensation) in steps.into_iter().rev() {
+        match compensation.await {
+            Ok(()) => tracing::info!("Rolled back {}", step),
+            Err(e) => tracing::warn!("Failed to roll back {}: {}", step, e),
+        }
+    }
+}
+
+impl Drop for Rollback {
+    fn drop(&mut self) {
+        if self.steps.is_empty() {
+            return;
+        }
+        let steps = std::mem::take(&mut self.steps);
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn(run_steps(steps));
+            }
+            Err(_) => tracing::warn!("{} steps left without a runtime to roll back", steps.len()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[tokio::test]
+    async fn it_undoes_steps_in_reverse_order_unless_committed() {
+        let undone = Arc::new(Mutex::new(Vec::new()));
+        let rollback = |steps: &[&'static str]| {
+            let mut rollback = Rollback::default();
+            for &step in steps {
+                let undone = undone.clone();
+                rollback.push(step, async move {
+                    undone.lock().unwrap().push(step);
+                    if step == "network" {
+                        return Err("still in use".into());
+                    }
+                    Ok(())
+                });
+            }
+            rollback
+        };
+
+        rollback(&["directory", "network", "container"]).run().await;
+        assert_eq!(
+            *undone.lock().unwrap(),
+            vec!["container", "network", "directory"]
+        );
+
+        undone.lock().unwrap().clear();
+        rollback(&["directory", "network"]).commit();
+        assert!(undone.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,112 @@
+//! OCI runtime selection for workspace containers.
+//!
+//! Workspaces run under the daemon's default runtime (usually `runc`) unless the operator
+//! picks another one, operator-wide or per tier, e.g. gVisor (`runsc`) or Kata Containers
+//! (`kata-runtime`) for untrusted MCP servers. Runtimes have to be registered with the
+//! Docker daemon, which is checked at startup.
+
+use crate::ResourceTier;
+use crate::backend::WorkspaceBackend;
+
+#[derive(Default, Debug, Clone)]
+pub struct RuntimeConfig {
+    /// Runtime of every tier without its own (`MCP_RUNTIME`).
+    pub default: Option<String>,
+    /// Per tier runtimes (`MCP_RUNTIME_SMALL`, `MCP_RUNTIME_MEDIUM`, `MCP_RUNTIME_LARGE`).
+    pub small: Option<String>,
+    pub medium: Option<String>,
+    pub large: Option<String>,
+    /// The daemon's default runtime, filled in by [`RuntimeConfig::validate`].
+    pub daemon_default: String,
+}
+
+impl RuntimeConfig {
+    pub fn from_env() -> Self {
+        let var = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+        Self {
+            default: var("MCP_RUNTIME"),
+            small: var("MCP_RUNTIME_SMALL"),
+            medium: var("MCP_RUNTIME_MEDIUM"),
+            large: var("MCP_RUNTIME_LARGE"),
+            daemon_default: String::new(),
+        }
+    }
+
+    /// Runtime to set on containers of `tier`, `None` for the daemon's default.
+    pub fn for_tier(&self, tier: &ResourceTier) -> Option<&str> {
+        let runtime = match tier {
+            ResourceTier::Small => &self.small,
+            ResourceTier::Medium => &self.medium,
+            ResourceTier::Large => &self.large,
+        };
+        runtime.as_deref().or(self.default.as_deref())
+    }
+
+    /// Name of the runtime workspaces of `tier` actually run under.
+    pub fn resolved(&self, tier: &ResourceTier) -> String {
+        self.for_tier(tier)
+            .unwrap_or(&self.daemon_default)
+            .to_string()
+    }
+
+    /// Check that every configured runtime is registered with the daemon.
+    pub async fn validate(
+        &mut self,
+        backend: &dyn WorkspaceBackend,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let runtimes = backend.runtimes().await?;
+        self.daemon_default = runtimes.default;
+
+        for runtime in [&self.default, &self.small, &self.medium, &self.large]
+            .into_iter()
+            .flatten()
+        {
+            if !runtimes.available.contains(runtime) {
+                return Err(format!(
+                    "Runtime {} is not registered with the Docker daemon (available: {})",
+                    runtime,
+                    runtimes.available.join(", ")
+                )
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::fake::FakeBackend;
+
+    #[tokio::test]
+    async fn it_runs_tiers_without_a_runtime_under_the_daemon_default() {
+        let mut config = RuntimeConfig::default();
+        config.validate(&FakeBackend::default()).await.unwrap();
+        assert_eq!(config.for_tier(&ResourceTier::Medium), None);
+        assert_eq!(config.resolved(&ResourceTier::Medium), "runc");
+    }
+
+    #[test]
+    fn it_prefers_the_runtime_of_a_tier_over_the_operator_wide_one() {
+        let config = RuntimeConfig {
+            default: Some("runsc".to_string()),
+            large: Some("kata-runtime".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(config.for_tier(&ResourceTier::Small), Some("runsc"));
+        assert_eq!(config.for_tier(&ResourceTier::Medium), Some("runsc"));
+        assert_eq!(config.for_tier(&ResourceTier::Large), Some("kata-runtime"));
+    }
+
+    #[tokio::test]
+    async fn it_rejects_runtimes_the_daemon_does_not_know() {
+        let mut config = RuntimeConfig {
+            small: Some("runsc".to_string()),
+            ..Default::default()
+        };
+        let error = config.validate(&FakeBackend::default()).await.unwrap_err();
+        assert!(error.to_string().contains("runsc"));
+    }
+}
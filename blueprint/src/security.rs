@@ -0,0 +1,285 @@
+//! Hardened security profile of workspace containers.
+//!
+//! Every workspace runs as an unprivileged user with all capabilities dropped,
+//! `no-new-privileges`, a read-only root filesystem (with tmpfs scratch space), a pids
+//! limit and Docker's default seccomp and AppArmor profiles unless the operator sets its
+//! own. Images that can't run like this get individual relaxations from the operator's
+//! image profiles file (`MCP_IMAGE_PROFILES`), e.g.
+//!
+//! ```json
+//! { "tangle-mcp:0.1.0": { "cap_add": ["NET_BIND_SERVICE"], "tmpfs": ["/home/node"] } }
+//! ```
+
+use crate::backend::ContainerSpec;
+use std::collections::HashMap;
+
+/// User workspaces run as unless relaxed.
+pub const WORKSPACE_USER: &str = "1000:1000";
+
+const DEFAULT_PIDS_LIMIT: i64 = 256;
+// Scratch space on the otherwise read-only root filesystem
+const DEFAULT_TMPFS: &[&str] = &["/tmp", "/run"];
+const TMPFS_OPTIONS: &str = "rw,nosuid,nodev,size=256m";
+
+// Parts of the hardened profile an image is allowed to skip
+#[derive(Default, Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecurityRelaxations {
+    /// User (`uid:gid` or name) to run as instead of `1000:1000`.
+    pub user: Option<String>,
+    /// Capabilities added back after dropping all of them.
+    pub cap_add: Vec<String>,
+    pub writable_rootfs: bool,
+    /// Additional tmpfs mounts, for images writing outside of `/tmp` and `/run`.
+    pub tmpfs: Vec<String>,
+    pub allow_privilege_escalation: bool,
+    /// Seccomp profile path, or `unconfined`, instead of the operator's.
+    pub seccomp_profile: Option<String>,
+    /// AppArmor profile name, or `unconfined`, instead of the operator's.
+    pub apparmor_profile: Option<String>,
+    pub pids_limit: Option<i64>,
+}
+
+// Operator-wide settings of the hardened profile
+#[derive(Default, Debug, Clone)]
+pub struct SecurityConfig {
+    /// Contents of the seccomp profile (`MCP_SECCOMP_PROFILE`), Docker's default when unset.
+    pub seccomp_profile: Option<String>,
+    /// AppArmor profile name (`MCP_APPARMOR_PROFILE`), Docker's default when unset.
+    pub apparmor_profile: Option<String>,
+    /// Relaxations keyed by image (`MCP_IMAGE_PROFILES`).
+    pub image_profiles: HashMap<String, SecurityRelaxations>,
+}
+
+/// Numeric user, and group, the data of a workspace belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataOwner {
+    pub uid: u32,
+    /// Left as it is when the container's group is only known by name.
+    pub gid: Option<u32>,
+}
+
+impl DataOwner {
+    /// Owner for a container user (`uid[:gid]`), `None` for a user name only the image can
+    /// resolve.
+    pub fn of_user(user: &str) -> Option<Self> {
+        let (uid, gid) = match user.split_once(':') {
+            Some((uid, gid)) => (uid, gid.parse().ok()),
+            None => (user, None),
+        };
+        Some(Self {
+            uid: uid.parse().ok()?,
+            gid,
+        })
+    }
+}
+
+impl SecurityConfig {
+    /// User containers of `image` run as.
+    pub fn user(&self, image: &str) -> &str {
+        self.image_profiles
+            .get(image)
+            .and_then(|relaxations| relaxations.user.as_deref())
+            .unwrap_or(WORKSPACE_USER)
+    }
+
+    /// Who the data of workspaces running `image` belongs to, `None` when their user is a
+    /// name.
+    pub fn data_owner(&self, image: &str) -> Option<DataOwner> {
+        DataOwner::of_user(self.user(image))
+    }
+
+    /// Apply the hardened profile for the image of `spec`.
+    pub fn apply(
+        &self,
+        spec: &mut ContainerSpec,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let relaxations = self
+            .image_profiles
+            .get(&spec.image)
+            .cloned()
+            .unwrap_or_default();
+
+        spec.user = Some(
+            relaxations
+                .user
+                .unwrap_or_else(|| WORKSPACE_USER.to_string()),
+        );
+        spec.cap_drop = vec!["ALL".to_string()];
+        spec.cap_add = relaxations.cap_add;
+        spec.readonly_rootfs = !relaxations.writable_rootfs;
+        spec.tmpfs = DEFAULT_TMPFS
+            .iter()
+            .map(|path| path.to_string())
+            .chain(relaxations.tmpfs)
+            .map(|path| (path, TMPFS_OPTIONS.to_string()))
+            .collect();
+        spec.pids_limit = Some(relaxations.pids_limit.unwrap_or(DEFAULT_PIDS_LIMIT));
+
+        let mut security_opt = Vec::new();
+        if !relaxations.allow_privilege_escalation {
+            security_opt.push("no-new-privileges:true".to_string());
+        }
+        match relaxations.seccomp_profile.as_deref() {
+            Some("unconfined") => security_opt.push("seccomp=unconfined".to_string()),
+            Some(path) => security_opt.push(format!("seccomp={}", read_seccomp_profile(path)?)),
+            None => {
+                if let Some(profile) = &self.seccomp_profile {
+                    security_opt.push(format!("seccomp={}", profile));
+                }
+            }
+        }
+        if let Some(profile) = relaxations
+            .apparmor_profile
+            .as_ref()
+            .or(self.apparmor_profile.as_ref())
+        {
+            security_opt.push(format!("apparmor={}", profile));
+        }
+        spec.security_opt = security_opt;
+
+        Ok(())
+    }
+}
+
+/// Read a seccomp profile, which Docker expects inline as JSON rather than as a path.
+pub fn read_seccomp_profile(
+    path: &str,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let profile = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read seccomp profile {}: {}", path, e))?;
+    serde_json::from_str::<serde_json::Value>(&profile)
+        .map_err(|e| format!("Invalid seccomp profile {}: {}", path, e))?;
+    Ok(profile)
+}
+
+/// Hand the workspace directory to the user the workspace runs as, so it stays writable.
+/// Nothing is changed without a numeric `owner`.
+#[cfg(unix)]
+pub fn chown_workspace_dir(path: &std::path::Path, owner: Option<DataOwner>) {
+    let Some(owner) = owner else {
+        return;
+    };
+    if let Err(e) = std::os::unix::fs::chown(path, Some(owner.uid), owner.gid) {
+        tracing::warn!(
+            "Failed to hand {} to the workspace user: {}",
+            path.display(),
+            e
+        );
+    }
+}
+
+#[cfg(not(unix))]
+pub fn chown_workspace_dir(_path: &std::path::Path, _owner: Option<DataOwner>) {}
+
+/// Hand everything under `path` to `owner`, without following links.
+#[cfg(unix)]
+pub fn chown_workspace_tree(
+    path: &std::path::Path,
+    owner: Option<DataOwner>,
+) -> std::io::Result<()> {
+    let Some(owner) = owner else {
+        return Ok(());
+    };
+    std::os::unix::fs::lchown(path, Some(owner.uid), owner.gid)?;
+    if path.symlink_metadata()?.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            chown_workspace_tree(&entry?.path(), Some(owner))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn chown_workspace_tree(
+    _path: &std::path::Path,
+    _owner: Option<DataOwner>,
+) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Hand an open file or directory to `owner`, whatever its path now points to.
+#[cfg(unix)]
+pub fn chown_workspace_fd(
+    fd: impl std::os::fd::AsFd,
+    owner: Option<DataOwner>,
+) -> std::io::Result<()> {
+    match owner {
+        Some(owner) => std::os::unix::fs::fchown(fd, Some(owner.uid), owner.gid),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_applies_image_relaxations_on_top_of_the_hardened_profile() {
+        let mut config = SecurityConfig::default();
+        config.image_profiles.insert(
+            "relaxed:latest".to_string(),
+            serde_json::from_str(r#"{ "cap_add": ["NET_BIND_SERVICE"], "writable_rootfs": true }"#)
+                .unwrap(),
+        );
+
+        let mut hardened = ContainerSpec {
+            image: "tangle-mcp:0.1.0".to_string(),
+            ..Default::default()
+        };
+        config.apply(&mut hardened).unwrap();
+        assert_eq!(hardened.user.as_deref(), Some(WORKSPACE_USER));
+        assert_eq!(hardened.cap_drop, vec!["ALL".to_string()]);
+        assert!(hardened.cap_add.is_empty());
+        assert!(hardened.readonly_rootfs);
+        assert_eq!(hardened.pids_limit, Some(DEFAULT_PIDS_LIMIT));
+        assert_eq!(
+            hardened.security_opt,
+            vec!["no-new-privileges:true".to_string()]
+        );
+
+        let mut relaxed = ContainerSpec {
+            image: "relaxed:latest".to_string(),
+            ..Default::default()
+        };
+        config.apply(&mut relaxed).unwrap();
+        assert_eq!(relaxed.cap_add, vec!["NET_BIND_SERVICE".to_string()]);
+        assert!(!relaxed.readonly_rootfs);
+    }
+
+    #[test]
+    fn it_hands_data_to_the_user_of_the_image() {
+        let mut config = SecurityConfig::default();
+        for (image, user) in [("node:latest", "1001:1002"), ("named:latest", "node")] {
+            let relaxations = SecurityRelaxations {
+                user: Some(user.to_string()),
+                ..Default::default()
+            };
+            config.image_profiles.insert(image.to_string(), relaxations);
+        }
+
+        let owner = |uid, gid| Some(DataOwner { uid, gid });
+        assert_eq!(
+            config.data_owner("tangle-mcp:0.1.0"),
+            owner(1000, Some(1000))
+        );
+        assert_eq!(config.data_owner("node:latest"), owner(1001, Some(1002)));
+        assert_eq!(config.data_owner("named:latest"), None);
+        assert_eq!(DataOwner::of_user("1001:node"), owner(1001, None));
+
+        // Changing owners takes root
+        if unsafe { libc::geteuid() } == 0 {
+            use std::os::unix::fs::MetadataExt;
+
+            let temp = blueprint_sdk::testing::tempfile::TempDir::new().unwrap();
+            let config_dir = temp.path().join("config");
+            std::fs::create_dir(&config_dir).unwrap();
+            chown_workspace_tree(temp.path(), config.data_owner("node:latest")).unwrap();
+            let metadata = std::fs::metadata(&config_dir).unwrap();
+            assert_eq!((metadata.uid(), metadata.gid()), (1001, 1002));
+
+            chown_workspace_tree(temp.path(), config.data_owner("named:latest")).unwrap();
+            assert_eq!(std::fs::metadata(&config_dir).unwrap().uid(), 1001);
+        }
+    }
+}
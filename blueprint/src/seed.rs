@@ -0,0 +1,339 @@
+//! Initial contents of a workspace's `/blueprint` directory.
+//!
+//! `create_workspace` can seed a fresh workspace from a tarball, gzipped or not, either
+//! inline as base64 for small bundles or by its SHA-256 from the operator's blob store
+//! (`{MCP_BLOB_DIR}/{hash}`). The whole bundle is checked before anything is written: only
+//! regular files, directories and links are accepted, paths may not leave the workspace, links
+//! may only point inside it, nothing may go through a symlink of the bundle itself, and the
+//! unpacked size must fit the tier's storage limit.
+
+use crate::error::WorkspaceError;
+use crate::security::{DataOwner, chown_workspace_tree};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use flate2::read::GzDecoder;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Read};
+use std::path::{Component, Path, PathBuf};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Largest inline bundle, bigger ones go through the blob store.
+pub const MAX_INLINE_SIZE: usize = 256 * 1024;
+
+// Initial contents of a workspace, exactly one of the fields set
+#[derive(Default, Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct WorkspaceSeed {
+    /// Tarball, base64 encoded, of at most 256 KiB.
+    #[serde(default)]
+    pub inline: String,
+    /// SHA-256 of a tarball in the operator's blob store, hex encoded.
+    #[serde(default)]
+    pub hash: String,
+}
+
+// Where the tarball of a seed is read from
+enum Bundle {
+    Inline(Vec<u8>),
+    Blob(PathBuf),
+}
+
+impl Bundle {
+    fn resolve(seed: &WorkspaceSeed, blob_dir: Option<&Path>) -> Result<Self, WorkspaceError> {
+        match (seed.inline.is_empty(), seed.hash.is_empty()) {
+            (false, true) => {
+                let bundle = BASE64
+                    .decode(seed.inline.trim())
+                    .map_err(|e| invalid(format!("Invalid base64: {}", e)))?;
+                if bundle.len() > MAX_INLINE_SIZE {
+                    return Err(invalid(format!(
+                        "Inline bundles are limited to {} bytes, use the blob store",
+                        MAX_INLINE_SIZE
+                    )));
+                }
+                Ok(Bundle::Inline(bundle))
+            }
+            (true, false) => {
+                let hash = seed.hash.to_ascii_lowercase();
+                if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(invalid(format!("Invalid SHA-256 {}", seed.hash)));
+                }
+                let blob_dir = blob_dir
+                    .ok_or_else(|| invalid("This operator has no blob store".to_string()))?;
+                let path = blob_dir.join(&hash);
+                if !path.is_file() {
+                    return Err(invalid(format!("No blob {} in the blob store", hash)));
+                }
+                Ok(Bundle::Blob(path))
+            }
+            _ => Err(invalid(
+                "Set exactly one of `inline` and `hash`".to_string(),
+            )),
+        }
+    }
+
+    // The tar stream, decompressed when the bundle is gzipped
+    fn open(&self) -> Result<Box<dyn Read>, BoxError> {
+        let mut reader: Box<dyn BufRead> = match self {
+            Bundle::Inline(bundle) => Box::new(Cursor::new(bundle.clone())),
+            Bundle::Blob(path) => Box::new(BufReader::new(File::open(path)?)),
+        };
+        let gzipped = reader.fill_buf()?.starts_with(&[0x1f, 0x8b]);
+        if gzipped {
+            Ok(Box::new(GzDecoder::new(reader)))
+        } else {
+            Ok(reader)
+        }
+    }
+}
+
+fn invalid(message: String) -> WorkspaceError {
+    WorkspaceError::InvalidParams(format!("Invalid workspace seed: {}", message))
+}
+
+/// Unpack `seed` into the empty `workspace_dir` if it passes every check, allowing at most
+/// `limit` bytes of file contents, and hand it to `owner`.
+pub async fn unpack_seed(
+    seed: &WorkspaceSeed,
+    blob_dir: Option<&Path>,
+    workspace_dir: &Path,
+    limit: u64,
+    owner: Option<DataOwner>,
+) -> Result<u64, BoxError> {
+    let bundle = Bundle::resolve(seed, blob_dir)?;
+    let workspace_dir = workspace_dir.to_path_buf();
+
+    tokio::task::spawn_blocking(move || -> Result<u64, BoxError> {
+        if let Bundle::Blob(path) = &bundle {
+            verify_blob(path)?;
+        }
+        let size = check_entries(bundle.open()?, limit)?;
+
+        let mut archive = tar::Archive::new(bundle.open()?);
+        archive.set_preserve_permissions(true);
+        archive.set_preserve_ownerships(false);
+        archive.set_unpack_xattrs(false);
+        archive.unpack(&workspace_dir)?;
+        chown_workspace_tree(&workspace_dir, owner)?;
+        Ok(size)
+    })
+    .await?
+}
+
+// The blob store is addressed by content, a blob that doesn't match its name is corrupt
+fn verify_blob(path: &Path) -> Result<(), BoxError> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut File::open(path)?, &mut hasher)?;
+    let hash: String = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    if path.file_name().and_then(|name| name.to_str()) != Some(hash.as_str()) {
+        return Err(invalid(format!("Blob {} doesn't match its hash", path.display())).into());
+    }
+    Ok(())
+}
+
+// A link of the tarball, checked once all of its symlinks are known
+struct Link {
+    path: PathBuf,
+    target: PathBuf,
+    symlink: bool,
+}
+
+// Check every entry of the tarball, returning the size of its files
+fn check_entries(reader: Box<dyn Read>, limit: u64) -> Result<u64, BoxError> {
+    let mut archive = tar::Archive::new(reader);
+    let mut size = 0u64;
+    let mut paths = Vec::new();
+    let mut links = Vec::new();
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let path = entry.path()?.into_owned();
+        let normalized = normalize(&path)
+            .ok_or_else(|| invalid(format!("{} is outside of the workspace", path.display())))?;
+
+        let kind = entry.header().entry_type();
+        if kind.is_file() || kind.is_contiguous() {
+            size += entry.header().size()?;
+            if size > limit {
+                return Err(WorkspaceError::QuotaExceeded(format!(
+                    "the seed holds more than the {} bytes the tier allows",
+                    limit
+                ))
+                .into());
+            }
+        } else if kind.is_symlink() || kind.is_hard_link() {
+            let target = entry
+                .link_name()?
+                .ok_or_else(|| invalid(format!("{} has no link target", path.display())))?;
+            links.push(Link {
+                path: normalized.clone(),
+                target: target.into_owned(),
+                symlink: kind.is_symlink(),
+            });
+        } else if !kind.is_dir() && !kind.is_pax_global_extensions() {
+            return Err(invalid(format!(
+                "{} isn't a file, directory or link",
+                path.display()
+            ))
+            .into());
+        }
+        paths.push(normalized);
+    }
+
+    // Nothing may be unpacked or resolved through a symlink of the seed, as `a -> .` would
+    // take `a/b -> ../x` out of the workspace while looking one directory down
+    let symlinks: HashSet<&Path> = links
+        .iter()
+        .filter(|link| link.symlink)
+        .map(|link| link.path.as_path())
+        .collect();
+    for path in &paths {
+        if path
+            .ancestors()
+            .skip(1)
+            .any(|parent| symlinks.contains(parent))
+        {
+            return Err(invalid(format!("{} is inside of a symlink", path.display())).into());
+        }
+    }
+    for link in &links {
+        // Symlinks resolve from their directory, hard links from the workspace
+        let base = if link.symlink {
+            link.path.parent().unwrap_or(Path::new(""))
+        } else {
+            Path::new("")
+        };
+        if !stays_inside(&link.target, base, &symlinks) {
+            return Err(invalid(format!(
+                "{} links to {} outside of the workspace",
+                link.path.display(),
+                link.target.display()
+            ))
+            .into());
+        }
+    }
+    Ok(size)
+}
+
+// `path` relative to the workspace without `.` components, `None` if it leaves the workspace
+fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => normalized.push(name),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    Some(normalized)
+}
+
+// Whether relative `target`, followed from `base`, stays in the workspace without going
+// through one of the seed's `symlinks`
+fn stays_inside(target: &Path, base: &Path, symlinks: &HashSet<&Path>) -> bool {
+    let mut resolved = base.to_path_buf();
+    for component in target.components() {
+        if symlinks.contains(resolved.as_path()) {
+            return false;
+        }
+        match component {
+            Component::Normal(name) => resolved.push(name),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !resolved.pop() {
+                    return false;
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return false,
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueprint_sdk::testing::tempfile::TempDir;
+
+    fn tarball(build: impl FnOnce(&mut tar::Builder<Vec<u8>>)) -> String {
+        let mut builder = tar::Builder::new(Vec::new());
+        build(&mut builder);
+        BASE64.encode(builder.into_inner().unwrap())
+    }
+
+    fn file(builder: &mut tar::Builder<Vec<u8>>, path: &str, contents: &[u8]) {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_entry_type(tar::EntryType::Regular);
+        builder.append_data(&mut header, path, contents).unwrap();
+    }
+
+    fn link(builder: &mut tar::Builder<Vec<u8>>, path: &str, target: &str) {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(0);
+        header.set_entry_type(tar::EntryType::Symlink);
+        builder.append_link(&mut header, path, target).unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_unpacks_only_safe_bundles() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path();
+        let unpack = |inline: String, limit| {
+            let dir = root.join("workspace");
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            let seed = WorkspaceSeed {
+                inline,
+                ..Default::default()
+            };
+            async move { unpack_seed(&seed, None, &dir, limit, None).await }
+        };
+
+        let safe = tarball(|builder| {
+            file(builder, "config/settings.json", b"{}");
+            link(builder, "config/current", "settings.json");
+        });
+        assert_eq!(unpack(safe.clone(), 1024).await.unwrap(), 2);
+        assert_eq!(
+            std::fs::read_to_string(root.join("workspace/config/current")).unwrap(),
+            "{}"
+        );
+        let error = unpack(safe, 1).await.unwrap_err();
+        assert_eq!(WorkspaceError::from(error).code(), 9);
+
+        let escaping = tarball(|builder| link(builder, "config/etc", "../../etc"));
+        assert_eq!(
+            WorkspaceError::from(unpack(escaping, 1024).await.unwrap_err()).code(),
+            1
+        );
+        let absolute = tarball(|builder| link(builder, "passwd", "/etc/passwd"));
+        assert_eq!(
+            WorkspaceError::from(unpack(absolute, 1024).await.unwrap_err()).code(),
+            1
+        );
+        // Links through a symlink of the seed only look like they stay inside
+        let through_symlink = tarball(|builder| {
+            link(builder, "a", ".");
+            link(builder, "a/b", "../x");
+        });
+        assert_eq!(
+            WorkspaceError::from(unpack(through_symlink, 1024).await.unwrap_err()).code(),
+            1
+        );
+        let target_through_symlink = tarball(|builder| {
+            link(builder, "a", ".");
+            link(builder, "c", "a/../etc");
+        });
+        assert_eq!(
+            WorkspaceError::from(unpack(target_through_symlink, 1024).await.unwrap_err()).code(),
+            1
+        );
+    }
+}
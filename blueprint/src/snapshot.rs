@@ -0,0 +1,267 @@
+//! Named, versioned snapshots of workspace data.
+//!
+//! `snapshot_workspace` packs the workspace directory into
+//! `{data_dir}/snapshots/{service_id}/{name}-{version}.tar.gz`, next to a
+//! `{name}-{version}.json` file describing it. Versions of a name count up from 1.
+//!
+//! The workspace data and its snapshots together may use at most the storage limit of the
+//! workspace's tier. Images committed from the container live in the engine's storage and
+//! aren't counted. Snapshots are deleted along with the workspace.
+
+use crate::archive::{pack, unpack};
+use crate::error::WorkspaceError;
+use crate::storage::{move_entries, move_into_place};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+const MAX_NAME_LEN: usize = 64;
+
+// A snapshot of a workspace's data
+#[derive(Default, Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct SnapshotInfo {
+    pub name: String,
+    pub version: u32,
+    /// Seconds since the Unix epoch.
+    pub created_at: u64,
+    /// Bytes the snapshot's data archive takes.
+    pub size: u64,
+    /// Image committed from the container along with the data, empty without one.
+    pub image: String,
+}
+
+// The snapshots of one workspace
+pub struct SnapshotStore {
+    dir: PathBuf,
+}
+
+impl SnapshotStore {
+    pub fn new(data_dir: &Path, service_id: u64) -> Self {
+        Self {
+            dir: data_dir.join("snapshots").join(service_id.to_string()),
+        }
+    }
+
+    /// All snapshots, by name and then version.
+    pub fn list(&self) -> Result<Vec<SnapshotInfo>, BoxError> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut snapshots = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_none_or(|extension| extension != "json") {
+                continue;
+            }
+            match serde_json::from_slice::<SnapshotInfo>(&std::fs::read(&path)?) {
+                Ok(snapshot) => snapshots.push(snapshot),
+                Err(e) => tracing::warn!("Skipping unreadable snapshot {}: {}", path.display(), e),
+            }
+        }
+        snapshots.sort_by(|a, b| (&a.name, a.version).cmp(&(&b.name, b.version)));
+        Ok(snapshots)
+    }
+
+    /// Snapshot `name` at `version`, its latest version when `version` is `0`.
+    pub fn get(&self, name: &str, version: u32) -> Result<Option<SnapshotInfo>, BoxError> {
+        Ok(self
+            .list()?
+            .into_iter()
+            .filter(|snapshot| {
+                snapshot.name == name && (version == 0 || snapshot.version == version)
+            })
+            .max_by_key(|snapshot| snapshot.version))
+    }
+
+    /// Bytes all snapshots take.
+    pub fn used(&self) -> Result<u64, BoxError> {
+        Ok(self.list()?.iter().map(|snapshot| snapshot.size).sum())
+    }
+
+    /// Version the next snapshot called `name` gets.
+    pub fn next_version(&self, name: &str) -> Result<u32, BoxError> {
+        let latest = self.get(name, 0)?;
+        Ok(latest.map_or(1, |snapshot| snapshot.version + 1))
+    }
+
+    /// Pack `workspace_dir` into version `version` of snapshot `name`, as long as the
+    /// workspace and all its snapshots stay within `limit` bytes. `image` is the image
+    /// committed along with the data, if any.
+    pub async fn take(
+        &self,
+        name: &str,
+        version: u32,
+        workspace_dir: &Path,
+        limit: u64,
+        image: String,
+    ) -> Result<SnapshotInfo, BoxError> {
+        let path = self.archive_path(name, version);
+        let size = pack(workspace_dir, &path).await?;
+
+        let used = self.used()? + dir_size(workspace_dir)? + size;
+        if used > limit {
+            std::fs::remove_file(&path)?;
+            return Err(WorkspaceError::QuotaExceeded(format!(
+                "snapshot {} would bring the workspace to {} bytes, its tier allows {}",
+                name, used, limit
+            ))
+            .into());
+        }
+
+        let snapshot = SnapshotInfo {
+            name: name.to_string(),
+            version,
+            created_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            size,
+            image,
+        };
+        self.save(&snapshot)?;
+        Ok(snapshot)
+    }
+
+    /// Replace `workspace_dir` with the data of `snapshot`. The current data is kept when
+    /// unpacking fails.
+    pub async fn restore(
+        &self,
+        snapshot: &SnapshotInfo,
+        workspace_dir: &Path,
+    ) -> Result<(), BoxError> {
+        let previous = workspace_dir.with_extension("previous");
+        if previous.exists() {
+            tokio::fs::remove_dir_all(&previous).await?;
+        }
+        // Move the data rather than the directory, which may be a volume's mountpoint
+        if workspace_dir.exists() {
+            move_entries(workspace_dir, &previous)?;
+        }
+
+        let path = self.archive_path(&snapshot.name, snapshot.version);
+        if let Err(e) = unpack(&path, workspace_dir).await {
+            if previous.exists() {
+                move_into_place(&previous, workspace_dir)?;
+            }
+            return Err(e);
+        }
+        if previous.exists() {
+            tokio::fs::remove_dir_all(&previous).await?;
+        }
+        Ok(())
+    }
+
+    /// Delete every snapshot, returning the images committed along with them.
+    pub fn remove_all(&self) -> Result<Vec<String>, BoxError> {
+        let images = self
+            .list()?
+            .into_iter()
+            .map(|snapshot| snapshot.image)
+            .filter(|image| !image.is_empty())
+            .collect();
+        match std::fs::remove_dir_all(&self.dir) {
+            Ok(()) => Ok(images),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(images),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn archive_path(&self, name: &str, version: u32) -> PathBuf {
+        self.dir.join(format!("{}-{}.tar.gz", name, version))
+    }
+
+    // Write the description next to the archive, replacing it atomically
+    fn save(&self, snapshot: &SnapshotInfo) -> Result<(), BoxError> {
+        let path = self
+            .dir
+            .join(format!("{}-{}.json", snapshot.name, snapshot.version));
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(snapshot)?)?;
+        std::fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+}
+
+/// Repository the images committed from a workspace are tagged in, as `{name}-{version}`.
+pub fn image_repository(service_id: u64) -> String {
+    format!("mcp-snapshot-{}", service_id)
+}
+
+/// Check that `name` can be used as a snapshot name, a file name and an image tag alike.
+pub fn validate_name(name: &str) -> Result<(), WorkspaceError> {
+    let valid = name.len() <= MAX_NAME_LEN
+        && name.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if valid {
+        Ok(())
+    } else {
+        Err(WorkspaceError::InvalidParams(format!(
+            "Invalid snapshot name {:?}, use up to {} letters, digits, `_` and `-`",
+            name, MAX_NAME_LEN
+        )))
+    }
+}
+
+/// Bytes the files under `dir` take, without following links.
+pub fn dir_size(dir: &Path) -> Result<u64, BoxError> {
+    let mut size = 0;
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let metadata = entry.path().symlink_metadata()?;
+        if metadata.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueprint_sdk::testing::tempfile::TempDir;
+
+    #[tokio::test]
+    async fn it_versions_snapshots_within_the_limit() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path();
+        let workspace = root.join("workspaces/4");
+        std::fs::create_dir_all(&workspace).unwrap();
+        std::fs::write(workspace.join("notes.md"), "first").unwrap();
+
+        let store = SnapshotStore::new(root, 4);
+        let first = store
+            .take("before-rm", 1, &workspace, 1 << 20, String::new())
+            .await
+            .unwrap();
+        std::fs::write(workspace.join("notes.md"), "second").unwrap();
+        assert_eq!(store.next_version("before-rm").unwrap(), 2);
+        store
+            .take("before-rm", 2, &workspace, 1 << 20, String::new())
+            .await
+            .unwrap();
+        assert_eq!(store.list().unwrap().len(), 2);
+
+        let error = store
+            .take("big", 1, &workspace, 10, String::new())
+            .await
+            .unwrap_err();
+        assert_eq!(WorkspaceError::from(error).code(), 9);
+        assert_eq!(store.get("big", 0).unwrap(), None);
+
+        store.restore(&first, &workspace).await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(workspace.join("notes.md")).unwrap(),
+            "first"
+        );
+        assert!(validate_name("../etc").is_err());
+    }
+}
@@ -0,0 +1,156 @@
+//! Records of the workspaces this operator runs.
+//!
+//! Each record is kept as `{data_dir}/state/{service_id}.json`, next to (not inside) the
+//! workspace directory that is mounted into the container, and loaded again on startup.
+
+use crate::egress::EgressPolicy;
+use crate::{McpTransport, ResourceTier};
+use blueprint_sdk::crypto::BytesEncoding;
+use blueprint_sdk::crypto::sp_core::SpSr25519Public;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct WorkspaceRecord {
+    pub service_id: u64,
+    pub workspace_name: String,
+    /// Job call that created the workspace, to recognize redelivered create jobs.
+    #[serde(default)]
+    pub call_id: u64,
+    /// Owner's Sr25519 public key, hex encoded.
+    pub owner_public_key: String,
+    pub tier: ResourceTier,
+    pub transport: McpTransport,
+    /// Command of a stdio-only MCP server, empty for HTTP servers.
+    #[serde(default)]
+    pub stdio_command: Vec<String>,
+    pub url: String,
+    pub ws_url: String,
+    /// SHA-256 fingerprint of the workspace certificate, empty without TLS.
+    #[serde(default)]
+    pub tls_fingerprint: String,
+    pub egress: EgressPolicy,
+    /// OCI runtime the container runs under.
+    #[serde(default)]
+    pub runtime: String,
+    /// Seconds since the Unix epoch.
+    pub created_at: u64,
+    /// Image the workspace runs when restored from a snapshot with a committed container,
+    /// the default workspace image otherwise.
+    #[serde(default)]
+    pub image: Option<String>,
+    /// When the workspace was destroyed with a grace period, `None` while it is live.
+    #[serde(default)]
+    pub deleted_at: Option<u64>,
+}
+
+impl WorkspaceRecord {
+    /// The owner's key, which records keep hex encoded as `SpSr25519Public` displays it.
+    pub fn owner_key(&self) -> Result<SpSr25519Public, BoxError> {
+        let hex = &self.owner_public_key;
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| {
+                hex.get(i..i + 2)
+                    .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+            })
+            .collect::<Option<Vec<u8>>>()
+            .ok_or("Invalid owner key in workspace record")?;
+        Ok(SpSr25519Public::from_bytes(&bytes)?)
+    }
+}
+
+pub struct WorkspaceStore {
+    dir: Option<PathBuf>,
+    records: Mutex<HashMap<u64, WorkspaceRecord>>,
+}
+
+impl WorkspaceStore {
+    /// Load the stored records, keeping them in memory only without a data directory.
+    pub fn open(data_dir: Option<&Path>) -> Result<Self, BoxError> {
+        let mut records = HashMap::new();
+        let dir = data_dir.map(|data_dir| data_dir.join("state"));
+
+        if let Some(dir) = &dir {
+            std::fs::create_dir_all(dir)?;
+            for entry in std::fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.extension().is_none_or(|extension| extension != "json") {
+                    continue;
+                }
+                match serde_json::from_slice::<WorkspaceRecord>(&std::fs::read(&path)?) {
+                    Ok(record) => {
+                        records.insert(record.service_id, record);
+                    }
+                    Err(e) => {
+                        tracing::warn!("Skipping unreadable record {}: {}", path.display(), e)
+                    }
+                }
+            }
+        } else {
+            tracing::warn!(
+                "No data directory configured, workspace records won't survive restarts"
+            );
+        }
+
+        Ok(Self {
+            dir,
+            records: Mutex::new(records),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.records
+            .lock()
+            .map(|records| records.len())
+            .unwrap_or_default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, service_id: u64) -> Option<WorkspaceRecord> {
+        self.records.lock().ok()?.get(&service_id).cloned()
+    }
+
+    pub fn list(&self) -> Vec<WorkspaceRecord> {
+        self.records
+            .lock()
+            .map(|records| records.values().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn insert(&self, record: WorkspaceRecord) -> Result<(), BoxError> {
+        if let Some(dir) = &self.dir {
+            // Write then rename, so a crash never leaves a truncated record behind
+            let path = dir.join(format!("{}.json", record.service_id));
+            let tmp = path.with_extension("json.tmp");
+            std::fs::write(&tmp, serde_json::to_vec_pretty(&record)?)?;
+            std::fs::rename(&tmp, &path)?;
+        }
+        self.records
+            .lock()
+            .map_err(|_| "Workspace store poisoned")?
+            .insert(record.service_id, record);
+        Ok(())
+    }
+
+    pub fn remove(&self, service_id: u64) -> Result<Option<WorkspaceRecord>, BoxError> {
+        if let Some(dir) = &self.dir {
+            match std::fs::remove_file(dir.join(format!("{}.json", service_id))) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(self
+            .records
+            .lock()
+            .map_err(|_| "Workspace store poisoned")?
+            .remove(&service_id))
+    }
+}
@@ -0,0 +1,239 @@
+//! Where the `/blueprint` data of workspaces lives.
+//!
+//! With the default bind driver it is `{data_dir}/workspaces/{service_id}` on the host, bind
+//! mounted into the container, and workspaces keep no data without a data directory. With
+//! the volume driver every workspace gets a named volume (`mcp-workspace-{service_id}`)
+//! created through the container engine, carrying the workspace's ownership labels and,
+//! for drivers that support it, a size matching its tier.
+//!
+//! Either way the operator reaches the data through a host directory, the volume's
+//! mountpoint for volumes, so archives, snapshots, backups and the file API work the same.
+//! Volumes must therefore live on the operator's host, as bind mounts do, and creating a
+//! workspace fails when the engine reports a mountpoint the operator can't reach (e.g. a
+//! remote engine). Mountpoints are filled in place rather than replaced, the engine may
+//! mount the volume's storage on them.
+
+use crate::error::WorkspaceError;
+use crate::{MyContext, ResourceTier, labels};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// How workspace data is stored
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub enum StorageDriver {
+    /// Host directories under the data directory, bind mounted.
+    #[default]
+    Bind,
+    /// A named volume per workspace.
+    Volume(VolumeConfig),
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct VolumeConfig {
+    /// Volume driver, the engine's default (`local`) when unset (`MCP_VOLUME_DRIVER`).
+    pub driver: Option<String>,
+    /// Options passed to the driver (`MCP_VOLUME_OPTS`, comma separated `key=value` pairs).
+    pub options: HashMap<String, String>,
+    /// Driver option the tier's storage limit is passed in, in bytes, for drivers that can
+    /// cap the size of a volume (`MCP_VOLUME_SIZE_OPT`, e.g. `size`).
+    pub size_option: Option<String>,
+}
+
+/// Name of the volume holding the data of a workspace.
+pub fn volume_name(service_id: u64) -> String {
+    format!("mcp-workspace-{}", service_id)
+}
+
+/// Whether workspaces keep their data on this operator.
+pub fn is_persistent(ctx: &MyContext) -> bool {
+    match ctx.config.storage {
+        StorageDriver::Bind => ctx.env.data_dir.is_some(),
+        StorageDriver::Volume(_) => true,
+    }
+}
+
+/// Host directory holding the data of a workspace, `None` when it has none.
+pub async fn workspace_dir(ctx: &MyContext, service_id: u64) -> Result<Option<PathBuf>, BoxError> {
+    match &ctx.config.storage {
+        StorageDriver::Bind => Ok(ctx
+            .env
+            .data_dir
+            .as_ref()
+            .map(|data_dir| data_dir.join("workspaces").join(service_id.to_string()))
+            .filter(|dir| dir.exists())),
+        StorageDriver::Volume(_) => Ok(ctx.backend.volume_path(&volume_name(service_id)).await?),
+    }
+}
+
+/// Set up the storage of a workspace that has no data yet, returning the directory to put
+/// its data in with [`move_into_place`]. It doesn't exist yet with the bind driver, and is
+/// the volume's empty mountpoint with the volume driver.
+pub async fn create_workspace_dir(
+    ctx: &MyContext,
+    service_id: u64,
+    tier: &ResourceTier,
+) -> Result<PathBuf, BoxError> {
+    let StorageDriver::Volume(config) = &ctx.config.storage else {
+        let data_dir = ctx.env.data_dir.as_ref().ok_or_else(|| {
+            WorkspaceError::InvalidParams("This operator doesn't keep workspace data".to_string())
+        })?;
+        let workspaces_dir = data_dir.join("workspaces");
+        tokio::fs::create_dir_all(&workspaces_dir).await?;
+        return Ok(workspaces_dir.join(service_id.to_string()));
+    };
+
+    let mut options = config.options.clone();
+    if let Some(size_option) = &config.size_option {
+        options.insert(size_option.clone(), tier.storage_limit().to_string());
+    }
+    let name = volume_name(service_id);
+    let path = ctx
+        .backend
+        .create_volume(
+            &name,
+            config.driver.as_deref(),
+            &options,
+            &labels::ownership(ctx.blueprint_id(), service_id),
+        )
+        .await?;
+    if !path.is_dir() {
+        ctx.backend.remove_volume(&name).await?;
+        return Err(WorkspaceError::InvalidParams(format!(
+            "Mountpoint {} of volume {} isn't reachable from the operator, the volume driver \
+             needs the engine on the operator's host",
+            path.display(),
+            name
+        ))
+        .into());
+    }
+    // A leftover volume's data must not be mixed with the new workspace's
+    if std::fs::read_dir(&path)?.next().is_some() {
+        return Err(format!("Volume {} already holds data", name).into());
+    }
+    Ok(path)
+}
+
+/// Move the data prepared in `tmp` to `workspace_dir`. An existing `workspace_dir`, like a
+/// volume's mountpoint, must be empty and is filled rather than replaced.
+pub fn move_into_place(tmp: &Path, workspace_dir: &Path) -> std::io::Result<()> {
+    if !workspace_dir.exists() {
+        return std::fs::rename(tmp, workspace_dir);
+    }
+    if std::fs::read_dir(workspace_dir)?.next().is_some() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{} isn't empty", workspace_dir.display()),
+        ));
+    }
+    move_entries(tmp, workspace_dir)?;
+    std::fs::remove_dir(tmp)
+}
+
+/// Move everything in `from` to `to`, creating it, leaving `from` itself in place.
+pub fn move_entries(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        std::fs::rename(entry.path(), to.join(entry.file_name()))?;
+    }
+    Ok(())
+}
+
+/// Delete the data of a workspace, succeeding if it has none.
+pub async fn remove_workspace_dir(ctx: &MyContext, service_id: u64) -> Result<(), BoxError> {
+    match &ctx.config.storage {
+        StorageDriver::Bind => match workspace_dir(ctx, service_id).await? {
+            Some(dir) => Ok(tokio::fs::remove_dir_all(dir).await?),
+            None => Ok(()),
+        },
+        StorageDriver::Volume(_) => Ok(ctx.backend.remove_volume(&volume_name(service_id)).await?),
+    }
+}
+
+/// What `/blueprint` is mounted from, the volume or the directory itself.
+pub fn mount_source(ctx: &MyContext, service_id: u64, dir: &Path) -> String {
+    match &ctx.config.storage {
+        StorageDriver::Bind => dir.display().to_string(),
+        StorageDriver::Volume(_) => volume_name(service_id),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::fake::{FakeBackend, context};
+    use crate::config::OperatorConfig;
+    use crate::{CreateWorkspaceParams, create_workspace, destroy_workspace};
+    use blueprint_sdk::extract::Context;
+    use blueprint_sdk::tangle::extract::{CallId, ServiceId, TangleArg};
+    use blueprint_sdk::testing::tempfile::TempDir;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn it_keeps_data_in_volumes() {
+        let temp = TempDir::new().unwrap();
+        let backend = Arc::new(FakeBackend::with_volumes(temp.path().join("volumes")));
+        let mut ctx = context(backend.clone());
+        ctx.config = Arc::new(OperatorConfig {
+            storage: StorageDriver::Volume(VolumeConfig {
+                size_option: Some("size".to_string()),
+                ..Default::default()
+            }),
+            ..(*ctx.config).clone()
+        });
+
+        let created = create_workspace(
+            Context(ctx.clone()),
+            ServiceId(3),
+            CallId(1),
+            TangleArg(CreateWorkspaceParams {
+                workspace_name: "volume".to_string(),
+                tier: ResourceTier::Small,
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(created.0.error.code, 0);
+        let volume = backend.volume("mcp-workspace-3").unwrap();
+        assert_eq!(volume.options["size"], "5368709120");
+        assert!(volume.labels.contains_key(labels::SERVICE_ID));
+        let dir = workspace_dir(&ctx, 3).await.unwrap().unwrap();
+        assert!(dir.is_dir());
+
+        destroy_workspace(Context(ctx.clone()), ServiceId(3), TangleArg(true))
+            .await
+            .unwrap();
+        assert!(backend.volume("mcp-workspace-3").is_none());
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn it_fills_existing_directories_in_place() {
+        use std::os::unix::fs::MetadataExt;
+
+        let temp = TempDir::new().unwrap();
+        let prepare = |name: &str| {
+            let tmp = temp.path().join(name);
+            std::fs::create_dir_all(tmp.join("config")).unwrap();
+            std::fs::write(tmp.join("config/settings.json"), "{}").unwrap();
+            tmp
+        };
+
+        // Like a volume's mountpoint
+        let mountpoint = temp.path().join("_data");
+        std::fs::create_dir(&mountpoint).unwrap();
+        let inode = std::fs::metadata(&mountpoint).unwrap().ino();
+        move_into_place(&prepare("restore"), &mountpoint).unwrap();
+        assert_eq!(std::fs::metadata(&mountpoint).unwrap().ino(), inode);
+        assert!(mountpoint.join("config/settings.json").is_file());
+        assert!(!temp.path().join("restore").exists());
+        assert!(move_into_place(&prepare("again"), &mountpoint).is_err());
+
+        let missing = temp.path().join("workspace");
+        move_into_place(&prepare("restore"), &missing).unwrap();
+        assert!(missing.join("config/settings.json").is_file());
+    }
+}
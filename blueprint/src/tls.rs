@@ -0,0 +1,265 @@
+//! TLS termination for workspace endpoints.
+//!
+//! Certificates either come from the operator, or are issued per workspace by a CA that is
+//! generated on first use and persisted under `{data_dir}/tls`. The SHA-256 fingerprint of
+//! the served certificate is reported to clients so they can pin it.
+
+use crate::config::TlsMode;
+use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair, KeyUsagePurpose};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use sha2::{Digest, Sha256};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::{JoinHandle, JoinSet};
+use tokio_rustls::TlsAcceptor;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// Pause after a failed accept, which keeps failing while e.g. file descriptors run out
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+// Server certificate of a single workspace
+#[derive(Clone)]
+pub struct WorkspaceTls {
+    pub acceptor: TlsAcceptor,
+    /// Uppercase, colon separated SHA-256 fingerprint of the leaf certificate.
+    pub fingerprint: String,
+}
+
+struct CertificateAuthority {
+    cert: rcgen::Certificate,
+    key: KeyPair,
+}
+
+enum Issuer {
+    Operator(WorkspaceTls),
+    SelfSigned(Box<CertificateAuthority>),
+}
+
+pub struct TlsProvider {
+    issuer: Issuer,
+}
+
+impl TlsProvider {
+    /// Load the operator certificate or the self-signed CA, `None` when TLS is disabled.
+    pub fn new(mode: &TlsMode, data_dir: Option<&Path>) -> Result<Option<Self>, BoxError> {
+        let issuer = match mode {
+            TlsMode::Disabled => return Ok(None),
+            TlsMode::Operator {
+                cert_path,
+                key_path,
+            } => {
+                let chain =
+                    CertificateDer::pem_file_iter(cert_path)?.collect::<Result<Vec<_>, _>>()?;
+                let key = PrivateKeyDer::from_pem_file(key_path)?;
+                let fingerprint = fingerprint(chain.first().ok_or("Empty TLS certificate file")?);
+                Issuer::Operator(WorkspaceTls {
+                    acceptor: acceptor(chain, key)?,
+                    fingerprint,
+                })
+            }
+            TlsMode::SelfSigned => {
+                let (ca_cert, ca_key) = load_or_generate_ca(data_dir)?;
+                Issuer::SelfSigned(Box::new(CertificateAuthority {
+                    cert: ca_cert,
+                    key: ca_key,
+                }))
+            }
+        };
+
+        Ok(Some(Self { issuer }))
+    }
+
+    /// The certificate a workspace reachable at `host` should be served with.
+    pub fn workspace_tls(&self, host: &str) -> Result<WorkspaceTls, BoxError> {
+        match &self.issuer {
+            Issuer::Operator(tls) => Ok(tls.clone()),
+            Issuer::SelfSigned(ca) => {
+                let mut params = CertificateParams::new(vec![host.to_string()])?;
+                params
+                    .distinguished_name
+                    .push(DnType::CommonName, host.to_string());
+                let key = KeyPair::generate()?;
+                let cert = params.signed_by(&key, &ca.cert, &ca.key)?;
+
+                let fingerprint = fingerprint(cert.der());
+                let chain = vec![cert.der().clone(), ca.cert.der().clone()];
+                let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der()));
+                Ok(WorkspaceTls {
+                    acceptor: acceptor(chain, key)?,
+                    fingerprint,
+                })
+            }
+        }
+    }
+}
+
+fn acceptor(
+    chain: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+) -> Result<TlsAcceptor, BoxError> {
+    let config = rustls::ServerConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()?
+    .with_no_client_auth()
+    .with_single_cert(chain, key)?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+fn load_or_generate_ca(data_dir: Option<&Path>) -> Result<(rcgen::Certificate, KeyPair), BoxError> {
+    let Some(data_dir) = data_dir else {
+        tracing::warn!("No data directory configured, the self-signed CA won't survive restarts");
+        return generate_ca();
+    };
+
+    let tls_dir = data_dir.join("tls");
+    let cert_path = tls_dir.join("ca.pem");
+    let key_path = tls_dir.join("ca.key");
+
+    if cert_path.exists() && key_path.exists() {
+        let ca_key = KeyPair::from_pem(&std::fs::read_to_string(&key_path)?)?;
+        let params = CertificateParams::from_ca_cert_pem(&std::fs::read_to_string(&cert_path)?)?;
+        // Re-signing keeps the subject and key, so issued certificates chain to the stored CA
+        let ca_cert = params.self_signed(&ca_key)?;
+        return Ok((ca_cert, ca_key));
+    }
+
+    let (ca_cert, ca_key) = generate_ca()?;
+    std::fs::create_dir_all(&tls_dir)?;
+    std::fs::write(&cert_path, ca_cert.pem())?;
+    write_private(&key_path, ca_key.serialize_pem().as_bytes())?;
+    tracing::info!(
+        "Generated self-signed workspace CA at {}",
+        cert_path.display()
+    );
+
+    Ok((ca_cert, ca_key))
+}
+
+fn generate_ca() -> Result<(rcgen::Certificate, KeyPair), BoxError> {
+    let mut params = CertificateParams::default();
+    params
+        .distinguished_name
+        .push(DnType::CommonName, "Tangle MCP Blueprint Workspace CA");
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+    let key = KeyPair::generate()?;
+    let cert = params.self_signed(&key)?;
+    Ok((cert, key))
+}
+
+#[cfg(unix)]
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(contents)
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    std::fs::write(path, contents)
+}
+
+pub fn fingerprint(der: &[u8]) -> String {
+    Sha256::digest(der)
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+// TLS-terminating TCP proxy in front of a workspace endpoint, stopped when dropped
+pub struct TlsProxy {
+    task: JoinHandle<()>,
+}
+
+impl TlsProxy {
+    pub async fn spawn(
+        acceptor: TlsAcceptor,
+        port: u16,
+        upstream: SocketAddr,
+    ) -> Result<Self, BoxError> {
+        let listener = TcpListener::bind(("0.0.0.0", port)).await?;
+
+        let task = tokio::spawn(async move {
+            let mut connections = JoinSet::new();
+            loop {
+                let (stream, peer) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        tracing::warn!("TLS proxy failed to accept connection: {}", e);
+                        tokio::time::sleep(ACCEPT_BACKOFF).await;
+                        continue;
+                    }
+                };
+
+                let acceptor = acceptor.clone();
+                connections.spawn(async move {
+                    if let Err(e) = proxy_connection(acceptor, stream, upstream).await {
+                        tracing::debug!("TLS proxy connection from {} closed: {}", peer, e);
+                    }
+                });
+
+                // Reap finished connections
+                while connections.try_join_next().is_some() {}
+            }
+        });
+
+        Ok(Self { task })
+    }
+}
+
+impl Drop for TlsProxy {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn proxy_connection(
+    acceptor: TlsAcceptor,
+    stream: TcpStream,
+    upstream: SocketAddr,
+) -> Result<(), BoxError> {
+    let mut client = acceptor.accept(stream).await?;
+    let mut server = TcpStream::connect(upstream).await?;
+    tokio::io::copy_bidirectional(&mut client, &mut server).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueprint_sdk::testing::tempfile::TempDir;
+
+    #[test]
+    fn it_issues_workspace_certificates_from_a_persisted_ca() {
+        let temp = TempDir::new().unwrap();
+        let dir = temp.path();
+        let provider = TlsProvider::new(&TlsMode::SelfSigned, Some(dir))
+            .unwrap()
+            .unwrap();
+        let first = provider.workspace_tls("localhost").unwrap();
+        let second = provider.workspace_tls("localhost").unwrap();
+        assert_eq!(first.fingerprint.len(), 32 * 3 - 1);
+        assert_ne!(first.fingerprint, second.fingerprint);
+
+        // The CA is reused on the next start
+        let ca = std::fs::read(dir.join("tls").join("ca.pem")).unwrap();
+        TlsProvider::new(&TlsMode::SelfSigned, Some(dir))
+            .unwrap()
+            .unwrap();
+        assert_eq!(ca, std::fs::read(dir.join("tls").join("ca.pem")).unwrap());
+    }
+}